@@ -0,0 +1,318 @@
+use std::cmp::Ordering;
+use std::fmt::{Debug, Display};
+use std::rc::Rc;
+use std::str::FromStr;
+
+/*
+    Arbitrary-precision integers, the integers of Karm.
+    Karm integers never overflow: an operation whose result does not fit in 64 bits promotes it to
+    a big integer, and a big result which fits again is demoted back, so that the small values, by
+    far the most common, take the fast path of machine arithmetic. Division truncates toward zero
+    and `%` takes the sign of the dividend, so that `a == (a / b) * b + a % b`. Any backend must
+    keep these semantics, the results of a program do not depend on how it is run.
+*/
+
+// The base of the digits of big integers, a power of ten to print them easily
+const BASE: u64 = 1_000_000_000;
+
+/// An integer of any size
+#[derive(Clone, PartialEq, Eq)]
+pub enum Int {
+    Small(i64),
+    /// Only for the values which do not fit in an i64
+    Big(Rc<BigInt>),
+}
+
+/// Sign and magnitude of an integer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    /// Little-endian digits in base `BASE`, without trailing zeros, empty for zero
+    digits: Vec<u32>,
+}
+
+impl From<i64> for Int {
+    fn from(n: i64) -> Self {
+        Int::Small(n)
+    }
+}
+
+impl Int {
+    pub fn add(&self, other: &Int) -> Int {
+        match (self, other) {
+            (Int::Small(a), Int::Small(b)) if a.checked_add(*b).is_some() => Int::Small(a + b),
+            _ => Int::demote(self.big().add(&other.big())),
+        }
+    }
+
+    pub fn sub(&self, other: &Int) -> Int {
+        match (self, other) {
+            (Int::Small(a), Int::Small(b)) if a.checked_sub(*b).is_some() => Int::Small(a - b),
+            _ => Int::demote(self.big().add(&other.big().neg())),
+        }
+    }
+
+    pub fn mul(&self, other: &Int) -> Int {
+        match (self, other) {
+            (Int::Small(a), Int::Small(b)) if a.checked_mul(*b).is_some() => Int::Small(a * b),
+            _ => Int::demote(self.big().mul(&other.big())),
+        }
+    }
+
+    /// The quotient rounded toward zero, `None` when dividing by zero
+    pub fn div(&self, other: &Int) -> Option<Int> {
+        match (self, other) {
+            (_, Int::Small(0)) => None,
+            (Int::Small(a), Int::Small(b)) if a.checked_div(*b).is_some() => Some(Int::Small(a / b)),
+            _ => Some(Int::demote(self.big().div_rem(&other.big()).0)),
+        }
+    }
+
+    /// The remainder of `div`, with the sign of `self`
+    pub fn rem(&self, other: &Int) -> Option<Int> {
+        match (self, other) {
+            (_, Int::Small(0)) => None,
+            (Int::Small(a), Int::Small(b)) if a.checked_rem(*b).is_some() => Some(Int::Small(a % b)),
+            _ => Some(Int::demote(self.big().div_rem(&other.big()).1)),
+        }
+    }
+
+    pub fn to_i128(&self) -> Option<i128> {
+        match self {
+            Int::Small(n) => Some(*n as i128),
+            Int::Big(big) => big.to_i128(),
+        }
+    }
+
+    fn big(&self) -> BigInt {
+        match self {
+            Int::Small(n) => BigInt::from_i128(*n as i128),
+            Int::Big(big) => (**big).clone(),
+        }
+    }
+
+    fn demote(big: BigInt) -> Int {
+        match big.to_i128().and_then(|n| i64::try_from(n).ok()) {
+            Some(n) => Int::Small(n),
+            None => Int::Big(Rc::new(big)),
+        }
+    }
+}
+
+impl BigInt {
+    fn from_i128(n: i128) -> Self {
+        let (mut magnitude, mut digits) = (n.unsigned_abs(), Vec::new());
+        while magnitude > 0 {
+            digits.push((magnitude % BASE as u128) as u32);
+            magnitude /= BASE as u128;
+        }
+        BigInt {
+            negative: n < 0,
+            digits,
+        }
+    }
+
+    fn to_i128(&self) -> Option<i128> {
+        let magnitude = self
+            .digits
+            .iter()
+            .rev()
+            .try_fold(0i128, |acc, d| acc.checked_mul(BASE as i128)?.checked_add(*d as i128))?;
+        Some(if self.negative { -magnitude } else { magnitude })
+    }
+
+    fn new(negative: bool, mut digits: Vec<u32>) -> Self {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        BigInt {
+            negative: negative && !digits.is_empty(),
+            digits,
+        }
+    }
+
+    fn neg(&self) -> Self {
+        BigInt::new(!self.negative, self.digits.clone())
+    }
+
+    fn add(&self, other: &BigInt) -> Self {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_digits(&self.digits, &other.digits));
+        }
+        match cmp_digits(&self.digits, &other.digits) {
+            Ordering::Less => BigInt::new(other.negative, sub_digits(&other.digits, &self.digits)),
+            _ => BigInt::new(self.negative, sub_digits(&self.digits, &other.digits)),
+        }
+    }
+
+    fn mul(&self, other: &BigInt) -> Self {
+        let mut digits = vec![0u64; self.digits.len() + other.digits.len() + 1];
+        for (i, a) in self.digits.iter().enumerate() {
+            let mut carry = 0;
+            for (j, b) in other.digits.iter().enumerate() {
+                let product = digits[i + j] + *a as u64 * *b as u64 + carry;
+                digits[i + j] = product % BASE;
+                carry = product / BASE;
+            }
+            digits[i + other.digits.len()] += carry;
+        }
+        BigInt::new(self.negative != other.negative, digits.into_iter().map(|d| d as u32).collect())
+    }
+
+    // Schoolbook long division, finding each digit of the quotient by bisection
+    fn div_rem(&self, other: &BigInt) -> (Self, Self) {
+        let mut quotient = vec![0; self.digits.len()];
+        let mut remainder: Vec<u32> = Vec::new();
+        for (i, d) in self.digits.iter().enumerate().rev() {
+            remainder.insert(0, *d);
+            let remainder_trimmed = BigInt::new(false, remainder).digits;
+            let (mut lo, mut hi) = (0, BASE - 1);
+            while lo < hi {
+                let mid = (lo + hi).div_ceil(2);
+                match cmp_digits(&mul_digit(&other.digits, mid), &remainder_trimmed) {
+                    Ordering::Greater => hi = mid - 1,
+                    _ => lo = mid,
+                }
+            }
+            quotient[i] = lo as u32;
+            remainder = sub_digits(&remainder_trimmed, &mul_digit(&other.digits, lo));
+        }
+        (
+            BigInt::new(self.negative != other.negative, quotient),
+            BigInt::new(self.negative, remainder),
+        )
+    }
+}
+
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut digits = Vec::new();
+    let mut carry = 0;
+    for i in 0..a.len().max(b.len()) {
+        let sum = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        digits.push((sum % BASE) as u32);
+        carry = sum / BASE;
+    }
+    digits.push(carry as u32);
+    digits
+}
+
+// a - b, with a >= b
+fn sub_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut digits = Vec::new();
+    let mut borrow = 0;
+    for (i, a) in a.iter().enumerate() {
+        let sub = *b.get(i).unwrap_or(&0) as i64 + borrow;
+        let (digit, next) = if (*a as i64) < sub { (*a as i64 + BASE as i64 - sub, 1) } else { (*a as i64 - sub, 0) };
+        digits.push(digit as u32);
+        borrow = next;
+    }
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    digits
+}
+
+fn mul_digit(a: &[u32], k: u64) -> Vec<u32> {
+    BigInt::new(false, a.to_vec()).mul(&BigInt::new(false, vec![k as u32])).digits
+}
+
+// Compares magnitudes without trailing zeros
+fn cmp_digits(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+impl Ord for Int {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Int::Small(a), Int::Small(b)) => a.cmp(b),
+            _ => {
+                let (a, b) = (self.big(), other.big());
+                match (a.negative, b.negative) {
+                    (false, true) => Ordering::Greater,
+                    (true, false) => Ordering::Less,
+                    (false, false) => cmp_digits(&a.digits, &b.digits),
+                    (true, true) => cmp_digits(&b.digits, &a.digits),
+                }
+            }
+        }
+    }
+}
+
+impl PartialOrd for Int {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromStr for Int {
+    type Err = ();
+
+    /// Parses an optional minus sign followed by decimal digits
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(());
+        }
+        let digits = digits
+            .as_bytes()
+            .rchunks(9)
+            .map(|chunk| std::str::from_utf8(chunk).unwrap().parse().unwrap())
+            .collect();
+        Ok(Int::demote(BigInt::new(negative, digits)))
+    }
+}
+
+impl Display for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let big = match self {
+            Int::Small(n) => return write!(f, "{n}"),
+            Int::Big(big) => big,
+        };
+        if big.negative {
+            write!(f, "-")?;
+        }
+        let mut digits = big.digits.iter().rev();
+        write!(f, "{}", digits.next().unwrap_or(&0))?;
+        for digit in digits {
+            write!(f, "{digit:09}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(s: &str) -> Int {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn arithmetic_beyond_64_bits() {
+        let factorial = (1..=30).fold(Int::from(1), |acc, n| acc.mul(&Int::from(n)));
+        assert_eq!(factorial.to_string(), "265252859812191058636308480000000");
+
+        // Promoted on overflow, demoted once small again
+        let max = Int::from(i64::MAX);
+        assert!(matches!(max.add(&Int::from(1)), Int::Big(_)));
+        assert_eq!(max.add(&Int::from(1)).sub(&Int::from(2)), Int::from(i64::MAX - 1));
+        assert_eq!(Int::from(i64::MIN).div(&Int::from(-1)), Some(int("9223372036854775808")));
+
+        // Truncated division, the remainder has the sign of the dividend
+        let (a, b) = (int("-100000000000000000000000007"), int("1000000000000"));
+        assert_eq!(a.div(&b), Some(int("-100000000000000")));
+        assert_eq!(a.rem(&b), Some(int("-7")));
+        assert_eq!(b.div(&Int::from(0)), None);
+        assert!(a < b && b < factorial);
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt::Display;
+
+use crate::errors::{RuntimeError, SyntaxError, TypeError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+/// A message about the program, rendered along with the source line it points at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The main message, on the first line of the rendering
+    pub message: String,
+    /// (line, col) the diagnostic points at, if it is tied to a location
+    pub pos: Option<(usize, usize)>,
+    /// Other locations explaining the diagnostic, each printed with its source line and message
+    pub labels: Vec<((usize, usize), String)>,
+    /// Additional lines printed under the source snippet
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: String, pos: Option<(usize, usize)>) -> Self {
+        Diagnostic {
+            severity,
+            message,
+            pos,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_labels(mut self, labels: Vec<((usize, usize), String)>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn with_notes(mut self, notes: Vec<String>) -> Self {
+        self.notes = notes;
+        self
+    }
+
+    /// Renders the diagnostic, `source` being the program `pos` refers to.
+    /// ```text
+    /// info: hole `?goal` has type Int
+    ///  --> 1:20
+    ///   |
+    /// 1 | lam f :: n -> n + ?goal;
+    ///   |                   ^
+    ///   = n : Int
+    /// ```
+    /// The labels are printed after the main snippet, each under its own source line.
+    pub fn render(&self, source: &str) -> String {
+        self.render_in(source, &[])
+    }
+
+    /// Renders the diagnostic in `source`, the concatenation of the files of a program and of its
+    /// modules whose names and first lines are `files`. Positions are shown as `math.kr:3:5`.
+    pub fn render_in(&self, source: &str, files: &[(String, usize)]) -> String {
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+
+        // The position of a line within its file, numbered from 1
+        let file_of = |line: usize| match files.iter().rev().find(|(_, first)| *first <= line) {
+            Some((name, first)) => (format!("{name}:"), line - first + 1),
+            None => (String::new(), line),
+        };
+        let line_of = |(line, col): (usize, usize)| {
+            source.lines().nth(line.wrapping_sub(1)).map(|text| (file_of(line), col, text))
+        };
+        let line = self.pos.and_then(line_of);
+
+        // Wide enough for every line number printed
+        let width = std::iter::once(self.pos)
+            .chain(self.labels.iter().map(|(pos, _)| Some(*pos)))
+            .flatten()
+            .filter_map(line_of)
+            .map(|((_, line), _, _)| line.to_string().len())
+            .max();
+        let gutter = " ".repeat(width.unwrap_or(0));
+
+        if let Some(((file, line), col, text)) = line {
+            out.push_str(&format!("{gutter}--> {file}{line}:{col}\n"));
+            out.push_str(&format!("{gutter} |\n"));
+            out.push_str(&format!("{line:>w$} | {text}\n", w = gutter.len()));
+            out.push_str(&format!("{gutter} | {}^\n", " ".repeat(col.saturating_sub(1))));
+        }
+
+        for (pos, label) in &self.labels {
+            if let Some(((_, line), col, text)) = line_of(*pos) {
+                out.push_str(&format!("{gutter} |\n"));
+                out.push_str(&format!("{line:>w$} | {text}\n", w = gutter.len()));
+                out.push_str(&format!("{gutter} | {}^ {label}\n", " ".repeat(col.saturating_sub(1))));
+            }
+        }
+
+        for note in &self.notes {
+            out.push_str(&format!("{gutter} = {note}\n"));
+        }
+        out
+    }
+}
+
+impl From<&SyntaxError> for Diagnostic {
+    fn from(SyntaxError(message, pos): &SyntaxError) -> Self {
+        Diagnostic::new(Severity::Error, format!("syntax error, {message}"), Some(*pos))
+    }
+}
+
+impl From<&TypeError> for Diagnostic {
+    fn from(TypeError(message): &TypeError) -> Self {
+        Diagnostic::new(Severity::Error, format!("type error, {message}"), None)
+    }
+}
+
+// The number of calls of a stack trace shown, the innermost ones
+const SHOWN_CALLS: usize = 10;
+
+impl From<&RuntimeError> for Diagnostic {
+    /// Each call of the stack trace is a label at its position, the innermost first. The same
+    /// call repeated by a recursion is only shown once.
+    fn from(RuntimeError(message, pos, trace): &RuntimeError) -> Self {
+        let message = match trace.first() {
+            Some((function, _)) => format!("runtime error in `{function}`, {message}"),
+            None => format!("runtime error, {message}"),
+        };
+        let mut calls: Vec<((usize, usize), &String, usize)> = Vec::new();
+        for (function, pos) in trace {
+            match (calls.last_mut(), pos) {
+                (Some((last, name, count)), Some(pos)) if last == pos && *name == function => *count += 1,
+                (_, Some(pos)) => calls.push((*pos, function, 1)),
+                (_, None) => {}
+            }
+        }
+        let labels = calls
+            .iter()
+            .take(SHOWN_CALLS)
+            .map(|(pos, function, count)| match count {
+                1 => (*pos, format!("`{function}` called here")),
+                _ => (*pos, format!("`{function}` called here, {count} times")),
+            })
+            .collect();
+        let mut notes = Vec::new();
+        if calls.len() > SHOWN_CALLS {
+            notes.push(format!("... and {} more calls", calls.len() - SHOWN_CALLS));
+        }
+        Diagnostic::new(Severity::Error, message, *pos).with_labels(labels).with_notes(notes)
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let data = match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        };
+        write!(f, "{data}")
+    }
+}
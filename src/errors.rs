@@ -17,3 +17,27 @@ impl Display for SyntaxError {
 
 #[derive(Debug)]
 pub struct TypeError(pub String);
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TypeError -> {}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct RuntimeError(pub String, pub Option<(usize, usize)>, pub Vec<(String, Option<(usize, usize)>)>);
+// RuntimeError(message, (line, col) of the expression being evaluated, when known, stack trace).
+// The trace holds the calls active when the error occurred, innermost first: the called lambda and
+// the (line, col) of the call, which `main` does not have.
+
+impl RuntimeError {
+    pub fn new(message: String, pos: Option<(usize, usize)>) -> Self {
+        RuntimeError(message, pos, Vec::new())
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RuntimeError -> {}", self.0)
+    }
+}
@@ -0,0 +1,777 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::rc::Rc;
+
+use crate::bigint::Int;
+use crate::errors::RuntimeError;
+use crate::parser::{is_type_var, Expr, Literal, Pattern, Program, TypeExpr};
+
+use self::collections::{checked_index, List, Matrix};
+
+mod collections;
+
+/*
+    Tree-walking interpreter, evaluating a typechecked program by calling its `main` lambda.
+    The functions are the toplevel lambdas, which only see their parameters and the other
+    toplevel definitions, and the builtins the typechecker knows about. A function used as a
+    value is a closure over the global environment, so it can be passed around and called later.
+    Functions are curried: called with fewer arguments than it has parameters, a function gives
+    a partial application holding them until the others are given, and the result of a call
+    given more arguments is called with the remaining ones. A call with exactly as many
+    arguments as parameters, the most common, skips this bookkeeping.
+*/
+
+/// The values computed by the interpreter
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value<'a> {
+    Int(Int),
+    Str(Rc<str>),
+    Bool(bool),
+    Record(Rc<BTreeMap<String, Value<'a>>>),
+    Function(Function<'a>),
+    List(Rc<List<'a>>),
+    Array(Rc<[Value<'a>]>),
+    Matrix(Rc<Matrix>),
+    Tuple(Rc<[Value<'a>]>),
+}
+
+/// A function value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Function<'a> {
+    /// A toplevel `Expr::LamDef`
+    Lambda(&'a Expr),
+    /// An operator or a function implemented by the interpreter, such as `+` or `print`
+    Builtin(&'static str),
+    /// `f >> g`, calling `g` with the result of `f`
+    Composed(Rc<(Value<'a>, Value<'a>)>),
+    /// `add(1)`, a function with the first of its arguments, never itself a partial application
+    Partial(Rc<(Function<'a>, Vec<Value<'a>>)>),
+}
+
+// The values of the parameters of the function being evaluated, few enough to be searched linearly
+type Env<'a> = Rc<Vec<(&'a str, Value<'a>)>>;
+
+// The operators and the native functions of the prelude (see `prelude`)
+pub const BUILTINS: [&str; 32] = [
+    "+", "-", "*", "/", "%", "==", "!=", "<=", ">=", "<", ">", "print", "printf", "read_line", "length", "concat",
+    "substring", "int_to_str", "::", ".+", ".-", ".*", "@", "size", "to_array", "split", "negate", "not", "&&", "||",
+    ">>", "<<",
+];
+
+// The number of active calls after which the recursion is considered infinite, which must fit
+// in the stack of the interpreter thread (see `main`). Tail calls are not counted.
+const MAX_DEPTH: usize = 10_000;
+
+/// The structure holding the definitions of the program being interpreted
+pub struct Interpreter<'a> {
+    globals: HashMap<&'a str, &'a Expr>,
+    /// The number of active calls, each one also being a Rust call of `eval`
+    depth: Cell<usize>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(ast: &'a Program) -> Self {
+        let globals = ast
+            .0
+            .iter()
+            .filter_map(|expr| match expr {
+                Expr::LamDef { ident, .. } => Some((ident.as_str(), expr)),
+                _ => None,
+            })
+            .collect();
+        Interpreter {
+            globals,
+            depth: Cell::new(0),
+        }
+    }
+
+    /// Calls the `main` lambda of the program, which takes no parameter, returning its result.
+    pub fn run(&self) -> Result<Value<'a>, RuntimeError> {
+        let Some(main) = self.globals.get("main") else {
+            return Err(RuntimeError::new("The program has no `main` lambda to run.".to_owned(), None));
+        };
+        let Expr::LamDef { params, .. } = main else { unreachable!() };
+        if !params.is_empty() {
+            return Err(RuntimeError::new("`main` must not take any parameter.".to_owned(), None));
+        }
+        let (body, env) = self.enter(main, Vec::new(), None)?;
+        self.eval(body, &env).map_err(|mut err| {
+            err.2.push(("main".to_owned(), None));
+            err
+        })
+    }
+
+    // Evaluates `expr`, looping instead of recursing on the expressions in tail position: the
+    // branches of an if and the body of a called lambda. A tail call replaces the current one
+    // instead of growing the Rust stack, so the recursive loops of Karm run in constant space.
+    // The frame of the lambda called by this evaluation, if any, is added to the stack trace of
+    // the errors going through it, tail calls replacing the frame of their caller.
+    fn eval(&self, expr: &'a Expr, env: &Env<'a>) -> Result<Value<'a>, RuntimeError> {
+        let mut frame = None;
+        let result = self.eval_tail(expr, env, &mut frame);
+        let Some(frame) = frame else {
+            return result;
+        };
+        self.depth.set(self.depth.get() - 1);
+        result.map_err(|mut err| {
+            err.2.push(frame);
+            err
+        })
+    }
+
+    // `frame` is set to the called lambda and the position of its call when one is entered
+    fn eval_tail(&self, mut expr: &'a Expr, env: &Env<'a>, frame: &mut Option<(String, Option<(usize, usize)>)>) -> Result<Value<'a>, RuntimeError> {
+        let mut env = env.clone();
+        loop {
+            match expr {
+                // The right operand is only evaluated when the left one does not decide the result
+                Expr::LamCall { ident, params, pos, .. }
+                    if matches!(ident.as_str(), "&&" | "||") && params.len() == 2 && !self.globals.contains_key(ident.as_str()) =>
+                {
+                    match self.eval(&params[0], &env)? {
+                        Value::Bool(b) if b == (ident == "||") => return Ok(Value::Bool(b)),
+                        Value::Bool(_) => expr = &params[1],
+                        value => return Err(RuntimeError::new(format!("The operand of `{ident}` is {value}, not a Bool."), Some(*pos))),
+                    }
+                }
+                Expr::LamCall { ident, params, pos, .. } => {
+                    let function = match self.lookup(ident, &env, *pos)? {
+                        Value::Function(function) => function,
+                        value => return Err(RuntimeError::new(format!("`{ident}` is {value}, not a function."), Some(*pos))),
+                    };
+                    let args = params.iter().map(|param| self.eval(param, &env)).collect::<Result<_, _>>()?;
+                    match function {
+                        Function::Lambda(def @ Expr::LamDef { params: defined, .. }) if defined.len() == params.len() => {
+                            if frame.is_none() {
+                                if self.depth.get() >= MAX_DEPTH {
+                                    let message = format!("Stack overflow, more than {MAX_DEPTH} calls are active.");
+                                    return Err(RuntimeError::new(message, Some(*pos)));
+                                }
+                                self.depth.set(self.depth.get() + 1);
+                            }
+                            // Named after the lambda, `ident` may be a parameter holding it
+                            let Expr::LamDef { ident, .. } = def else { unreachable!() };
+                            *frame = Some((ident.clone(), Some(*pos)));
+                            (expr, env) = self.enter(def, args, Some(*pos))?;
+                        }
+                        function => return self.call(function, args, Some(*pos)),
+                    }
+                }
+                Expr::If { cond, then, alter, pos } => match self.eval(cond, &env)? {
+                    Value::Bool(true) => expr = then,
+                    Value::Bool(false) => expr = alter,
+                    value => return Err(RuntimeError::new(format!("The condition of an if is {value}, not a Bool."), Some(*pos))),
+                },
+                // The body of the first arm whose pattern matches, with the names it binds
+                Expr::Match { scrutinee, arms, pos } => {
+                    let value = self.eval(scrutinee, &env)?;
+                    let mut bindings = Vec::new();
+                    let Some((_, body)) = arms.iter().find(|(pattern, _)| {
+                        bindings.clear();
+                        matches(pattern, &value, &mut bindings)
+                    }) else {
+                        return Err(RuntimeError::new(format!("No pattern of the match matches {value}."), Some(*pos)));
+                    };
+                    let mut scope = (*env).clone();
+                    scope.extend(bindings);
+                    (expr, env) = (body, Rc::new(scope));
+                }
+                _ => return self.eval_value(expr, &env),
+            }
+        }
+    }
+
+    // Evaluates the expressions which are not in tail position
+    fn eval_value(&self, expr: &'a Expr, env: &Env<'a>) -> Result<Value<'a>, RuntimeError> {
+        match expr {
+            Expr::Literal(Literal::Int(n)) => Ok(Value::Int(n.clone())),
+            // The literal keeps the quotes of its token
+            Expr::Literal(Literal::Str(s)) => Ok(Value::Str(s[1..s.len() - 1].into())),
+            Expr::Var(name, pos) => match self.lookup(name, env, *pos)? {
+                // A lambda without parameters is a constant, evaluated where it is referenced as
+                // it is typed by the type of its body
+                Value::Function(Function::Lambda(def @ Expr::LamDef { params, .. })) if params.is_empty() => {
+                    self.apply(Function::Lambda(def), Vec::new(), Some(*pos))
+                }
+                value => Ok(value),
+            },
+            Expr::Record(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(label, field)| Ok((label.clone(), self.eval(field, env)?)))
+                    .collect::<Result<_, RuntimeError>>()?;
+                Ok(Value::Record(Rc::new(fields)))
+            }
+            Expr::Field { record, field } => match self.eval(record, env)? {
+                Value::Record(fields) if fields.contains_key(field) => Ok(fields[field].clone()),
+                value => Err(RuntimeError::new(format!("{value} has no field `{field}`."), None)),
+            },
+            Expr::Update { record, fields } => {
+                let Value::Record(record) = self.eval(record, env)? else {
+                    return Err(RuntimeError::new("Only records can be updated.".to_owned(), None));
+                };
+                let mut record = (*record).clone();
+                for (label, field) in fields {
+                    record.insert(label.clone(), self.eval(field, env)?);
+                }
+                Ok(Value::Record(Rc::new(record)))
+            }
+            Expr::Cast { expr, target, pos } => {
+                let value = self.eval(expr, env)?;
+                if value.conforms(target) {
+                    Ok(value)
+                } else {
+                    Err(RuntimeError::new(
+                        format!("The value given here is {value}, which is not of type {}.", show_type(target)),
+                        Some(*pos),
+                    ))
+                }
+            }
+            Expr::List(elements) => Ok(Value::List(List::from_values(self.eval_all(elements, env)?))),
+            Expr::Array(elements) => Ok(Value::Array(self.eval_all(elements, env)?.into())),
+            Expr::Tuple(components) => Ok(Value::Tuple(self.eval_all(components, env)?.into())),
+            Expr::Project { tuple, index, pos } => match self.eval(tuple, env)? {
+                Value::Tuple(components) if *index < components.len() => Ok(components[*index].clone()),
+                value => Err(RuntimeError::new(format!("{value} has no component {index}."), Some(*pos))),
+            },
+            Expr::Matrix(rows) => {
+                let rows = rows
+                    .iter()
+                    .map(|row| {
+                        self.eval_all(row, env)?
+                            .into_iter()
+                            .map(|value| match value {
+                                Value::Int(n) => Ok(n),
+                                value => Err(RuntimeError::new(format!("A matrix holds integers, not {value}."), None)),
+                            })
+                            .collect()
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::Matrix(Rc::new(Matrix::from_rows(rows))))
+            }
+            Expr::Index { collection, index, pos } => match (self.eval(collection, env)?, self.eval(index, env)?) {
+                (Value::Array(values), Value::Int(index)) => {
+                    let index = checked_index(&index, values.len(), &format!("an array of length {}", values.len()), Some(*pos))?;
+                    Ok(values[index].clone())
+                }
+                (Value::Matrix(matrix), Value::Int(index)) => matrix.row(&index, Some(*pos)),
+                (value, _) => Err(RuntimeError::new(format!("{value} cannot be indexed."), Some(*pos))),
+            },
+            Expr::Hole { name, pos } => {
+                let hole = name.as_ref().map_or("_".to_owned(), |name| format!("?{name}"));
+                Err(RuntimeError::new(format!("Reached the hole `{hole}`."), Some(*pos)))
+            }
+            Expr::LamCall { .. } | Expr::If { .. } | Expr::Match { .. } => self.eval(expr, env),
+            Expr::LamDef { .. }
+            | Expr::Use { .. }
+            | Expr::Export { .. }
+            | Expr::Fixity { .. }
+            | Expr::TypeDecl { .. }
+            | Expr::Pi { .. }
+            | Expr::Sigma { .. } => {
+                Err(RuntimeError::new("This expression cannot be evaluated.".to_owned(), None))
+            }
+        }
+    }
+
+    // The value of a name: a parameter, a toplevel lambda or a builtin
+    fn lookup(&self, name: &str, env: &Env<'a>, pos: (usize, usize)) -> Result<Value<'a>, RuntimeError> {
+        if let Some((_, value)) = env.iter().find(|(param, _)| *param == name) {
+            return Ok(value.clone());
+        }
+        if let Some(def) = self.globals.get(name) {
+            return Ok(Value::Function(Function::Lambda(def)));
+        }
+        match BUILTINS.iter().find(|builtin| **builtin == name) {
+            Some(builtin) => Ok(Value::Function(Function::Builtin(builtin))),
+            None => Err(RuntimeError::new(format!("`{name}` is not defined."), Some(pos))),
+        }
+    }
+
+    fn eval_all(&self, exprs: &'a [Expr], env: &Env<'a>) -> Result<Vec<Value<'a>>, RuntimeError> {
+        exprs.iter().map(|expr| self.eval(expr, env)).collect()
+    }
+
+    // Calls `function` with any number of arguments: a partial application when they are too
+    // few, the result being called with the remaining ones when they are too many
+    fn call(&self, function: Function<'a>, mut args: Vec<Value<'a>>, pos: Option<(usize, usize)>) -> Result<Value<'a>, RuntimeError> {
+        let function = match function {
+            Function::Partial(partial) => {
+                let (function, given) = &*partial;
+                args = given.iter().cloned().chain(args).collect();
+                function.clone()
+            }
+            function => function,
+        };
+        let arity = match &function {
+            Function::Lambda(Expr::LamDef { params, .. }) => params.len(),
+            Function::Builtin(name) => builtin_arity(name, &args),
+            _ => 1,
+        };
+        if args.len() < arity {
+            return Ok(Value::Function(Function::Partial(Rc::new((function, args)))));
+        }
+        let rest = args.split_off(arity);
+        let result = self.apply(function, args, pos)?;
+        if rest.is_empty() {
+            return Ok(result);
+        }
+        self.call(Self::function(&result, pos)?, rest, pos)
+    }
+
+    // Calls a function with as many arguments as it takes, outside of a tail position
+    fn apply(&self, function: Function<'a>, args: Vec<Value<'a>>, pos: Option<(usize, usize)>) -> Result<Value<'a>, RuntimeError> {
+        match function {
+            Function::Lambda(def) => {
+                let Expr::LamDef { ident, .. } = def else { unreachable!() };
+                if self.depth.get() >= MAX_DEPTH {
+                    let message = format!("Stack overflow, more than {MAX_DEPTH} calls are active.");
+                    return Err(RuntimeError::new(message, pos));
+                }
+                let (body, env) = self.enter(def, args, pos)?;
+                self.depth.set(self.depth.get() + 1);
+                let result = self.eval(body, &env);
+                self.depth.set(self.depth.get() - 1);
+                result.map_err(|mut err| {
+                    err.2.push((ident.clone(), pos));
+                    err
+                })
+            }
+            Function::Builtin(name) => builtin(name, args, pos),
+            Function::Composed(functions) => {
+                let (first, second) = &*functions;
+                let value = self.call(Self::function(first, pos)?, args, pos)?;
+                self.call(Self::function(second, pos)?, vec![value], pos)
+            }
+            function @ Function::Partial(_) => self.call(function, args, pos),
+        }
+    }
+
+    fn function(value: &Value<'a>, pos: Option<(usize, usize)>) -> Result<Function<'a>, RuntimeError> {
+        match value {
+            Value::Function(function) => Ok(function.clone()),
+            value => Err(RuntimeError::new(format!("{value} is not a function."), pos)),
+        }
+    }
+
+    // The body of the lambda `def` and the environment binding its parameters to `args`
+    fn enter(&self, def: &'a Expr, args: Vec<Value<'a>>, pos: Option<(usize, usize)>) -> Result<(&'a Expr, Env<'a>), RuntimeError> {
+        let Expr::LamDef { ident, params, operation, .. } = def else {
+            unreachable!("functions are lambda definitions")
+        };
+        if params.len() != args.len() {
+            return Err(RuntimeError::new(
+                format!("`{ident}` takes {} arguments, but {} were given.", params.len(), args.len()),
+                pos,
+            ));
+        }
+        Ok((operation, Rc::new(params.iter().map(String::as_str).zip(args).collect())))
+    }
+}
+
+// The number of arguments taken by the builtin `name`, that of printf depending on its format
+fn builtin_arity(name: &str, args: &[Value]) -> usize {
+    match name {
+        "print" | "read_line" | "length" | "int_to_str" | "size" | "to_array" | "negate" | "not" => 1,
+        "substring" => 3,
+        "printf" => match args.first() {
+            Some(Value::Str(format)) => 1 + format.matches('{').count(),
+            _ => 1,
+        },
+        _ => 2,
+    }
+}
+
+fn builtin<'a>(name: &str, args: Vec<Value<'a>>, pos: Option<(usize, usize)>) -> Result<Value<'a>, RuntimeError> {
+    let division_by_zero = || RuntimeError::new("Division by zero.".to_owned(), pos);
+    match (name, args.as_slice()) {
+        ("==", [l, r]) => Ok(Value::Bool(l.equals(r, pos)?)),
+        ("!=", [l, r]) => Ok(Value::Bool(!l.equals(r, pos)?)),
+        ("print", [Value::Str(s)]) => {
+            println!("{s}");
+            Ok(Value::Record(Rc::default()))
+        }
+        ("printf", [Value::Str(format), values @ ..]) => {
+            println!("{}", printf(format, values));
+            Ok(Value::Record(Rc::default()))
+        }
+        ("read_line", [_]) => {
+            let mut line = String::new();
+            std::io::stdin()
+                .read_line(&mut line)
+                .map_err(|err| RuntimeError::new(format!("Cannot read a line: {err}."), pos))?;
+            Ok(Value::Str(line.trim_end_matches(['\n', '\r']).into()))
+        }
+        ("length", [Value::Str(s)]) => Ok(Value::Int(Int::from(s.chars().count() as i64))),
+        ("concat", [Value::Str(a), Value::Str(b)]) => Ok(Value::Str(format!("{a}{b}").into())),
+        ("int_to_str", [Value::Int(n)]) => Ok(Value::Str(n.to_string().into())),
+        ("negate", [Value::Int(n)]) => Ok(Value::Int(Int::from(0).sub(n))),
+        (">>", [f, g]) => Ok(Value::Function(Function::Composed(Rc::new((f.clone(), g.clone()))))),
+        ("<<", [f, g]) => Ok(Value::Function(Function::Composed(Rc::new((g.clone(), f.clone()))))),
+        ("not", [Value::Bool(b)]) => Ok(Value::Bool(!b)),
+        ("&&", [Value::Bool(a), Value::Bool(b)]) => Ok(Value::Bool(*a && *b)),
+        ("||", [Value::Bool(a), Value::Bool(b)]) => Ok(Value::Bool(*a || *b)),
+        // The `len` characters from the index `start`
+        ("substring", [Value::Str(s), Value::Int(start), Value::Int(len)]) => {
+            let length = s.chars().count();
+            let range = start.to_i128().zip(len.to_i128()).and_then(|(start, len)| {
+                let end = start.checked_add(len)?;
+                (0 <= start && 0 <= len && end <= length as i128).then_some((start as usize, len as usize))
+            });
+            let Some((start, len)) = range else {
+                let message = format!("The substring of length {len} from {start} is out of a string of length {length}.");
+                return Err(RuntimeError::new(message, pos));
+            };
+            Ok(Value::Str(s.chars().skip(start).take(len).collect::<String>().into()))
+        }
+        ("::", [head, Value::List(tail)]) => Ok(Value::List(Rc::new(List::Cons(head.clone(), tail.clone())))),
+        ("size", [Value::Array(values)]) => Ok(Value::Int(Int::from(values.len() as i64))),
+        ("to_array", [Value::List(list)]) => Ok(Value::Array(list.iter().cloned().collect())),
+        // The parts of `s` around each occurrence of `separator`
+        ("split", [Value::Str(s), Value::Str(separator)]) => {
+            if separator.is_empty() {
+                return Err(RuntimeError::new("Cannot split a string around the empty string.".to_owned(), pos));
+            }
+            let parts = s.split(&**separator).map(|part| Value::Str(part.into())).collect();
+            Ok(Value::List(List::from_values(parts)))
+        }
+        (".+" | ".-" | ".*" | "@", [Value::Matrix(a), Value::Matrix(b)]) => Ok(Value::Matrix(Rc::new(a.apply(name, b, pos)?))),
+        // Integers never overflow, see `bigint`
+        (_, [Value::Int(l), Value::Int(r)]) => match name {
+            "+" => Ok(Value::Int(l.add(r))),
+            "-" => Ok(Value::Int(l.sub(r))),
+            "*" => Ok(Value::Int(l.mul(r))),
+            "/" => l.div(r).map(Value::Int).ok_or_else(division_by_zero),
+            "%" => l.rem(r).map(Value::Int).ok_or_else(division_by_zero),
+            "<=" => Ok(Value::Bool(l <= r)),
+            ">=" => Ok(Value::Bool(l >= r)),
+            "<" => Ok(Value::Bool(l < r)),
+            ">" => Ok(Value::Bool(l > r)),
+            _ => Err(RuntimeError::new(format!("`{name}` cannot be applied to integers."), pos)),
+        },
+        _ => Err(RuntimeError::new(format!("Wrong arguments given to `{name}`."), pos)),
+    }
+}
+
+// Whether `value` matches `pattern`, pushing the names it binds to `bindings`
+fn matches<'a>(pattern: &'a Pattern, value: &Value<'a>, bindings: &mut Vec<(&'a str, Value<'a>)>) -> bool {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Var(name), value) => {
+            bindings.push((name, value.clone()));
+            true
+        }
+        (Pattern::Literal(Literal::Int(n)), Value::Int(value)) => n == value,
+        (Pattern::Literal(Literal::Str(s)), Value::Str(value)) => s[1..s.len() - 1] == **value,
+        (Pattern::List(elements), Value::List(list)) => {
+            let mut values = list.iter();
+            elements.iter().all(|element| values.next().is_some_and(|value| matches(element, value, bindings)))
+                && values.next().is_none()
+        }
+        (Pattern::Tuple(patterns), Value::Tuple(components)) => {
+            patterns.len() == components.len()
+                && patterns.iter().zip(components.iter()).all(|(pattern, value)| matches(pattern, value, bindings))
+        }
+        (Pattern::Cons(head, tail), Value::List(list)) => match &**list {
+            List::Cons(first, rest) => matches(head, first, bindings) && matches(tail, &Value::List(rest.clone()), bindings),
+            List::Nil => false,
+        },
+        _ => false,
+    }
+}
+
+// The format with its specifiers `{i}`, `{b}` and `{s}` replaced by the values, in order
+fn printf(format: &str, values: &[Value]) -> String {
+    let mut out = String::new();
+    let (mut rest, mut values) = (format, values.iter());
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else { break };
+        out.push_str(&rest[..start]);
+        match values.next() {
+            Some(Value::Str(s)) => out.push_str(s),
+            Some(value) => out.push_str(&value.to_string()),
+            None => out.push_str(&rest[start..start + end + 1]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+impl<'a> Value<'a> {
+    // Structural equality, functions cannot be compared
+    fn equals(&self, other: &Value<'a>, pos: Option<(usize, usize)>) -> Result<bool, RuntimeError> {
+        match (self, other) {
+            (Value::Function(_), _) | (_, Value::Function(_)) => {
+                Err(RuntimeError::new("Functions cannot be compared.".to_owned(), pos))
+            }
+            (Value::Record(l), Value::Record(r)) => {
+                if l.len() != r.len() || l.keys().ne(r.keys()) {
+                    return Ok(false);
+                }
+                for (l, r) in l.values().zip(r.values()) {
+                    if !l.equals(r, pos)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            (Value::List(l), Value::List(r)) => Self::all_equal(l.iter(), r.iter(), pos),
+            (Value::Array(l), Value::Array(r)) | (Value::Tuple(l), Value::Tuple(r)) => Self::all_equal(l.iter(), r.iter(), pos),
+            _ => Ok(self == other),
+        }
+    }
+
+    fn all_equal<'v>(
+        mut l: impl Iterator<Item = &'v Value<'a>>,
+        mut r: impl Iterator<Item = &'v Value<'a>>,
+        pos: Option<(usize, usize)>,
+    ) -> Result<bool, RuntimeError>
+    where
+        'a: 'v,
+    {
+        loop {
+            match (l.next(), r.next()) {
+                (Some(l), Some(r)) if l.equals(r, pos)? => continue,
+                (None, None) => return Ok(true),
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    // Whether the value has the type `ty`, as checked by the casts of gradual typing. Only the
+    // outermost constructor of functions can be checked, and type variables hold any value.
+    fn conforms(&self, ty: &TypeExpr) -> bool {
+        match (self, ty) {
+            (_, TypeExpr::Dynamic) => true,
+            (_, TypeExpr::Refined { base, .. }) => self.conforms(base),
+            (Value::Int(_), TypeExpr::Named { name, .. }) => name == "Int",
+            (Value::Str(_), TypeExpr::Named { name, .. }) => name == "Str",
+            (Value::Bool(_), TypeExpr::Named { name, .. }) => name == "Bool",
+            (Value::Matrix(_), TypeExpr::Named { name, .. }) => name == "Matrix",
+            (Value::List(list), TypeExpr::Named { name, args, .. }) if name == "List" => {
+                list.iter().all(|value| value.conforms(&args[0]))
+            }
+            (Value::Array(values), TypeExpr::Named { name, args, .. }) if name == "Array" => {
+                values.iter().all(|value| value.conforms(&args[0]))
+            }
+            (_, TypeExpr::Named { name, .. }) => is_type_var(name),
+            (Value::Record(values), TypeExpr::Record { fields, rest }) => {
+                fields.iter().all(|(label, ty)| values.get(label).is_some_and(|value| value.conforms(ty)))
+                    && (rest.is_some() || values.len() == fields.len())
+            }
+            (Value::Function(_), TypeExpr::Joined(..)) => true,
+            (Value::Tuple(values), TypeExpr::Tuple(components)) => {
+                values.len() == components.len() && values.iter().zip(components).all(|(value, ty)| value.conforms(ty))
+            }
+            _ => false,
+        }
+    }
+}
+
+fn show_type(ty: &TypeExpr) -> String {
+    match ty {
+        TypeExpr::Named { name, args, .. } => {
+            std::iter::once(name.clone()).chain(args.iter().map(show_type)).collect::<Vec<_>>().join(" ")
+        }
+        TypeExpr::Record { fields, rest } => {
+            let fields: Vec<String> = fields.iter().map(|(label, ty)| format!("{label}: {}", show_type(ty))).collect();
+            match rest {
+                Some(rest) => format!("{{ {} | {rest} }}", fields.join(", ")),
+                None => format!("{{ {} }}", fields.join(", ")),
+            }
+        }
+        TypeExpr::Joined(i, o) => format!("({} -> {})", show_type(i), show_type(o)),
+        TypeExpr::Tuple(components) => format!("({})", components.iter().map(show_type).collect::<Vec<_>>().join(", ")),
+        TypeExpr::Dynamic => "?".to_owned(),
+        TypeExpr::Refined { var, base, .. } => format!("{{{var}: {} | ...}}", show_type(base)),
+        TypeExpr::Term(_) => "a term".to_owned(),
+    }
+}
+
+impl Display for Value<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{n}"),
+            Value::Str(s) => write!(f, "{s:?}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Record(fields) if fields.is_empty() => write!(f, "{{}}"),
+            Value::Record(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(label, value)| format!("{label} = {value}")).collect();
+                write!(f, "{{ {} }}", fields.join(", "))
+            }
+            Value::Function(Function::Lambda(Expr::LamDef { ident, .. })) => write!(f, "<lam {ident}>"),
+            Value::Function(Function::Lambda(_)) => write!(f, "<lam>"),
+            Value::Function(Function::Builtin(name)) => write!(f, "<builtin {name}>"),
+            Value::Function(Function::Composed(functions)) => write!(f, "<{} >> {}>", functions.0, functions.1),
+            Value::Function(Function::Partial(partial)) => {
+                write!(f, "{}({}, ..)", Value::Function(partial.0.clone()), join(partial.1.iter()))
+            }
+            Value::List(list) => write!(f, "[{}]", join(list.iter())),
+            Value::Array(values) => write!(f, "[|{}|]", join(values.iter())),
+            Value::Tuple(components) => write!(f, "({})", join(components.iter())),
+            Value::Matrix(matrix) => {
+                let rows: Vec<String> = matrix.rows().map(|row| join(row.iter())).collect();
+                write!(f, "[|{}|]", rows.join("; "))
+            }
+        }
+    }
+}
+
+fn join<T: Display>(values: impl Iterator<Item = T>) -> String {
+    values.map(|value| value.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::typechecker::TypeChecker;
+
+    fn run(source: &str) -> Result<String, RuntimeError> {
+        let ast = Parser::new(&mut Lexer::new(source)).parse().unwrap();
+        Interpreter::new(&ast).run().map(|value| value.to_string())
+    }
+
+    #[test]
+    fn evaluation() {
+        let fib = "lam fib :: n -> if n <= 1 ? n : fib(n - 1) + fib(n - 2);";
+        assert_eq!(run(&format!("{fib} lam main -> fib(20);")).unwrap(), "6765");
+
+        // Functions are values, records are built and updated
+        let program = r#"
+            lam twice :: f, x -> f(f(x));
+            lam succ :: n -> n + 1;
+            lam main -> { { name = "karm", age = twice(succ, 1) } | age = twice(succ, 2) };"#;
+        assert_eq!(run(program).unwrap(), r#"{ age = 4, name = "karm" }"#);
+
+        let factorial = "lam fact :: n -> if n == 0 ? 1 : n * fact(n - 1);";
+        assert_eq!(run(&format!("{factorial} lam main -> fact(25) % 1000000007;")).unwrap(), "440732388");
+
+        let err = run("lam main -> 1 / (2 - 2);").unwrap_err();
+        assert_eq!((err.0.as_str(), err.1), ("Division by zero.", Some((1, 15))));
+    }
+
+    #[test]
+    fn tail_calls_run_in_constant_space() {
+        let program = "
+            lam count :: n, acc -> if n == 0 ? acc : count(n - 1, acc + 1);
+            lam is_even :: n -> if n == 0 ? 1 == 1 : is_odd(n - 1);
+            lam is_odd :: n -> if n == 0 ? 1 == 0 : is_even(n - 1);
+            lam main -> if is_odd(100001) ? count(1000000, 0) : 0;";
+        assert_eq!(run(program).unwrap(), "1000000");
+    }
+
+    #[test]
+    fn errors_carry_a_stack_trace() {
+        let program = "
+            lam f :: n -> 10 / n;
+            lam g :: n -> 1 + f(n);
+            lam h :: n -> g(n);
+            lam main -> h(0) + 1;";
+        let err = run(program).unwrap_err();
+        let calls: Vec<_> = err.2.iter().map(|(function, pos)| (function.as_str(), *pos)).collect();
+        // `h` is replaced by its tail call to `g`
+        assert_eq!(calls, vec![("f", Some((3, 31))), ("g", Some((4, 27))), ("main", None)]);
+    }
+
+    #[test]
+    fn collections() {
+        let program = "
+            lam sum :: xs -> match xs with | [] -> 0 | x :: rest -> x + sum(rest);
+            lam main -> sum(2 :: [3, 4]);";
+        assert_eq!(run(program).unwrap(), "9");
+        assert_eq!(run("lam main -> [|1, 2; 3, 4|] @ [|5, 6; 7, 8|];").unwrap(), "[|19, 22; 43, 50|]");
+        assert_eq!(run("lam main -> [|1, 2; 3, 4|][1];").unwrap(), "[|3, 4|]");
+
+        let err = run("lam main -> [|10, 20, 30|][3];").unwrap_err();
+        assert_eq!(err.0, "The index 3 is out of the bounds of an array of length 3.");
+        assert_eq!(err.1, Some((1, 27)));
+        let err = run("lam main -> [|1, 2, 3; 4, 5, 6|] .+ [|1; 2|];").unwrap_err();
+        assert_eq!(err.0, "Cannot combine the elements of a 2x3 matrix and a 2x1 matrix.");
+    }
+
+    #[test]
+    fn tuples() {
+        let program = "
+            lam div_mod :: a, b -> (a / b, a % b);
+            lam swap :: (a, b) -> (b, a);
+            lam main -> match swap(div_mod(17, 5)) with (r, q) -> (q, r, (r, q).1);";
+        assert_eq!(run(program).unwrap(), "(3, 2, 3)");
+    }
+
+    #[test]
+    fn composition() {
+        let program = "
+            lam double :: n -> n * 2;
+            lam twice :: f, x -> f(f(x));
+            lam main -> twice(double >> negate, 5) |> int_to_str;";
+        assert_eq!(run(program).unwrap(), r#""20""#);
+    }
+
+    #[test]
+    fn partial_application() {
+        let program = "
+            lam add :: a, b -> a + b;
+            lam map :: f, xs -> match xs with | [] -> [] | x :: rest -> f(x) :: map(f, rest);
+            lam adder :: n -> add(n);
+            lam apply :: f, x -> f(x);
+            lam main -> (map(add(1), [1, 2]), adder(2, 3), apply(substring(\"curry\", 1), 3), add(1));";
+        assert_eq!(run(program).unwrap(), r#"([2, 3], 5, "urr", <lam add>(1, ..))"#);
+    }
+
+    #[test]
+    fn constants() {
+        let program = "
+            lam one -> 1;
+            lam two -> one + one;
+            lam main -> (3 - -two * one, two);";
+        assert_eq!(run(program).unwrap(), "(5, 2)");
+    }
+
+    #[test]
+    fn short_circuits() {
+        let program = "
+            lam safe :: n -> n != 0 && 10 / n > 1 || n == 0;
+            lam main -> (safe(0), safe(5), safe(20), 1 == 1 || 1 / 0 == 0);";
+        assert_eq!(run(program).unwrap(), "(true, true, false, true)");
+    }
+
+    #[test]
+    fn casts_blame_their_call() {
+        let source = r#"
+            lam inc :: n: Int -> n + 1;
+            lam pass :: x -> inc(x);
+            lam main -> pass("one");"#;
+        let ast = Parser::new(&mut Lexer::new(source)).parse().unwrap();
+        let mut checker = TypeChecker::new(&ast).gradual();
+        checker.init().unwrap();
+        let program = checker.insert_casts();
+
+        let err = Interpreter::new(&program).run().unwrap_err();
+        assert_eq!(err.0, "The value given here is \"one\", which is not of type Int.");
+        assert_eq!(err.1, Some((3, 30)));
+
+        // An explicit `?` is cast where it meets a static type, whether or not the unannotated
+        // parameters are dynamic: as an argument, an operand, a condition or a returned value
+        let cast = |source: &str| {
+            let ast = Parser::new(&mut Lexer::new(source)).parse().unwrap();
+            let mut checker = TypeChecker::new(&ast);
+            checker.init().unwrap();
+            let program = checker.insert_casts();
+            let result = Interpreter::new(&program).run().map(|value| value.to_string());
+            result.map_err(|err| (err.0, err.1))
+        };
+        let blamed = |value: &str, ty: &str, pos| Err((format!("The value given here is {value}, which is not of type {ty}."), Some(pos)));
+        assert_eq!(
+            cast("lam inc :: n: Int -> n + 1; lam pass :: x: ? -> inc(x); lam main -> pass(\"one\");"),
+            blamed("\"one\"", "Int", (1, 49))
+        );
+        assert_eq!(cast("lam twice :: x: ? -> x * 2; lam main -> twice(\"2\");"), blamed("\"2\"", "Int", (1, 24)));
+        assert_eq!(cast("lam pick :: x: ? -> if x ? 1 : 2; lam main -> pick(3);"), blamed("3", "Bool", (1, 21)));
+        assert_eq!(cast("lam id :: x: ? : Int -> x; lam main -> id(\"a\") + 1;"), blamed("\"a\"", "Int", (1, 5)));
+        assert_eq!(cast("lam id :: x: ? : Int -> x; lam main -> id(2) + 1;"), Ok("3".to_string()));
+    }
+}
@@ -0,0 +1,137 @@
+use std::rc::Rc;
+
+use crate::bigint::Int;
+use crate::errors::RuntimeError;
+
+use super::Value;
+
+/*
+    The runtime representations of the collections.
+    A list is a chain of cells shared by the lists built on it, so that `x :: xs` takes constant
+    time whatever the length of `xs`. Arrays are slices of values, and matrices keep their
+    integers row by row. Every access is checked against their bounds, and the operations on
+    matrices against their dimensions.
+*/
+
+/// An immutable linked list
+#[derive(Clone, Debug, PartialEq)]
+pub enum List<'a> {
+    Nil,
+    Cons(Value<'a>, Rc<List<'a>>),
+}
+
+impl<'a> List<'a> {
+    pub fn from_values(values: Vec<Value<'a>>) -> Rc<List<'a>> {
+        values
+            .into_iter()
+            .rev()
+            .fold(Rc::new(List::Nil), |tail, head| Rc::new(List::Cons(head, tail)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value<'a>> {
+        let mut list = self;
+        std::iter::from_fn(move || match list {
+            List::Nil => None,
+            List::Cons(head, tail) => {
+                list = tail;
+                Some(head)
+            }
+        })
+    }
+}
+
+// Dropping the cells one by one, as the derived drop would recurse once per element
+impl Drop for List<'_> {
+    fn drop(&mut self) {
+        let List::Cons(_, tail) = self else { return };
+        let mut tail = std::mem::replace(tail, Rc::new(List::Nil));
+        while let Ok(mut cell) = Rc::try_unwrap(tail) {
+            match &mut cell {
+                List::Cons(_, next) => tail = std::mem::replace(next, Rc::new(List::Nil)),
+                List::Nil => break,
+            }
+        }
+    }
+}
+
+/// A matrix of integers
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    /// The elements row by row
+    cells: Vec<Int>,
+}
+
+impl Matrix {
+    /// Builds the matrix from rows of the same length
+    pub fn from_rows(rows: Vec<Vec<Int>>) -> Self {
+        Matrix {
+            rows: rows.len(),
+            cols: rows.first().map_or(0, Vec::len),
+            cells: rows.into_iter().flatten().collect(),
+        }
+    }
+
+    /// The row `index` as an array
+    pub fn row<'a>(&self, index: &Int, pos: Option<(usize, usize)>) -> Result<Value<'a>, RuntimeError> {
+        let row = checked_index(index, self.rows, &format!("a matrix with {} rows", self.rows), pos)?;
+        let cells = &self.cells[row * self.cols..(row + 1) * self.cols];
+        Ok(Value::Array(cells.iter().cloned().map(Value::Int).collect()))
+    }
+
+    /// `.+`, `.-` and `.*` apply their operation to the elements at the same place, `@` is the
+    /// product of matrices.
+    pub fn apply(&self, op: &str, other: &Matrix, pos: Option<(usize, usize)>) -> Result<Matrix, RuntimeError> {
+        let mismatch = |what: &str| {
+            let message = format!(
+                "Cannot {what} a {}x{} matrix and a {}x{} matrix.",
+                self.rows, self.cols, other.rows, other.cols
+            );
+            Err(RuntimeError::new(message, pos))
+        };
+        if op == "@" {
+            if self.cols != other.rows {
+                return mismatch("multiply");
+            }
+            let mut cells = Vec::with_capacity(self.rows * other.cols);
+            for i in 0..self.rows {
+                for j in 0..other.cols {
+                    let products = (0..self.cols).map(|k| self.cells[i * self.cols + k].mul(&other.cells[k * other.cols + j]));
+                    cells.push(products.fold(Int::from(0), |sum, product| sum.add(&product)));
+                }
+            }
+            return Ok(Matrix {
+                rows: self.rows,
+                cols: other.cols,
+                cells,
+            });
+        }
+
+        if (self.rows, self.cols) != (other.rows, other.cols) {
+            return mismatch("combine the elements of");
+        }
+        let cells = self.cells.iter().zip(&other.cells).map(|(a, b)| match op {
+            ".+" => a.add(b),
+            ".-" => a.sub(b),
+            _ => a.mul(b),
+        });
+        Ok(Matrix {
+            cells: cells.collect(),
+            ..*self
+        })
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[Int]> {
+        self.cells.chunks(self.cols.max(1))
+    }
+}
+
+/// The position of `index` in a collection of `len` elements, described as `collection` when
+/// it is out of its bounds
+pub fn checked_index(index: &Int, len: usize, collection: &str, pos: Option<(usize, usize)>) -> Result<usize, RuntimeError> {
+    match index.to_i128() {
+        Some(i) if 0 <= i && i < len as i128 => Ok(i as usize),
+        _ => Err(RuntimeError::new(format!("The index {index} is out of the bounds of {collection}."), pos)),
+    }
+}
@@ -1,66 +1,144 @@
-use std::iter::Peekable;
+use std::sync::OnceLock;
 
-use regex::{Captures, Regex};
+use regex::Regex;
 
 use crate::lexer::tokens::Token;
 
+use self::layout::{Layout, PRAGMA};
 use self::tokens::Kind;
 
+pub mod layout;
 pub mod tokens;
 
 // Don't mess up the order or it becomes hell
-const REGEX_SET: [(&str, Option<Kind>); 25] = [
+const REGEX_SET: [(&str, Option<Kind>); 54] = [
     (r"^\d+", Some(Kind::Integer)), // Integers
     (r"^\n", Some(Kind::Newline)),  // Newline
-    (r"^\s+", None),                // Whitespace
+    (r"^[^\S\n]+", None),           // Whitespace
     (r"^\blam\b", Some(Kind::Lam)),
     (r"^\buse\b", Some(Kind::Use)),
+    (r"^\bexport\b", Some(Kind::Export)),
     (r"^\bif\b", Some(Kind::If)),
+    (r"^\bpure\b", Some(Kind::Pure)),
+    (r"^\btype\b", Some(Kind::Type)),
+    (r"^\bmatch\b", Some(Kind::Match)),
+    (r"^\bwith\b", Some(Kind::With)),
+    (r"^\bnot\b", Some(Kind::Not)),
+    (r"^\binfix[lr]?\b", Some(Kind::Fixity)),
     (r"^::", Some(Kind::DoubleColon)),
     (r"^:", Some(Kind::Colon)),
     (r"^;", Some(Kind::SemiColon)),
+    (r"^\[\|", Some(Kind::LArray)),
+    (r"^\|\]", Some(Kind::RArray)),
+    (r"^\|>", Some(Kind::Pipe)),
+    (r"^\|\|", Some(Kind::Or)),
+    (r"^&&", Some(Kind::And)),
     (r"^\|", Some(Kind::Bar)),
+    (r"^\[", Some(Kind::LBracket)),
+    (r"^\]", Some(Kind::RBracket)),
     (r#"^"[^"]*""#, Some(Kind::String)),
     (r"^->", Some(Kind::Arrow)),
+    (r"^\.\+", Some(Kind::DotPlus)),
+    (r"^\.-", Some(Kind::DotMin)),
+    (r"^\.\*", Some(Kind::DotMul)),
+    (r"^@", Some(Kind::At)),
     (r"^\*", Some(Kind::Mul)),
     (r"^/", Some(Kind::Div)),
+    (r"^%", Some(Kind::Mod)),
     (r"^\+", Some(Kind::Plus)),
     (r"^\-", Some(Kind::Min)),
+    (r"^>>", Some(Kind::Compose)),
+    (r"^<<", Some(Kind::ComposeBack)),
     (r"^<=", Some(Kind::Leq)),
     (r"^>=", Some(Kind::Geq)),
     (r"^==", Some(Kind::DoubleEq)),
     (r"^!=", Some(Kind::Neq)),
+    (r"^<", Some(Kind::Lt)),
+    (r"^>", Some(Kind::Gt)),
+    (r"^=", Some(Kind::Eq)),
+    (r"^\.", Some(Kind::Dot)),
+    (r"^\{", Some(Kind::LBrace)),
+    (r"^\}", Some(Kind::RBrace)),
     (r"^,", Some(Kind::Comma)),
     (r"^\?", Some(Kind::QMark)),
     (r"^\(", Some(Kind::LParen)),
     (r"^\)", Some(Kind::RParen)),
+    (OPERATOR, Some(Kind::Operator)),
     (r"^\w+", Some(Kind::Ident)),
+    (r"^.", Some(Kind::Unknown)), // Anything else, reported by the parser
 ];
 
+// A user-defined operator, such as `~` or `<+>`. A run of these symbols is a single operator,
+// unless it is exactly one of the operators above, or one of them followed by a `-` negating what
+// follows: `x*-1` is `x * -1` and `n<-1` is `n < -1`. The `=` of the records and of the
+// definitions is not an operator either, so that `{ x=-1 }` and `{ x=~y }` bind a field.
+const OPERATOR: &str = r"^[!$%&*+\-/<=>@^|~]+";
+
+// Compiling the regexes is expensive, so it is done once for the whole program.
+fn compiled_regex_set() -> &'static Vec<(Regex, Option<Kind>)> {
+    static SET: OnceLock<Vec<(Regex, Option<Kind>)>> = OnceLock::new();
+    SET.get_or_init(|| {
+        REGEX_SET
+            .iter()
+            .map(|(reg, kind)| (Regex::new(reg).unwrap(), *kind))
+            .collect()
+    })
+}
+
+fn operator_regex() -> &'static Regex {
+    static OPERATOR_REGEX: OnceLock<Regex> = OnceLock::new();
+    OPERATOR_REGEX.get_or_init(|| Regex::new(OPERATOR).unwrap())
+}
+
 #[derive(Clone)]
 pub struct Lexer<'a> {
     program: &'a str,
     cursor: usize,
     pub peeked: Option<Token<'a>>,
-    pub coords: (usize, usize)
+    pub coords: (usize, usize),
+    // In layout mode, the virtual tokens delimiting the definitions and the matches (see `layout`)
+    layout: Option<Layout<'a>>,
 }
 
 impl<'a> Lexer<'a> {
+    /// A lexer in layout mode when the first line of `program` is the `#layout` pragma, which is
+    /// skipped.
     pub fn new(program: &'a str) -> Self {
-        Self {
+        let lexer = Self {
             program,
             cursor: 0,
             peeked: None,
-            coords: (1, 1)
+            coords: (1, 1),
+            layout: None,
+        };
+        if program.lines().next().map(str::trim_end) != Some(PRAGMA) {
+            return lexer;
         }
+        Self {
+            cursor: PRAGMA.len(),
+            coords: (1, PRAGMA.len() + 1),
+            ..lexer.layout()
+        }
+    }
+
+    /// Reads the program in layout mode, as if it began with the `#layout` pragma.
+    pub fn layout(mut self) -> Self {
+        self.layout.get_or_insert_with(Layout::default);
+        self
+    }
+
+    /// Counts the lines from `line`, for a program which is part of a larger source (see `modules`).
+    pub fn at_line(mut self, line: usize) -> Self {
+        self.coords = (line, 1);
+        self
     }
 
     pub fn has_more_token(&self) -> bool {
         self.cursor < self.program.len()
     }
 
+    // Consumes `capture` and returns the token it represents, skipping whitespaces and newlines.
     fn match_token(&mut self, tok_kind: Option<Kind>, capture: &'a str) -> Option<Token<'a>> {
-
         let (line, col) = self.coords;
 
         self.cursor += capture.len();
@@ -68,48 +146,74 @@ impl<'a> Lexer<'a> {
         match tok_kind {
             Some(Kind::Newline) => {
                 self.coords = (line + 1, 1);
-                self.next()
+                self.lex_token()
             }
             Some(kind) => {
-                self.coords = (line, col + capture.len() - 1);
+                self.coords = (line, col + capture.len());
                 Some(Token {
                     kind,
                     value: capture,
+                    pos: (line, col),
                 })
-            },
+            }
 
             // Whitespace
-            None => self.next(),
+            None => {
+                self.coords = (line, col + capture.len());
+                self.lex_token()
+            }
         }
-
     }
 
-
-}
-
-impl<'a> Iterator for Lexer<'a> {
-    type Item = Token<'a>;
-
-    fn next(&mut self) -> Option<Self::Item> {
+    // Reads the next token of the program, `None` once the end of the program is reached.
+    fn lex_token(&mut self) -> Option<Token<'a>> {
         if !self.has_more_token() {
             return None;
         }
 
         let current = &self.program[self.cursor..];
 
-        let next = self.peeked.clone();
-
-        for (reg, tok_type) in REGEX_SET {
-
-            let captured_regex = Regex::new(reg).unwrap().captures(current);
-
-            if let Some(caps) = captured_regex {
-                self.peeked = self.match_token(tok_type, caps.get(0).unwrap().as_str())
+        for (reg, tok_type) in compiled_regex_set() {
+            if let Some(caps) = reg.captures(current) {
+                let capture = caps.get(0).unwrap().as_str();
+                return match operator_regex().find(current).map(|run| run.as_str()) {
+                    Some(run)
+                        if run.len() > capture.len()
+                            && *tok_type != Some(Kind::Eq)
+                            && run.strip_suffix('-') != Some(capture) =>
+                    {
+                        self.match_token(Some(Kind::Operator), run)
+                    }
+                    _ => self.match_token(*tok_type, capture),
+                };
             }
+        }
 
+        None
+    }
+
+    // Reads the next token, preceded by the virtual tokens it implies in layout mode.
+    fn read(&mut self) -> Option<Token<'a>> {
+        let Some(layout) = &mut self.layout else {
+            return self.lex_token();
+        };
+        if let Some(token) = layout.pending() {
+            return Some(token);
         }
+        let token = self.lex_token();
+        let Some(layout) = &mut self.layout else { unreachable!() };
+        layout.push(token, self.coords);
+        layout.pending()
+    }
+}
 
-        println!("{:?}", next);
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    // Returns the lookahead token and reads the following one into `peeked`.
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.peeked.take();
+        self.peeked = self.read();
         next
     }
 }
@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+
+use crate::lexer::tokens::{Kind, Token};
+
+/*
+    The layout mode, in which the lines of a file delimit its definitions and the arms of its
+    matches instead of `;`. It is selected by the `#layout` pragma on the first line of the file,
+    or by the `--layout` flag for the file given to the CLI:
+
+        #layout
+        lam sum :: xs -> match xs with
+            | [] -> 0
+            | x :: rest -> x + sum(rest)
+        lam main -> sum([1, 2, 3])
+
+    A line starting on the first column, outside of any bracket, begins a new definition, the
+    lexer emitting a virtual `;` before it. The first token after the `with` of a match gives the
+    column of its block of arms, which a line starting to the left of it closes with a virtual
+    `Kind::BlockEnd`, so that an inner match ends where its lines do. A block is also closed by
+    the bracket it is in, by a comma following it in that bracket or by a `;`, which can still be
+    written to put several definitions on a line.
+*/
+
+pub const PRAGMA: &str = "#layout";
+
+// A block of arms, by the column of its first token and the number of brackets open around it
+#[derive(Clone)]
+struct Block {
+    column: usize,
+    depth: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct Layout<'a> {
+    blocks: Vec<Block>,
+    // The number of brackets open
+    depth: usize,
+    // The last token read from the file, `None` before the first one and once the file is read
+    last: Option<Token<'a>>,
+    // The next token opens a block, following a `with`
+    opening: bool,
+    // The tokens to return before reading the next one, starting with the virtual ones
+    pending: VecDeque<Token<'a>>,
+}
+
+impl<'a> Layout<'a> {
+    pub fn pending(&mut self) -> Option<Token<'a>> {
+        self.pending.pop_front()
+    }
+
+    /// Queues `token`, read from the file, after the virtual tokens it implies. The end of the file,
+    /// at `pos`, is `None` and closes the blocks and the definition left open.
+    pub fn push(&mut self, token: Option<Token<'a>>, pos: (usize, usize)) {
+        let Some(token) = token else {
+            for _ in self.blocks.drain(..) {
+                self.pending.push_back(Self::virtual_token(Kind::BlockEnd, pos));
+            }
+            if self.last.take().is_some_and(|last| last.kind != Kind::SemiColon) {
+                self.pending.push_back(Self::virtual_token(Kind::SemiColon, pos));
+            }
+            return;
+        };
+
+        let closing = matches!(token.kind, Kind::RParen | Kind::RBracket | Kind::RBrace | Kind::RArray);
+        if closing || token.kind == Kind::Comma {
+            self.blocks.retain(|block| block.depth < self.depth);
+        }
+
+        if let Some(last) = self.last.as_ref().filter(|last| last.pos.0 < token.pos.0) {
+            let column = token.pos.1;
+            while self.blocks.last().is_some_and(|block| block.column > column && block.depth == self.depth) {
+                self.blocks.pop();
+                self.pending.push_back(Self::virtual_token(Kind::BlockEnd, token.pos));
+            }
+            if column == 1 && self.depth == 0 && last.kind != Kind::SemiColon {
+                self.blocks.clear();
+                self.pending.push_back(Self::virtual_token(Kind::SemiColon, token.pos));
+            }
+        }
+
+        if self.opening {
+            self.blocks.push(Block {
+                column: token.pos.1,
+                depth: self.depth,
+            });
+        }
+        self.opening = token.kind == Kind::With;
+        match token.kind {
+            _ if closing => self.depth = self.depth.saturating_sub(1),
+            Kind::LParen | Kind::LBracket | Kind::LBrace | Kind::LArray => self.depth += 1,
+            Kind::SemiColon if self.depth == 0 => self.blocks.clear(),
+            _ => {}
+        }
+
+        self.last = Some(token.clone());
+        self.pending.push_back(token);
+    }
+
+    fn virtual_token(kind: Kind, pos: (usize, usize)) -> Token<'a> {
+        Token { kind, value: "", pos }
+    }
+}
@@ -8,6 +8,7 @@ pub enum Kind {
     SemiColon,
     Mul,
     Div,
+    Mod,
     Arrow,
     Plus,
     Min,
@@ -25,24 +26,50 @@ pub enum Kind {
     Geq,
     DoubleEq,
     Neq,
+    Lt,
+    Gt,
     Use,
+    Export,
+    Eq,
+    Dot,
+    LBrace,
+    RBrace,
+    Pure,
+    Type,
+    Match,
+    With,
+    LBracket,
+    RBracket,
+    LArray,
+    RArray,
+    DotPlus,
+    DotMin,
+    DotMul,
+    At,
+    Not,
+    And,
+    Or,
+    // |>
+    Pipe,
+    // >>
+    Compose,
+    // <<
+    ComposeBack,
+    // `infixl`, `infixr` or `infix`
+    Fixity,
+    // A user-defined operator, see `lexer::OPERATOR`
+    Operator,
+    // The virtual end of the arms of a match in layout mode, see `lexer::layout`
+    BlockEnd,
+    Unknown,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token<'a> {
     pub kind: Kind,
     pub value: &'a str,
-}
-
-impl<'a> Token<'a> {
-    pub fn get_prec(&self) -> i32 {
-        match self.kind {
-            Kind::Mul | Kind::Div => 3,
-            Kind::Plus | Kind::Min => 2,
-            Kind::DoubleEq | Kind::Geq | Kind::Neq | Kind::Leq => 1,
-            _ => 0,
-        }
-    }
+    // (line, col) of the first character of the token
+    pub pos: (usize, usize),
 }
 
 impl Debug for Kind {
@@ -52,6 +79,7 @@ impl Debug for Kind {
             Kind::Colon => ":",
             Kind::Comma => ",",
             Kind::Div => "/",
+            Kind::Mod => "%",
             Kind::DoubleColon => "::",
             Kind::DoubleEq => "==",
             Kind::Lam => "lam",
@@ -61,6 +89,8 @@ impl Debug for Kind {
             Kind::Integer => "INT",
             Kind::LParen => "(",
             Kind::Leq => "<=",
+            Kind::Lt => "<",
+            Kind::Gt => ">",
             Kind::Min => "-",
             Kind::Mul => "*",
             Kind::Neq => "!=",
@@ -70,7 +100,34 @@ impl Debug for Kind {
             Kind::SemiColon => ";",
             Kind::String => "STR",
             Kind::Use => "USE",
+            Kind::Export => "export",
             Kind::Bar => "|",
+            Kind::Eq => "=",
+            Kind::Dot => ".",
+            Kind::LBrace => "{",
+            Kind::RBrace => "}",
+            Kind::Pure => "pure",
+            Kind::Type => "type",
+            Kind::Match => "match",
+            Kind::With => "with",
+            Kind::LBracket => "[",
+            Kind::RBracket => "]",
+            Kind::LArray => "[|",
+            Kind::RArray => "|]",
+            Kind::DotPlus => ".+",
+            Kind::DotMin => ".-",
+            Kind::DotMul => ".*",
+            Kind::At => "@",
+            Kind::Not => "not",
+            Kind::And => "&&",
+            Kind::Or => "||",
+            Kind::Pipe => "|>",
+            Kind::Compose => ">>",
+            Kind::ComposeBack => "<<",
+            Kind::Fixity => "infix",
+            Kind::Operator => "OPERATOR",
+            Kind::BlockEnd => "end of the arms",
+            Kind::Unknown => "UNKNOWN",
             _ => "",
         };
         write!(f, "{}", data)
@@ -1,15 +1,29 @@
+mod bigint;
+mod diagnostics;
 mod errors;
+mod interpreter;
 mod lexer;
+mod modules;
 mod parser;
-// mod repl;
-// mod typechecker;
+mod prelude;
+mod repl;
+mod resolver;
+mod typechecker;
 
 use clap::{Parser, Subcommand};
+use diagnostics::{Diagnostic, Severity};
+use interpreter::Interpreter;
 use lexer::Lexer;
+use modules::{Loader, Source};
 use parser::Parser as KarmParser;
+use resolver::Resolver;
 use core::panic;
-use std::{fs, process::exit};
-// use typechecker::TypeChecker;
+use std::{fs, path::{Path, PathBuf}, process::exit};
+use typechecker::dependent::DependentChecker;
+use typechecker::refinements::RefinementChecker;
+use typechecker::TypeChecker;
+
+const INTERPRETER_STACK_SIZE: usize = 256 << 20;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -20,6 +34,24 @@ struct Cli {
     /// Print the result AST
     #[arg(short, long)]
     ast: bool,
+
+    /// Check the program with the experimental dependently typed core
+    #[arg(long)]
+    dependent: bool,
+
+    /// Give the dynamic type `?` to unannotated parameters, checking them at runtime
+    #[arg(long)]
+    gradual: bool,
+
+    /// Delimit the definitions and the arms of the matches of the file by its lines, as with the
+    /// `#layout` pragma
+    #[arg(long)]
+    layout: bool,
+
+    /// Look up the modules used by the program in this directory too, after the directory of
+    /// the file using them
+    #[arg(short = 'I', long = "include", value_name = "DIR")]
+    search_path: Vec<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -27,6 +59,9 @@ enum Commands {
     /// Build the specified file (see karm build --help)
     Build { file: String },
 
+    /// Check the specified file, then run its `main` lambda
+    Run { file: String },
+
     /// Interpret the input using a shell
     Shell {},
 }
@@ -35,13 +70,27 @@ fn main() {
     let cli = Cli::parse();
 
     match &cli.command {
-        Some(Commands::Build { file }) => build(file, &cli),
-        Some(Commands::Shell {}) => /* _shell() */ panic!("Shell not implemented!"),
+        Some(Commands::Build { file }) => {
+            build(file, &cli);
+        }
+        // Every active Karm call is a Rust call of the interpreter, the stack must hold as many as
+        // the interpreter allows before reporting a stack overflow, even in a debug build
+        Some(Commands::Run { file }) => std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .stack_size(INTERPRETER_STACK_SIZE)
+                .spawn_scoped(scope, || run(file, &cli))
+                .unwrap()
+                .join()
+                .unwrap()
+        }),
+        Some(Commands::Shell {}) => shell(),
         None => {}
     }
 }
 
-fn build(path: &String, cli: &Cli) {
+/// Parses and checks the file at `path` along with the modules it uses, exiting on the first
+/// error. Returns their source and the program to run, with its casts.
+fn build(path: &String, cli: &Cli) -> (Source, parser::Program) {
 
     if !path.ends_with(".kr") {
         println!("This is not a valid Karm file! (.kr)");
@@ -54,26 +103,115 @@ fn build(path: &String, cli: &Cli) {
     };
 
     let mut lexer = Lexer::new(&program);
+    if cli.layout {
+        lexer = lexer.layout();
+    }
+
+    let mut parser = KarmParser::new(&mut lexer);
+    if cli.dependent {
+        parser = parser.dependent();
+    }
 
-    let ast = match KarmParser::new(&mut lexer).program() {
+    let ast = match parser.program() {
         Ok(ast) => ast,
         Err(err) => {
-            println!("{err}");
+            println!("{}", Diagnostic::from(&err).render(&program));
             exit(1)
         }
     };
 
-    if cli.ast {
+    // In gradual mode, the AST is printed once the casts are inserted
+    if cli.ast && !cli.gradual {
         println!("{:#?}", ast);
     }
 
-    // TODO: As we are here passing `ast` so its value is moved and not borrowed (but we don't want that...)
-    // println!("{:?}", TypeChecker::new(ast).init());
+    if cli.dependent {
+        check_dependent(&ast, &program);
+        return (Source { files: vec![(path.clone(), 1)], text: program }, ast);
+    }
+
+    let own = ast.0.len();
+    let mut loader = Loader::new(cli.search_path.clone());
+    let ast = match loader.load(Path::new(path), &program, ast) {
+        Ok(ast) => ast,
+        Err(err) => {
+            println!("{}", err.render_in(&loader.source.text, &loader.source.files));
+            exit(1)
+        }
+    };
+    let Source { text: program, files } = loader.source;
+
+    let diagnostics = Resolver::new(&ast, own).check();
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic.render_in(&program, &files));
+    }
+    if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        exit(1);
+    }
+
+    let mut checker = TypeChecker::new(&ast);
+    if cli.gradual {
+        checker = checker.gradual();
+    }
+    let gamma = match checker.init() {
+        Ok(gamma) => gamma,
+        Err(err) => {
+            println!("{}", checker.explain(&err).render_in(&program, &files));
+            exit(1);
+        }
+    };
+
+    let errors = RefinementChecker::new(&ast, &gamma).check();
+    for error in &errors {
+        println!("{}", error.render_in(&program, &files));
+    }
+    if !errors.is_empty() {
+        exit(1);
+    }
+
+    let checked = checker.insert_casts();
+    if cli.ast && cli.gradual {
+        println!("{:#?}", checked);
+    }
+
+    for report in checker.hole_reports() {
+        println!("{}", report.render_in(&program, &files));
+    }
+    (Source { text: program, files }, checked)
+}
+
+fn run(path: &String, cli: &Cli) {
+    if cli.dependent {
+        println!("The dependent core cannot be run, only checked.");
+        exit(1);
+    }
+
+    let (source, ast) = build(path, cli);
+    let result = Interpreter::new(&ast).run();
+    match result {
+        // The result of a `main` only performing effects is not worth printing
+        Ok(interpreter::Value::Record(fields)) if fields.is_empty() => {}
+        Ok(value) => println!("{value}"),
+        Err(err) => {
+            println!("{}", Diagnostic::from(&err).render_in(&source.text, &source.files));
+            exit(1);
+        }
+    }
+}
+
+fn check_dependent(ast: &parser::Program, program: &str) {
+    let mut checker = DependentChecker::new(ast);
+    if let Err(err) = checker.init() {
+        println!("{}", Diagnostic::from(&err).render(program));
+        exit(1);
+    }
+
+    for report in checker.hole_reports() {
+        println!("{}", report.render(program));
+    }
 }
 
-/* 
-fn _shell() {
+fn shell() {
     let session = repl::Repl::new(">>> ".to_string(), "... ".to_string(), Vec::new());
     session.run();
 }
-*/
\ No newline at end of file
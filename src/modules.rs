@@ -0,0 +1,492 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::interpreter::BUILTINS;
+use crate::lexer::Lexer;
+use crate::parser::fixity::Fixity;
+use crate::parser::{is_type_var, Expr, LamStyle, Parser, Program, TypeExpr};
+use crate::prelude;
+use crate::typechecker::BUILTIN_TYPES;
+
+/*
+    The module system.
+    `use "math";` loads the file math.kr, looked up in the directory of the file using it, then in
+    each directory of the search path given with `-I`. The modules of the standard library, such
+    as the prelude, are found in `prelude` instead.
+    A module is a namespace named after its file: the definitions of math.kr are used as
+    `math.square(2)` or `math.pi`, and only the ones listed by its `export` declarations when it
+    has some. Operators are not namespaced, they are visible to every module, but a module using
+    an operator must declare the fixity the module defining it declares, if any, since the
+    modules are parsed before the ones they use.
+    The loader flattens the program and the modules it uses, transitively, into a single program
+    where the definitions of math.kr are renamed `math.square`, so that the typechecker and the
+    interpreter never see modules. Each module is parsed once however many modules use it, and a
+    module which ends up using itself is reported as an import cycle.
+*/
+
+/// The text of a program followed by the text of the modules it uses, each starting on its own
+/// line so that the positions in the flattened program point into it.
+pub struct Source {
+    pub text: String,
+    /// The name of each file and its first line in `text`
+    pub files: Vec<(String, usize)>,
+}
+
+// A parsed module, its definitions not renamed yet
+struct Module {
+    // Empty for the program itself, whose definitions are not renamed
+    namespace: String,
+    program: Program,
+    // The names of its definitions which are namespaced
+    defined: HashSet<String>,
+    // The names it exports, `None` when it has no export declaration and exports everything
+    exported: Option<HashSet<String>>,
+    // The fixities it declares, by operator
+    fixities: HashMap<String, Fixity>,
+    // The modules it uses, by namespace
+    imports: HashMap<String, PathBuf>,
+}
+
+pub struct Loader {
+    search_path: Vec<PathBuf>,
+    /// Every module parsed so far, by canonical path
+    modules: HashMap<PathBuf, Module>,
+    /// The modules in the order they were parsed
+    order: Vec<PathBuf>,
+    /// The modules being loaded, each using the next one, with the name they are shown with
+    loading: Vec<(PathBuf, String)>,
+    pub source: Source,
+}
+
+impl Loader {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Loader {
+            search_path,
+            modules: HashMap::new(),
+            order: Vec::new(),
+            loading: Vec::new(),
+            source: Source {
+                text: String::new(),
+                files: Vec::new(),
+            },
+        }
+    }
+
+    /// Adds the modules used by `program`, parsed from the file `path` whose content is `text`.
+    /// The definitions of the program come first, keeping the indices of its expressions.
+    pub fn load(&mut self, path: &Path, text: &str, program: Program) -> Result<Program, Diagnostic> {
+        let name = path.display().to_string();
+        self.add_text(&name, text);
+        let main = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.add(main.clone(), name, String::new(), program)?;
+
+        let mut flattened = Vec::new();
+        // The module defining each operator, which is not namespaced
+        let mut operators: HashMap<&str, &str> = HashMap::new();
+        for path in std::iter::once(&main).chain(self.order.iter().filter(|path| **path != main)) {
+            let module = &self.modules[path];
+            let scope = Scope { loader: self, module };
+            for expr in &module.program.0 {
+                match expr {
+                    Expr::LamDef { ident, pos, .. } if !is_name(ident) => {
+                        let defining = operators.insert(ident, &module.namespace);
+                        if let Some(other) = defining.filter(|other| **other != module.namespace) {
+                            let (other, this) = (describe(other), describe(&module.namespace));
+                            return Err(error(format!("`{ident}` is defined by {other} and by {this}."), *pos));
+                        }
+                    }
+                    _ => {}
+                }
+                let mut expr = expr.clone();
+                scope.expr(&mut expr, &mut Vec::new())?;
+                flattened.push(expr);
+            }
+        }
+        Ok(Program(flattened))
+    }
+
+    // Appends the text of a file to the source, returning its first line
+    fn add_text(&mut self, name: &str, text: &str) -> usize {
+        let first_line = self.source.text.lines().count() + 1;
+        self.source.text.push_str(text);
+        if !text.ends_with('\n') {
+            self.source.text.push('\n');
+        }
+        self.source.files.push((name.to_string(), first_line));
+        first_line
+    }
+
+    // Loads the modules used by `program` before adding it
+    fn add(&mut self, path: PathBuf, name: String, namespace: String, program: Program) -> Result<(), Diagnostic> {
+        self.loading.push((path.clone(), name));
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut imports = HashMap::new();
+        for expr in &program.0 {
+            let Expr::Use { path: used, pos } = expr else {
+                continue;
+            };
+            if prelude::is_std(used) {
+                imports.insert(used.clone(), self.add_std(used)?);
+                continue;
+            }
+            let Some((found, name)) = self.find(used, &dir) else {
+                return Err(error(format!("Cannot find the module \"{used}\"."), *pos));
+            };
+            if let Some(start) = self.loading.iter().position(|(path, _)| *path == found) {
+                let cycle: Vec<&str> = self.loading[start..].iter().map(|(_, name)| name.as_str()).collect();
+                return Err(error(format!("Import cycle: {} -> {name}.", cycle.join(" -> ")), *pos));
+            }
+
+            let module_namespace = found.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let clash = self.modules.iter().find(|(path, module)| module.namespace == module_namespace && **path != found);
+            if let Some((other, _)) = clash {
+                return Err(error(
+                    format!("The modules {} and {} are both named `{module_namespace}`.", other.display(), found.display()),
+                    *pos,
+                ));
+            }
+
+            if !self.modules.contains_key(&found) {
+                let text = fs::read_to_string(&found)
+                    .map_err(|err| error(format!("Cannot read the module \"{used}\": {err}."), *pos))?;
+                let first_line = self.add_text(&name, &text);
+                let module = Parser::new(&mut Lexer::new(&text).at_line(first_line))
+                    .parse()
+                    .map_err(|err| Diagnostic::from(&err))?;
+                self.add(found.clone(), name, module_namespace.clone(), module)?;
+            }
+            imports.insert(module_namespace, found);
+        }
+        if namespace != prelude::PRELUDE {
+            imports.insert(prelude::PRELUDE.to_string(), self.add_std(prelude::PRELUDE)?);
+        }
+        self.loading.pop();
+
+        let defined: HashSet<String> = program
+            .0
+            .iter()
+            .filter_map(|expr| match expr {
+                Expr::LamDef { ident, .. } | Expr::TypeDecl { ident, .. } if is_name(ident) => Some(ident.clone()),
+                _ => None,
+            })
+            .collect();
+        let mut exported: Option<HashSet<String>> = None;
+        let mut fixities = HashMap::new();
+        for expr in &program.0 {
+            match expr {
+                Expr::Export { names, pos } => {
+                    if let Some(name) = names.iter().find(|name| !defined.contains(*name)) {
+                        return Err(error(format!("`{name}` is exported but the module does not define it."), *pos));
+                    }
+                    exported.get_or_insert_with(HashSet::new).extend(names.iter().cloned());
+                }
+                Expr::Fixity { ops, fixity, .. } => fixities.extend(ops.iter().map(|op| (op.clone(), *fixity))),
+                _ => {}
+            }
+        }
+
+        self.modules.insert(
+            path.clone(),
+            Module {
+                namespace,
+                program,
+                defined,
+                exported,
+                fixities,
+                imports,
+            },
+        );
+        self.order.push(path);
+        Ok(())
+    }
+
+    // Adds the module `name` of the standard library unless it is already, returning its path
+    fn add_std(&mut self, name: &str) -> Result<PathBuf, Diagnostic> {
+        let path = PathBuf::from(format!("std/{name}.kr"));
+        if !self.modules.contains_key(&path) {
+            let (shown, text) = (path.display().to_string(), prelude::source(name).unwrap_or_default());
+            let first_line = self.add_text(&shown, text);
+            let module = Parser::new(&mut Lexer::new(text).at_line(first_line))
+                .parse()
+                .map_err(|err| Diagnostic::from(&err))?;
+            self.add(path.clone(), shown, name.to_string(), module)?;
+        }
+        Ok(path)
+    }
+
+    // The canonical path of the module `used` and the name it is shown with, looked up first in
+    // `dir`, then in the search path
+    fn find(&self, used: &str, dir: &Path) -> Option<(PathBuf, String)> {
+        let file = match Path::new(used).extension() {
+            Some(_) => PathBuf::from(used),
+            None => PathBuf::from(format!("{used}.kr")),
+        };
+        std::iter::once(dir)
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(&file))
+            .find_map(|candidate| Some((fs::canonicalize(&candidate).ok()?, candidate.display().to_string())))
+    }
+}
+
+impl Module {
+    fn exports(&self, name: &str) -> bool {
+        self.defined.contains(name) && self.exported.as_ref().is_none_or(|exported| exported.contains(name))
+    }
+}
+
+// A module error at `pos`, in the program or in one of its modules
+fn error(message: String, pos: (usize, usize)) -> Diagnostic {
+    Diagnostic::new(Severity::Error, format!("module error, {message}"), Some(pos))
+}
+
+// How the module of namespace `namespace` is named in the messages
+fn describe(namespace: &str) -> String {
+    match namespace {
+        "" => "the program".to_string(),
+        namespace => format!("the module `{namespace}`"),
+    }
+}
+
+/// Whether `ident` is an identifier, as opposed to an operator, which is not namespaced
+pub fn is_name(ident: &str) -> bool {
+    ident.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+}
+
+// Renames the definitions of a module and checks its qualified names
+struct Scope<'a> {
+    loader: &'a Loader,
+    module: &'a Module,
+}
+
+impl Scope<'_> {
+    // The name of `name` in the flattened program, when it is not bound by a parameter: the
+    // definitions of a module are namespaced, and the names it does not define may be exported
+    // by the prelude. A module sees neither the definitions of the program using it nor those of
+    // the other modules, so that any other name of a module must be one of the `builtins`.
+    fn qualify(&self, name: &mut String, pos: (usize, usize), builtins: &[&str]) -> Result<(), Diagnostic> {
+        let namespace = if self.module.defined.contains(name.as_str()) {
+            &self.module.namespace
+        } else {
+            match self.module.imports.get(prelude::PRELUDE).map(|path| &self.loader.modules[path]) {
+                Some(prelude) if prelude.exports(name) => &prelude.namespace,
+                _ if self.module.namespace.is_empty() || !is_name(name) || builtins.contains(&name.as_str()) => {
+                    return Ok(())
+                }
+                _ => {
+                    let namespace = &self.module.namespace;
+                    return Err(error(format!("`{name}` is not defined in the module `{namespace}`."), pos));
+                }
+            }
+        };
+        if !namespace.is_empty() {
+            *name = format!("{namespace}.{name}");
+        }
+        Ok(())
+    }
+
+    // Checks that the qualified name `module.name` refers to a definition exported by `module`
+    fn resolve(&self, qualified: &str, pos: (usize, usize)) -> Result<(), Diagnostic> {
+        let (namespace, name) = qualified.split_once('.').unwrap();
+        let Some(path) = self.module.imports.get(namespace) else {
+            return Err(error(
+                format!("Unknown module `{namespace}`, it must be imported with `use \"{namespace}\";`."),
+                pos,
+            ));
+        };
+        let module = &self.loader.modules[path];
+        if !module.defined.contains(name) {
+            return Err(error(format!("The module `{namespace}` does not define `{name}`."), pos));
+        }
+        if !module.exports(name) {
+            return Err(error(format!("`{name}` is not exported by the module `{namespace}`."), pos));
+        }
+        Ok(())
+    }
+
+    // Checks that the operator `op` is parsed with the fixity declared by the module defining it,
+    // the module using it having been parsed with its own declarations only
+    fn fixity(&self, op: &str, pos: (usize, usize)) -> Result<(), Diagnostic> {
+        let defines = |module: &Module| {
+            (module.program.0.iter()).any(|expr| matches!(expr, Expr::LamDef { ident, .. } if ident == op))
+        };
+        let declared = self.module.fixities.get(op);
+        let defining = self.loader.modules.values().find(|module| defines(module) && module.fixities.contains_key(op));
+        match defining {
+            Some(module) if declared != module.fixities.get(op) => {
+                let (namespace, fixity) = (&module.namespace, module.fixities[op]);
+                Err(error(
+                    format!("The module `{namespace}` declares `{op}` as `{fixity}`, the modules using it must declare it as well."),
+                    pos,
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // `bound` holds the parameters in scope, which hide the definitions of the module
+    fn expr(&self, expr: &mut Expr, bound: &mut Vec<String>) -> Result<(), Diagnostic> {
+        match expr {
+            Expr::Var(name, pos) if !bound.contains(name) => self.qualify(name, *pos, &BUILTINS)?,
+            Expr::LamCall { ident, pos, .. } if is_name(ident) && ident.contains('.') => self.resolve(ident, *pos)?,
+            Expr::LamCall { ident, style: LamStyle::Infix, pos, .. } if !is_name(ident) => self.fixity(ident, *pos)?,
+            Expr::LamCall { ident, pos, .. } if !bound.contains(ident) => self.qualify(ident, *pos, &BUILTINS)?,
+            // `math.pi`, a definition of a module rather than the field of a record
+            Expr::Field { record, field } => {
+                if let Expr::Var(namespace, pos) = &**record {
+                    if self.module.imports.contains_key(namespace) && !bound.contains(namespace) {
+                        let (qualified, pos) = (format!("{namespace}.{field}"), *pos);
+                        self.resolve(&qualified, pos)?;
+                        *expr = Expr::Var(qualified, pos);
+                        return Ok(());
+                    }
+                }
+            }
+            Expr::LamDef {
+                ident,
+                params,
+                annotations,
+                ret,
+                operation,
+                pos,
+                ..
+            } => {
+                self.qualify(ident, *pos, &[])?;
+                for annotation in annotations.iter_mut().flatten().chain(ret) {
+                    self.ty(annotation, bound)?;
+                }
+                let depth = bound.len();
+                bound.extend(params.iter().cloned());
+                self.expr(operation, bound)?;
+                bound.truncate(depth);
+                return Ok(());
+            }
+            Expr::TypeDecl { ident, body, pos, .. } => {
+                self.qualify(ident, *pos, &[])?;
+                self.ty(body, bound)?;
+            }
+            Expr::Match { scrutinee, arms, .. } => {
+                self.expr(scrutinee, bound)?;
+                for (pattern, body) in arms {
+                    let depth = bound.len();
+                    bound.extend(pattern.vars().into_iter().cloned());
+                    self.expr(body, bound)?;
+                    bound.truncate(depth);
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+        for sub in expr.sub_exprs_mut() {
+            self.expr(sub, bound)?;
+        }
+        Ok(())
+    }
+
+    fn ty(&self, ty: &mut TypeExpr, bound: &mut Vec<String>) -> Result<(), Diagnostic> {
+        match ty {
+            TypeExpr::Named { name, args, pos } => {
+                if name.contains('.') {
+                    self.resolve(name, *pos)?;
+                } else if !is_type_var(name) {
+                    self.qualify(name, *pos, &BUILTIN_TYPES)?;
+                }
+                for arg in args {
+                    self.ty(arg, bound)?;
+                }
+            }
+            TypeExpr::Record { fields, .. } => {
+                for (_, field) in fields {
+                    self.ty(field, bound)?;
+                }
+            }
+            TypeExpr::Joined(from, to) => {
+                self.ty(from, bound)?;
+                self.ty(to, bound)?;
+            }
+            TypeExpr::Tuple(components) => {
+                for component in components {
+                    self.ty(component, bound)?;
+                }
+            }
+            TypeExpr::Refined { var, base, pred } => {
+                self.ty(base, bound)?;
+                bound.push(var.clone());
+                self.expr(pred, bound)?;
+                bound.pop();
+            }
+            TypeExpr::Term(term) => self.expr(term, bound)?,
+            TypeExpr::Dynamic => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::typechecker::TypeChecker;
+
+    fn write(dir: &Path, name: &str, text: &str) {
+        fs::write(dir.join(name), text).unwrap();
+    }
+
+    fn load(dir: &Path, text: &str) -> Result<Program, Diagnostic> {
+        let program = Parser::new(&mut Lexer::new(text)).parse().map_err(|err| Diagnostic::from(&err))?;
+        Loader::new(Vec::new()).load(&dir.join("main.kr"), text, program)
+    }
+
+    #[test]
+    fn modules_are_namespaces() {
+        let dir = std::env::temp_dir().join(format!("karm-modules-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        write(&dir, "math.kr", "export square, Point; type Point = { x: Int, y: Int }; lam square :: n -> n * n; lam twice :: n -> n + n; lam unit -> square(1);");
+        write(&dir, "geometry.kr", "use \"math\"; lam norm :: p: math.Point -> math.square(p.x) + math.square(p.y);");
+
+        // Both modules use math, which is loaded once
+        let program = load(&dir, "use \"math\"; use \"geometry\"; lam square :: n -> 0; lam main -> geometry.norm({ x = 3, y = 4 }) + math.square(square(7));").unwrap();
+        TypeChecker::new(&program).init().unwrap();
+        let result = Interpreter::new(&program).run().unwrap();
+        assert_eq!(result.to_string(), "25");
+
+        let err = load(&dir, "use \"math\";\nlam main -> math.twice(2);").unwrap_err();
+        assert_eq!(err.message, "module error, `twice` is not exported by the module `math`.");
+        assert_eq!(err.pos, Some((2, 18)));
+
+        // An operator is parsed with the fixities of the module using it, which must repeat those of its module
+        write(&dir, "vectors.kr", "infixr 6 <+>; lam <+> :: a, b -> a - b; lam unit -> 1 <+> 1;");
+        let err = load(&dir, "use \"vectors\";\nlam main -> 10 <+> 4 <+> 1;").unwrap_err();
+        assert_eq!(err.message, "module error, The module `vectors` declares `<+>` as `infixr 6`, the modules using it must declare it as well.");
+        assert_eq!(err.pos, Some((2, 22)));
+        let program = load(&dir, "use \"vectors\"; infixr 6 <+>; lam main -> 10 <+> 4 <+> 1;").unwrap();
+        assert_eq!(Interpreter::new(&program).run().unwrap().to_string(), "7");
+
+        // Operators are not namespaced, two modules cannot define the same one
+        write(&dir, "matrices.kr", "infixr 6 <+>;\nlam <+> :: a, b -> a + b;");
+        let err = load(&dir, "use \"vectors\"; use \"matrices\";").unwrap_err();
+        assert_eq!(err.message, "module error, `<+>` is defined by the module `vectors` and by the module `matrices`.");
+        // At the definition of matrices.kr, which follows the prelude in the source
+        assert_eq!(err.pos.map(|(_, col)| col), Some(5));
+
+        // The definitions of the program are not in scope in its modules
+        write(&dir, "lib.kr", "lam f :: n: Int : Int -> helper(n);");
+        let err = load(&dir, "use \"lib\"; lam helper :: n: Int : Int -> n;\nlam main -> lib.f(1);").unwrap_err();
+        assert_eq!(err.message, "module error, `helper` is not defined in the module `lib`.");
+        write(&dir, "shapes.kr", "type Shape = { side: Size };");
+        let err = load(&dir, "use \"shapes\"; type Size = Int;").unwrap_err();
+        assert_eq!(err.message, "module error, `Size` is not defined in the module `shapes`.");
+
+        write(&dir, "even.kr", "use \"odd\"; lam even :: n -> n;");
+        write(&dir, "odd.kr", "\nuse \"even\"; lam odd :: n -> n;");
+        let err = load(&dir, "use \"even\";").unwrap_err();
+        let even = dir.join("even.kr").display().to_string();
+        let odd = dir.join("odd.kr").display().to_string();
+        assert_eq!(err.message, format!("module error, Import cycle: {even} -> {odd} -> {even}."));
+        // The `use` of odd.kr, on its second line, follows main.kr and even.kr in the source
+        assert_eq!(err.pos, Some((4, 1)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,15 @@
 use std::fmt::Debug;
 use std::process::exit;
 
+use crate::bigint::Int;
 use crate::errors::SyntaxError;
 use crate::lexer::tokens::{Kind, Token};
 use crate::lexer::Lexer;
 
+use self::fixity::{Assoc, Fixities, Fixity, NOT_PREC};
+
+pub mod fixity;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Literal(Literal),
@@ -12,20 +17,279 @@ pub enum Expr {
         ident: String,
         style: LamStyle,
         params: Vec<Expr>,
+        // Position of the called name, or of the operator
+        pos: (usize, usize),
     },
     LamDef {
         style: LamStyle,
         ident: String,
         params: Vec<String>,
+        // The type annotation of each parameter, if it has one
+        annotations: Vec<Option<TypeExpr>>,
+        // The annotated return type
+        ret: Option<TypeExpr>,
+        // Declared with `lam pure`, the body cannot perform any effect
+        pure: bool,
         operation: Box<Expr>,
+        // Position of the defined name
+        pos: (usize, usize),
     },
-    Var(String),
+    // A variable and its position
+    Var(String, (usize, usize)),
     If {
         cond: Box<Expr>,
         then: Box<Expr>,
         alter: Box<Expr>,
+        // Position of the `if` keyword
+        pos: (usize, usize),
+    },
+    // use "path", the path of a module without its quotes, see `modules`
+    Use {
+        path: String,
+        pos: (usize, usize),
+    },
+    // export square, cube: the definitions of a module visible to the modules using it
+    Export {
+        names: Vec<String>,
+        pos: (usize, usize),
+    },
+    // infixl 6 <+>, the fixity of operators, applied by the parser to the whole module
+    Fixity {
+        ops: Vec<String>,
+        fixity: Fixity,
+        pos: (usize, usize),
+    },
+    // type Pair a b = { fst: a, snd: b }, a name for a type taking the types `params`
+    TypeDecl {
+        ident: String,
+        params: Vec<String>,
+        body: TypeExpr,
+        pos: (usize, usize),
+    },
+    // Typed hole, `_` or `?name`, whose expected type is reported by the typechecker
+    Hole {
+        name: Option<String>,
+        pos: (usize, usize),
+    },
+    // { name = "Karm", age = 3 }
+    Record(Vec<(String, Expr)>),
+    // record.field
+    Field {
+        record: Box<Expr>,
+        field: String,
+    },
+    // { record | age = 4 }, the record with some of its fields replaced
+    Update {
+        record: Box<Expr>,
+        fields: Vec<(String, Expr)>,
+    },
+    // (x: A) -> B, the type of functions whose result type B depends on the argument x.
+    // A -> B is written with the binder `_`. Only understood by the dependent core.
+    Pi {
+        binder: String,
+        domain: Box<Expr>,
+        codomain: Box<Expr>,
+    },
+    // (x: A) * B, the type of pairs whose second component has a type depending on the first
+    Sigma {
+        binder: String,
+        fst: Box<Expr>,
+        snd: Box<Expr>,
+    },
+    // [1, 2, 3], a list built with `::`, the empty list being []
+    List(Vec<Expr>),
+    // [| 1, 2, 3 |], an array whose length is fixed once built
+    Array(Vec<Expr>),
+    // [| 1, 2; 3, 4 |], a matrix of integers given row by row, the rows having the same length
+    Matrix(Vec<Vec<Expr>>),
+    // (1, "one"), a tuple of at least two components
+    Tuple(Vec<Expr>),
+    // tuple.0, the component `index` of a tuple, counted from 0
+    Project {
+        tuple: Box<Expr>,
+        index: usize,
+        pos: (usize, usize),
+    },
+    // array[index], or the row `index` of a matrix as an array
+    Index {
+        collection: Box<Expr>,
+        index: Box<Expr>,
+        pos: (usize, usize),
+    },
+    // match xs with | [] -> 0 | x :: rest -> x, the body of the first arm whose pattern matches
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<(Pattern, Expr)>,
+        pos: (usize, usize),
+    },
+    // Checks at runtime that the value of `expr` has the type `target`, blaming `pos` otherwise.
+    // Only inserted by the typechecker in gradual mode, there is no syntax for it.
+    Cast {
+        expr: Box<Expr>,
+        target: TypeExpr,
+        pos: (usize, usize),
     },
-    Use(String),
+}
+
+// The patterns of the arms of a `match`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    // `_`, matching anything
+    Wildcard,
+    // Matches anything, binding it to the name
+    Var(String),
+    Literal(Literal),
+    // [a, b], the lists of exactly as many elements, [] matching the empty list
+    List(Vec<Pattern>),
+    // head :: tail, the lists with at least one element
+    Cons(Box<Pattern>, Box<Pattern>),
+    // (a, b), the tuples of as many components
+    Tuple(Vec<Pattern>),
+}
+
+// Types as written in annotations
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeExpr {
+    // `Int`, `Str`, `Bool`, a declared type or a type variable when it starts with a lowercase
+    // letter, applied to the types `args` as in `Pair Int Str`
+    Named {
+        name: String,
+        args: Vec<TypeExpr>,
+        pos: (usize, usize),
+    },
+    // { name: Str, age: Int } or, with the rest of the fields in the row variable `r`, { name: Str | r }
+    Record {
+        fields: Vec<(String, TypeExpr)>,
+        rest: Option<String>,
+    },
+    // (Int -> Str), always parenthesized in annotations
+    Joined(Box<TypeExpr>, Box<TypeExpr>),
+    // (Int, Str), the type of the tuples of these components
+    Tuple(Vec<TypeExpr>),
+    // Any expression, as types are terms in the dependent core (see `Parser::dependent`)
+    Term(Box<Expr>),
+    // `?`, the dynamic type, whose values are checked at runtime
+    Dynamic,
+    // {v: Int | v != 0}, the values `var` of type `base` for which `pred` holds
+    Refined {
+        var: String,
+        base: Box<TypeExpr>,
+        pred: Box<Expr>,
+    },
+}
+
+impl TypeExpr {
+    /// The position of the first type name of the annotation, if it has one
+    pub fn pos(&self) -> Option<(usize, usize)> {
+        match self {
+            TypeExpr::Named { pos, .. } => Some(*pos),
+            TypeExpr::Record { fields, .. } => fields.iter().find_map(|(_, field)| field.pos()),
+            TypeExpr::Joined(from, to) => from.pos().or_else(|| to.pos()),
+            TypeExpr::Tuple(components) => components.iter().find_map(TypeExpr::pos),
+            TypeExpr::Term(term) => term.pos(),
+            TypeExpr::Refined { base, .. } => base.pos(),
+            TypeExpr::Dynamic => None,
+        }
+    }
+}
+
+/// Whether the type name `name` is a type variable, starting with a lowercase letter. `m.Pair` is
+/// the type `Pair` of the module `m`.
+pub fn is_type_var(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_lowercase()) && !name.contains('.')
+}
+
+impl Expr {
+    /// The expressions directly nested in this one
+    pub fn sub_exprs(&self) -> Vec<&Expr> {
+        match self {
+            Expr::LamCall { params, .. } => params.iter().collect(),
+            Expr::LamDef { operation, .. } => vec![operation],
+            Expr::If { cond, then, alter, .. } => vec![cond, then, alter],
+            Expr::Record(fields) => fields.iter().map(|(_, e)| e).collect(),
+            Expr::Field { record, .. } => vec![record],
+            Expr::Update { record, fields } => std::iter::once(&**record)
+                .chain(fields.iter().map(|(_, e)| e))
+                .collect(),
+            Expr::Pi { domain, codomain, .. } => vec![domain, codomain],
+            Expr::Sigma { fst, snd, .. } => vec![fst, snd],
+            Expr::Cast { expr, .. } => vec![expr],
+            Expr::List(elements) | Expr::Array(elements) | Expr::Tuple(elements) => elements.iter().collect(),
+            Expr::Project { tuple, .. } => vec![tuple],
+            Expr::Matrix(rows) => rows.iter().flatten().collect(),
+            Expr::Index { collection, index, .. } => vec![collection, index],
+            Expr::Match { scrutinee, arms, .. } => std::iter::once(&**scrutinee)
+                .chain(arms.iter().map(|(_, body)| body))
+                .collect(),
+            Expr::Literal(_)
+            | Expr::Var(..)
+            | Expr::Use { .. }
+            | Expr::Export { .. }
+            | Expr::Fixity { .. }
+            | Expr::TypeDecl { .. }
+            | Expr::Hole { .. } => Vec::new(),
+        }
+    }
+
+    /// The position of the expression, or of the first of its parts which has one: a binary
+    /// operation is at its operator. Literals have none.
+    pub fn pos(&self) -> Option<(usize, usize)> {
+        match self {
+            Expr::Var(_, pos)
+            | Expr::LamCall { pos, .. }
+            | Expr::LamDef { pos, .. }
+            | Expr::If { pos, .. }
+            | Expr::Hole { pos, .. }
+            | Expr::Cast { pos, .. }
+            | Expr::Project { pos, .. }
+            | Expr::Index { pos, .. }
+            | Expr::Match { pos, .. } => Some(*pos),
+            _ => self.sub_exprs().into_iter().find_map(Expr::pos),
+        }
+    }
+
+    /// The expressions directly nested in this one, mutably
+    pub fn sub_exprs_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::LamCall { params, .. } => params.iter_mut().collect(),
+            Expr::LamDef { operation, .. } => vec![operation],
+            Expr::If { cond, then, alter, .. } => vec![cond, then, alter],
+            Expr::Record(fields) => fields.iter_mut().map(|(_, e)| e).collect(),
+            Expr::Field { record, .. } => vec![record],
+            Expr::Update { record, fields } => std::iter::once(&mut **record)
+                .chain(fields.iter_mut().map(|(_, e)| e))
+                .collect(),
+            Expr::Pi { domain, codomain, .. } => vec![domain, codomain],
+            Expr::Sigma { fst, snd, .. } => vec![fst, snd],
+            Expr::Cast { expr, .. } => vec![expr],
+            Expr::List(elements) | Expr::Array(elements) | Expr::Tuple(elements) => elements.iter_mut().collect(),
+            Expr::Project { tuple, .. } => vec![tuple],
+            Expr::Matrix(rows) => rows.iter_mut().flatten().collect(),
+            Expr::Index { collection, index, .. } => vec![collection, index],
+            Expr::Match { scrutinee, arms, .. } => std::iter::once(&mut **scrutinee)
+                .chain(arms.iter_mut().map(|(_, body)| body))
+                .collect(),
+            Expr::Literal(_)
+            | Expr::Var(..)
+            | Expr::Use { .. }
+            | Expr::Export { .. }
+            | Expr::Fixity { .. }
+            | Expr::TypeDecl { .. }
+            | Expr::Hole { .. } => Vec::new(),
+        }
+    }
+}
+
+impl Pattern {
+    /// The names bound by the pattern, in order
+    pub fn vars(&self) -> Vec<&String> {
+        match self {
+            Pattern::Var(name) => vec![name],
+            Pattern::List(elements) | Pattern::Tuple(elements) => elements.iter().flat_map(Pattern::vars).collect(),
+            Pattern::Cons(head, tail) => head.vars().into_iter().chain(tail.vars()).collect(),
+            Pattern::Wildcard | Pattern::Literal(_) => Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -37,27 +301,42 @@ pub enum LamStyle {
 #[derive(Clone, PartialEq)]
 pub enum Literal {
     Str(String),
-    Int(i32),
+    Int(Int),
 }
 
 // TODO: Simple disclaimers :
 // ! Could the parser potentially become an iterator too ?
 // ! Should I use a more functional approach for the parser ?
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone)]
 pub struct Program(pub Vec<Expr>);
 
 pub struct Parser<'a> {
     lexer: &'a mut Lexer<'a>, 
+    // Annotations are parsed as expressions, for the dependent core
+    terms_as_types: bool,
+    // Inside an annotation, where `->` ends the parameters instead of building a function type
+    in_annotation: bool,
+    fixities: Fixities,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(lexer: &'a mut Lexer<'a>) -> Self {
         lexer.next(); // First token is a `None`
+        let fixities = Fixities::scan(lexer);
         Self {
             lexer,
+            terms_as_types: false,
+            in_annotation: false,
+            fixities,
         }
     }
 
+    /// Parses the annotations as terms (`TypeExpr::Term`), as expected by the dependent core.
+    pub fn dependent(mut self) -> Self {
+        self.terms_as_types = true;
+        self
+    }
+
     pub fn program(mut self) -> Result<Program, SyntaxError> {
         if self.peek().is_none() {
             println!("Program Terminated : Lookahead is empty, nothing to parse.");
@@ -76,9 +355,9 @@ impl<'a> Parser<'a> {
     }
 
     fn expr_def(&mut self) -> Result<Expr, SyntaxError> {
-        let expr = self.expr();
+        let expr = self.expr()?;
         self.next(&Kind::SemiColon)?;
-        expr
+        Ok(expr)
     }
 
     fn expr(&mut self) -> Result<Expr, SyntaxError> {
@@ -94,67 +373,290 @@ impl<'a> Parser<'a> {
         match next_token.kind {
             Kind::Lam => self.lam_expr(),
             Kind::Use => self.use_expr(),
+            Kind::Export => self.export_expr(),
+            Kind::Type => self.type_decl(),
+            Kind::Fixity => self.fixity_decl(),
             _ => Err(SyntaxError(
-                "Only UseExprs and LamExprs are allowed on the toplevel.".to_owned(),
-                self.lexer.coords
+                "Only UseExprs, LamExprs, export lists, fixity and type declarations are allowed on the toplevel.".to_owned(),
+                next_token.pos
             )),
         }
     }
 
     fn use_expr(&mut self) -> Result<Expr, SyntaxError> {
-        self.next(&Kind::Use)?;
+        let pos = self.next(&Kind::Use)?.pos;
         let path = self.next(&Kind::String)?.value;
-        Ok(Expr::Use(path.to_string()))
+        Ok(Expr::Use {
+            path: path.trim_matches('"').to_string(),
+            pos,
+        })
+    }
+
+    fn export_expr(&mut self) -> Result<Expr, SyntaxError> {
+        let pos = self.next(&Kind::Export)?.pos;
+        let mut names = vec![self.next(&Kind::Ident)?.value.to_string()];
+        while self.peek_kind() == Some(Kind::Comma) {
+            self.next(&Kind::Comma)?;
+            names.push(self.next(&Kind::Ident)?.value.to_string());
+        }
+        Ok(Expr::Export { names, pos })
+    }
+
+    // Already taken into account by `Fixities::scan`, the declaration is checked here
+    fn fixity_decl(&mut self) -> Result<Expr, SyntaxError> {
+        let keyword = self.next(&Kind::Fixity)?;
+        let prec = self.next(&Kind::Integer)?;
+        let Some(fixity) = Fixities::declared(keyword.value, prec.value) else {
+            return Err(SyntaxError("A precedence goes from 0 to 9.".to_owned(), prec.pos));
+        };
+        let mut ops = Vec::new();
+        loop {
+            match self.peek() {
+                Some(op) if Fixities::is_operator(op.kind) => ops.push(self.next(&op.kind)?.value.to_string()),
+                other => {
+                    let pos = other.map_or(self.lexer.coords, |token| token.pos);
+                    return Err(SyntaxError("Expected an operator.".to_owned(), pos));
+                }
+            }
+            if self.peek_kind() != Some(Kind::Comma) {
+                break;
+            }
+            self.next(&Kind::Comma)?;
+        }
+        Ok(Expr::Fixity {
+            ops,
+            fixity,
+            pos: keyword.pos,
+        })
+    }
+
+    fn type_decl(&mut self) -> Result<Expr, SyntaxError> {
+        let pos = self.next(&Kind::Type)?.pos;
+        let ident = self.next(&Kind::Ident)?.value.to_string();
+        let mut params = Vec::new();
+        while self.peek_kind() == Some(Kind::Ident) {
+            params.push(self.next(&Kind::Ident)?.value.to_string());
+        }
+        self.next(&Kind::Eq)?;
+        // Nothing follows the body, so its arrows do not need parentheses
+        let body = self.joined_type_expr()?;
+        Ok(Expr::TypeDecl {
+            ident,
+            params,
+            body,
+            pos,
+        })
     }
 
     // ? No more function nesting (we call if_exprs and not expr everywhere)
     fn lam_expr(&mut self) -> Result<Expr, SyntaxError> {
         self.next(&Kind::Lam)?;
 
+        let pure = self.peek_kind() == Some(Kind::Pure);
+        if pure {
+            self.next(&Kind::Pure)?;
+        }
+
         // Represent the parsed parameters identifiers
         let mut params: Vec<String> = Vec::new();
-
-        // Identifier of the function
-        let id = self.next(&Kind::Ident)?.value.to_string();
+        let mut annotations: Vec<Option<TypeExpr>> = Vec::new();
+        // The tuple patterns given instead of a name, with their position
+        let mut patterns: Vec<(String, Pattern, (usize, usize))> = Vec::new();
+
+        // Identifier of the function, or the operator it defines as in `lam ~ :: x -> ...`
+        let name = match self.peek_kind() {
+            Some(Kind::Operator) => self.next(&Kind::Operator)?,
+            _ => self.next(&Kind::Ident)?,
+        };
+        let id = name.value.to_string();
 
         // Prefix | Infix 
         let mut style = LamStyle::Prefix;
 
-        let next_token = self.peek().unwrap();
-
-        if next_token.kind == Kind::Bar {
+        if self.peek_kind() == Some(Kind::Bar) {
             self.next(&Kind::Bar)?;
             style = LamStyle::Infix;
         }
 
         // Check if the function has parameters (if it has the :: operator, it has parameters).
-        if next_token.kind == Kind::DoubleColon {
+        if self.peek_kind() == Some(Kind::DoubleColon) {
             self.next(&Kind::DoubleColon)?;
-            while self.peek().unwrap().kind != Kind::Arrow {
-                params.push(self.next(&Kind::Ident)?.value.to_string());
-                if self.peek().unwrap().kind == Kind::Comma {
+            while !matches!(self.peek_kind(), Some(Kind::Arrow) | Some(Kind::Colon)) {
+                if let Some(Token { kind: Kind::LParen, pos, .. }) = self.peek() {
+                    // The parameter is named after its pattern, which no identifier can be
+                    let pattern = self.pattern_atom()?;
+                    params.push(pattern.to_string());
+                    patterns.push((pattern.to_string(), pattern, pos));
+                } else {
+                    params.push(self.next(&Kind::Ident)?.value.to_string());
+                }
+                if self.peek_kind() == Some(Kind::Colon) {
+                    self.next(&Kind::Colon)?;
+                    annotations.push(Some(self.annotation()?));
+                } else {
+                    annotations.push(None);
+                }
+                if self.peek_kind() == Some(Kind::Comma) {
                     self.next(&Kind::Comma)?;
                 }
             }
         }
 
+        // Return type, as in `lam succ :: n: Int : Int -> n + 1`
+        let mut ret = None;
+        if self.peek_kind() == Some(Kind::Colon) {
+            self.next(&Kind::Colon)?;
+            ret = Some(self.annotation()?);
+        }
+
         self.next(&Kind::Arrow)?;
 
+        // `lam swap :: (a, b) -> (b, a)` takes its parameter apart with a match
+        let mut operation = self.if_expr()?;
+        for (param, pattern, pos) in patterns.into_iter().rev() {
+            operation = Expr::Match {
+                scrutinee: Box::new(Expr::Var(param, pos)),
+                arms: vec![(pattern, operation)],
+                pos,
+            };
+        }
+
         Ok(Expr::LamDef {
             ident: id.to_string(),
             style,
             params,
-            operation: Box::new(self.if_expr()?),
+            annotations,
+            ret,
+            pure,
+            operation: Box::new(operation),
+            pos: name.pos,
         })
     }
 
+    fn annotation(&mut self) -> Result<TypeExpr, SyntaxError> {
+        if !self.terms_as_types {
+            return self.type_expr();
+        }
+        self.in_annotation = true;
+        let term = self.binary_expr();
+        self.in_annotation = false;
+        Ok(TypeExpr::Term(Box::new(term?)))
+    }
+
+    // Type of a parameter, arrows must be parenthesized as `->` ends the parameters
+    fn type_expr(&mut self) -> Result<TypeExpr, SyntaxError> {
+        let mut ty = self.type_atom()?;
+        if let TypeExpr::Named { args, .. } = &mut ty {
+            while matches!(
+                self.peek_kind(),
+                Some(Kind::Ident) | Some(Kind::LParen) | Some(Kind::LBrace) | Some(Kind::QMark)
+            ) {
+                args.push(self.type_atom()?);
+            }
+        }
+        Ok(ty)
+    }
+
+    // A type that can be given as argument to a named type without parentheses
+    fn type_atom(&mut self) -> Result<TypeExpr, SyntaxError> {
+        match self.peek_kind() {
+            Some(Kind::LBrace) => {
+                let open = self.next(&Kind::LBrace)?.pos;
+                let mut fields = Vec::new();
+                let mut rest = None;
+                while self.peek_kind() == Some(Kind::Ident) {
+                    let label = self.next(&Kind::Ident)?.value.to_string();
+                    self.next(&Kind::Colon)?;
+                    fields.push((label, self.type_expr()?));
+                    if self.peek_kind() != Some(Kind::Comma) {
+                        break;
+                    }
+                    self.next(&Kind::Comma)?;
+                }
+                if self.peek_kind() == Some(Kind::Bar) {
+                    self.next(&Kind::Bar)?;
+                    // A name after the bar is the rest of a record, anything else refines the field
+                    match self.if_expr()? {
+                        Expr::Var(name, _) => rest = Some(name),
+                        pred if fields.len() == 1 => {
+                            self.next(&Kind::RBrace)?;
+                            let (var, base) = fields.pop().unwrap();
+                            return Ok(TypeExpr::Refined {
+                                var,
+                                base: Box::new(base),
+                                pred: Box::new(pred),
+                            });
+                        }
+                        _ => {
+                            return Err(SyntaxError(
+                                "A refinement type `{v: Int | predicate}` has a single binder.".to_owned(),
+                                open,
+                            ))
+                        }
+                    }
+                }
+                self.next(&Kind::RBrace)?;
+                Ok(TypeExpr::Record { fields, rest })
+            }
+            Some(Kind::LParen) => {
+                self.next(&Kind::LParen)?;
+                let mut ty = self.type_expr()?;
+                if self.peek_kind() == Some(Kind::Comma) {
+                    let mut components = vec![ty];
+                    while self.peek_kind() == Some(Kind::Comma) {
+                        self.next(&Kind::Comma)?;
+                        components.push(self.joined_type_expr()?);
+                    }
+                    self.next(&Kind::RParen)?;
+                    return Ok(TypeExpr::Tuple(components));
+                }
+                if self.peek_kind() == Some(Kind::Arrow) {
+                    self.next(&Kind::Arrow)?;
+                    ty = TypeExpr::Joined(Box::new(ty), Box::new(self.joined_type_expr()?));
+                }
+                self.next(&Kind::RParen)?;
+                Ok(ty)
+            }
+            Some(Kind::QMark) => {
+                self.next(&Kind::QMark)?;
+                Ok(TypeExpr::Dynamic)
+            }
+            _ => {
+                let token = self.next(&Kind::Ident)?;
+                let mut name = token.value.to_string();
+                // A type of another module, `math.Vec`
+                if self.peek_kind() == Some(Kind::Dot) {
+                    self.next(&Kind::Dot)?;
+                    name = format!("{name}.{}", self.next(&Kind::Ident)?.value);
+                }
+                Ok(TypeExpr::Named {
+                    name,
+                    args: Vec::new(),
+                    pos: token.pos,
+                })
+            }
+        }
+    }
+
+    // Right hand side of an arrow inside parentheses, arrows associate to the right
+    fn joined_type_expr(&mut self) -> Result<TypeExpr, SyntaxError> {
+        let ty = self.type_expr()?;
+        if self.peek_kind() == Some(Kind::Arrow) {
+            self.next(&Kind::Arrow)?;
+            return Ok(TypeExpr::Joined(Box::new(ty), Box::new(self.joined_type_expr()?)));
+        }
+        Ok(ty)
+    }
+
     fn if_expr(&mut self) -> Result<Expr, SyntaxError> {
-        
-        let next_token = self.peek().unwrap();
 
-        if next_token.kind == Kind::If {
+        if self.peek_kind() == Some(Kind::Match) {
+            return self.match_expr();
+        }
+
+        if self.peek_kind() == Some(Kind::If) {
 
-            self.next(&Kind::If)?;
+            let pos = self.next(&Kind::If)?.pos;
 
             let cond: Expr = self.if_expr()?;
             self.next(&Kind::QMark)?;
@@ -168,6 +670,7 @@ impl<'a> Parser<'a> {
                 cond: Box::from(cond),
                 then: Box::from(then),
                 alter: Box::from(alter),
+                pos,
             });
         }
 
@@ -175,135 +678,503 @@ impl<'a> Parser<'a> {
     }
 
     fn binary_expr(&mut self) -> Result<Expr, SyntaxError> {
-        self.conditional_expr()
-    }
+        let left = self.operator_expr(0, None)?;
+
+        // A -> B, right associative
+        if self.peek_kind() == Some(Kind::Arrow) && !self.in_annotation {
+            self.next(&Kind::Arrow)?;
+            return Ok(Expr::Pi {
+                binder: "_".to_owned(),
+                domain: Box::new(left),
+                codomain: Box::new(self.if_expr()?),
+            });
+        }
 
-    fn parenthesized_expr(&mut self) -> Result<Expr, SyntaxError> {
-        self.next(&Kind::LParen)?;
-        let expr = self.if_expr();
-        self.next(&Kind::RParen)?;
-        expr
+        Ok(left)
     }
 
-    fn conditional_expr(&mut self) -> Result<Expr, SyntaxError> {
-
-        let mut left: Expr = self.low_prec_expr()?;
-
-        let next_token =  self.peek().unwrap();
-
-        while next_token.get_prec() == 1 {
-
-            let op = self.next(&next_token.kind)?.value.to_string();
-
-            let right = self.low_prec_expr()?;
+    fn parenthesized_expr(&mut self) -> Result<Expr, SyntaxError> {
+        let open = self.next(&Kind::LParen)?.pos;
+        let in_annotation = std::mem::replace(&mut self.in_annotation, false);
+        let expr = self.if_expr()?;
+
+        // (a, b), a tuple rather than a parenthesized expression
+        if self.peek_kind() == Some(Kind::Comma) {
+            let mut components = vec![expr];
+            while self.peek_kind() == Some(Kind::Comma) {
+                self.next(&Kind::Comma)?;
+                components.push(self.if_expr()?);
+            }
+            self.next(&Kind::RParen)?;
+            self.in_annotation = in_annotation;
+            return Ok(Expr::Tuple(components));
+        }
 
-            left = Expr::LamCall {
-                ident: op.to_string(),
-                style: LamStyle::Infix,
-                params: vec![left, right],
+        // (x: A) binds x in the type following it
+        let mut binder = None;
+        if self.peek_kind() == Some(Kind::Colon) {
+            let id = match &expr {
+                Expr::Var(id, _) => id.clone(),
+                Expr::Hole { name: None, .. } => "_".to_owned(),
+                _ => return Err(SyntaxError("Expected an identifier before `:`.".to_owned(), open)),
             };
-
+            self.next(&Kind::Colon)?;
+            binder = Some((id, self.if_expr()?));
         }
 
-        Ok(left)
+        self.next(&Kind::RParen)?;
+        self.in_annotation = in_annotation;
+
+        let Some((binder, domain)) = binder else {
+            return Ok(expr);
+        };
+        match self.peek_kind() {
+            Some(Kind::Arrow) if !self.in_annotation => {
+                self.next(&Kind::Arrow)?;
+                Ok(Expr::Pi {
+                    binder,
+                    domain: Box::new(domain),
+                    codomain: Box::new(self.if_expr()?),
+                })
+            }
+            Some(Kind::Mul) => {
+                self.next(&Kind::Mul)?;
+                Ok(Expr::Sigma {
+                    binder,
+                    fst: Box::new(domain),
+                    snd: Box::new(self.if_expr()?),
+                })
+            }
+            Some(Kind::Arrow) => Err(SyntaxError(
+                "A dependent function type must be parenthesized in an annotation.".to_owned(),
+                open,
+            )),
+            _ => Err(SyntaxError("Expected `->` or `*` after a binder `(x: A)`.".to_owned(), open)),
+        }
     }
 
-    // Operation such as +, - (expressions)
-    fn low_prec_expr(&mut self) -> Result<Expr, SyntaxError> {
+    // The arms of a match extend as far as possible, a match nested in an arm takes the
+    // following arms
+    fn match_expr(&mut self) -> Result<Expr, SyntaxError> {
+        let pos = self.next(&Kind::Match)?.pos;
+        let scrutinee = self.if_expr()?;
+        self.next(&Kind::With)?;
 
-        let mut left = self.high_prec_expr()?;
+        let mut arms = Vec::new();
+        // The bar before the first arm is optional
+        if self.peek_kind() == Some(Kind::Bar) {
+            self.next(&Kind::Bar)?;
+        }
+        loop {
+            let pattern = self.pattern()?;
+            self.next(&Kind::Arrow)?;
+            arms.push((pattern, self.if_expr()?));
+            if self.peek_kind() != Some(Kind::Bar) {
+                break;
+            }
+            self.next(&Kind::Bar)?;
+        }
+        // In layout mode, the arms indented less than the first one belong to an outer match
+        if self.peek_kind() == Some(Kind::BlockEnd) {
+            self.next(&Kind::BlockEnd)?;
+        }
 
-        let next_token = self.peek().unwrap();
+        Ok(Expr::Match {
+            scrutinee: Box::new(scrutinee),
+            arms,
+            pos,
+        })
+    }
 
-        while next_token.get_prec() == 2 {
+    // `head :: tail`, right associative
+    fn pattern(&mut self) -> Result<Pattern, SyntaxError> {
+        let head = self.pattern_atom()?;
+        if self.peek_kind() == Some(Kind::DoubleColon) {
+            self.next(&Kind::DoubleColon)?;
+            return Ok(Pattern::Cons(Box::new(head), Box::new(self.pattern()?)));
+        }
+        Ok(head)
+    }
 
-            let op = match self.next(&next_token.kind) {
-                Ok(val) => val.value.to_string(),
-                Err(e) => return Err(e),
-            };
-            
-            let right = self.high_prec_expr()?;
+    fn pattern_atom(&mut self) -> Result<Pattern, SyntaxError> {
+        match self.peek_kind() {
+            Some(Kind::Integer) => Ok(Pattern::Literal(Literal::Int(
+                self.next(&Kind::Integer)?.value.parse::<Int>().unwrap(),
+            ))),
+            Some(Kind::String) => Ok(Pattern::Literal(Literal::Str(
+                self.next(&Kind::String)?.value.to_string(),
+            ))),
+            Some(Kind::Min) => {
+                self.next(&Kind::Min)?;
+                let n = self.next(&Kind::Integer)?.value.parse::<Int>().unwrap();
+                Ok(Pattern::Literal(Literal::Int(Int::from(0).sub(&n))))
+            }
+            Some(Kind::LBracket) => {
+                self.next(&Kind::LBracket)?;
+                let mut elements = Vec::new();
+                while self.peek_kind() != Some(Kind::RBracket) {
+                    elements.push(self.pattern()?);
+                    if self.peek_kind() != Some(Kind::Comma) {
+                        break;
+                    }
+                    self.next(&Kind::Comma)?;
+                }
+                self.next(&Kind::RBracket)?;
+                Ok(Pattern::List(elements))
+            }
+            Some(Kind::LParen) => {
+                self.next(&Kind::LParen)?;
+                let mut components = vec![self.pattern()?];
+                while self.peek_kind() == Some(Kind::Comma) {
+                    self.next(&Kind::Comma)?;
+                    components.push(self.pattern()?);
+                }
+                self.next(&Kind::RParen)?;
+                Ok(if components.len() == 1 { components.pop().unwrap() } else { Pattern::Tuple(components) })
+            }
+            Some(Kind::Ident) => match self.next(&Kind::Ident)?.value {
+                "_" => Ok(Pattern::Wildcard),
+                name => Ok(Pattern::Var(name.to_string())),
+            },
+            _ => Err(SyntaxError("Expected a pattern.".to_owned(), self.lexer.coords)),
+        }
+    }
 
+    // The binary operators by precedence climbing, `min_prec` being the lowest precedence of the
+    // operators this operand can hold (see `fixity`). `above` is the operator this operand is
+    // the right side of, with which the following operators of the same precedence must associate.
+    fn operator_expr(&mut self, min_prec: u8, above: Option<(&'a str, Fixity)>) -> Result<Expr, SyntaxError> {
+        let mut left = self.prefix_expr()?;
+        let mut previous = above;
+
+        while let Some((op, fixity)) = self.peek_operator().filter(|(_, fixity)| fixity.prec >= min_prec) {
+            if let Some((other, other_fixity)) = previous.filter(|(_, other)| other.prec == fixity.prec) {
+                if fixity.assoc == Assoc::None || other_fixity.assoc != fixity.assoc {
+                    return Err(SyntaxError(
+                        format!("`{other}` and `{}` cannot be chained without parentheses, their fixities clash.", op.value),
+                        op.pos,
+                    ));
+                }
+            }
+            self.next(&op.kind)?;
+            let right_prec = if fixity.assoc == Assoc::Right { fixity.prec } else { fixity.prec + 1 };
+            let right = self.operator_expr(right_prec, Some((op.value, fixity)))?;
+            previous = Some((op.value, fixity));
+            if op.kind == Kind::Pipe {
+                left = Self::pipe(left, right, op.pos)?;
+                continue;
+            }
             left = Expr::LamCall {
-                ident: op.to_string(),
+                ident: op.value.to_string(),
                 style: LamStyle::Infix,
                 params: vec![left, right],
+                pos: op.pos,
             };
-
         }
 
         Ok(left)
     }
-    
-    // Operation such as *, /
-    fn high_prec_expr(&mut self) -> Result<Expr, SyntaxError> {
 
-        let mut left: Expr = self.factor()?;
-
-        let next_token = self.peek().unwrap();
+    // `x |> f` is the call `f(x)`, and `x |> f(a)` is `f(a, x)`. A pipeline through composed
+    // functions `x |> f >> g` is `g(f(x))`, without building the composition. Like `math.square(x)`,
+    // `x |> math.square` calls the function of a module rather than a field.
+    fn pipe(arg: Expr, function: Expr, pos: (usize, usize)) -> Result<Expr, SyntaxError> {
+        let function = match function {
+            Expr::Field { record, field } => match *record {
+                Expr::Var(module, pos) => Expr::Var(format!("{module}.{field}"), pos),
+                record => Expr::Field {
+                    record: Box::new(record),
+                    field,
+                },
+            },
+            function => function,
+        };
+        match function {
+            Expr::Var(ident, pos) => Ok(Expr::LamCall {
+                ident,
+                style: LamStyle::Prefix,
+                params: vec![arg],
+                pos,
+            }),
+            Expr::LamCall { ident, mut params, style: LamStyle::Prefix, pos } => {
+                params.push(arg);
+                Ok(Expr::LamCall {
+                    ident,
+                    style: LamStyle::Prefix,
+                    params,
+                    pos,
+                })
+            }
+            Expr::LamCall { ident, mut params, style: LamStyle::Infix, .. } if ident == ">>" || ident == "<<" => {
+                let (mut second, mut first) = (params.pop().unwrap(), params.pop().unwrap());
+                if ident == "<<" {
+                    std::mem::swap(&mut first, &mut second);
+                }
+                Self::pipe(Self::pipe(arg, first, pos)?, second, pos)
+            }
+            _ => Err(SyntaxError(
+                "The right side of `|>` must be a function, a call missing its last argument or a composition.".to_owned(),
+                pos,
+            )),
+        }
+    }
 
-        while next_token.get_prec() == 3 {
+    // The next token and its fixity if it is a binary operator
+    fn peek_operator(&self) -> Option<(Token<'a>, Fixity)> {
+        let token = self.peek()?;
+        self.fixities.of(&token).map(|fixity| (token, fixity))
+    }
 
-            let op = self.next(&next_token.kind)?.value.to_string();
+    // `not b`, whose operand holds the comparisons: `not x == y` is `not (x == y)`
+    fn prefix_expr(&mut self) -> Result<Expr, SyntaxError> {
+        if self.peek_kind() != Some(Kind::Not) {
+            return self.unary_expr();
+        }
+        let pos = self.next(&Kind::Not)?.pos;
+        Ok(Expr::LamCall {
+            ident: "not".to_string(),
+            style: LamStyle::Prefix,
+            params: vec![self.operator_expr(NOT_PREC, None)?],
+            pos,
+        })
+    }
 
-            let right = self.factor()?;
+    // `-x` and the user-defined prefix operators such as `~x`, binding tighter than the binary
+    // operators: `-x * y` is `(-x) * y`. `-1` is a literal rather than a negation.
+    fn unary_expr(&mut self) -> Result<Expr, SyntaxError> {
+        let op = match self.peek() {
+            Some(token @ Token { kind: Kind::Min | Kind::Operator, .. }) => self.next(&token.kind)?,
+            _ => return self.factor(),
+        };
+        let operand = self.unary_expr()?;
+        if let (Kind::Min, Expr::Literal(Literal::Int(n))) = (op.kind, &operand) {
+            return Ok(Expr::Literal(Literal::Int(Int::from(0).sub(n))));
+        }
+        Ok(Expr::LamCall {
+            ident: if op.kind == Kind::Min { "negate" } else { op.value }.to_string(),
+            style: LamStyle::Prefix,
+            params: vec![operand],
+            pos: op.pos,
+        })
+    }
 
-            left = Expr::LamCall {
-                ident: op.to_string(),
-                style: LamStyle::Infix,
-                params: vec![left, right],
+    // A factor followed by any number of field accesses, projections and indexings. `math.square(2)` calls
+    // the function `square` of the module `math`, which the module loader checks.
+    fn factor(&mut self) -> Result<Expr, SyntaxError> {
+        let mut expr = self.atom()?;
+
+        while let Some(kind @ (Kind::Dot | Kind::LBracket)) = self.peek_kind() {
+            if kind == Kind::LBracket {
+                let pos = self.next(&Kind::LBracket)?.pos;
+                let index = self.if_expr()?;
+                self.next(&Kind::RBracket)?;
+                expr = Expr::Index {
+                    collection: Box::new(expr),
+                    index: Box::new(index),
+                    pos,
+                };
+                continue;
+            }
+            self.next(&Kind::Dot)?;
+            if self.peek_kind() == Some(Kind::Integer) {
+                let component = self.next(&Kind::Integer)?;
+                let Ok(index) = component.value.parse() else {
+                    return Err(SyntaxError(format!("A tuple has no component {}.", component.value), component.pos));
+                };
+                expr = Expr::Project {
+                    tuple: Box::new(expr),
+                    index,
+                    pos: component.pos,
+                };
+                continue;
+            }
+            let field = self.next(&Kind::Ident)?;
+            expr = match expr {
+                Expr::Var(module, _) if self.peek_kind() == Some(Kind::LParen) => Expr::LamCall {
+                    ident: format!("{module}.{}", field.value),
+                    style: LamStyle::Prefix,
+                    params: self.call_args()?,
+                    pos: field.pos,
+                },
+                expr => Expr::Field {
+                    record: Box::new(expr),
+                    field: field.value.to_string(),
+                },
             };
-
         }
 
-        Ok(left)
+        Ok(expr)
     }
 
-    fn factor(&mut self) -> Result<Expr, SyntaxError> {
-        match self.peek().unwrap().kind {
-            Kind::Integer => Ok(Expr::Literal(Literal::Int(
+    fn atom(&mut self) -> Result<Expr, SyntaxError> {
+        match self.peek_kind() {
+            Some(Kind::Integer) => Ok(Expr::Literal(Literal::Int(
                 match self.next(&Kind::Integer) {
-                    Ok(val) => val.value.parse::<i32>().unwrap(),
+                    // The lexer only produces digits
+                    Ok(val) => val.value.parse::<Int>().unwrap(),
                     Err(e) => return Err(e),
                 },
             ))),
-            Kind::String => Ok(Expr::Literal(Literal::Str(
+            Some(Kind::String) => Ok(Expr::Literal(Literal::Str(
                 self.next(&Kind::String)?.value.to_string(),
             ))),
-            Kind::LParen => self.parenthesized_expr(),
+            Some(Kind::LParen) => self.parenthesized_expr(),
+            Some(Kind::QMark) => self.hole(),
+            Some(Kind::LBrace) => self.record(),
+            Some(Kind::LBracket) => {
+                self.next(&Kind::LBracket)?;
+                let elements = self.elements()?;
+                self.next(&Kind::RBracket)?;
+                Ok(Expr::List(elements))
+            }
+            Some(Kind::LArray) => self.array(),
             _ => self.ident(),
         }
     }
 
-    fn ident(&mut self) -> Result<Expr, SyntaxError> {
-        let id = self.next(&Kind::Ident)?.value.to_string();
+    // Comma separated expressions, up to the end of a list, of an array or of a row
+    fn elements(&mut self) -> Result<Vec<Expr>, SyntaxError> {
+        let mut elements = Vec::new();
+        while !matches!(self.peek_kind(), Some(Kind::RBracket | Kind::RArray | Kind::SemiColon)) {
+            elements.push(self.if_expr()?);
+            if self.peek_kind() != Some(Kind::Comma) {
+                break;
+            }
+            self.next(&Kind::Comma)?;
+        }
+        Ok(elements)
+    }
 
-        if self.peek().unwrap().kind == Kind::LParen {
-            let mut params: Vec<Expr> = Vec::new();
-            self.next(&Kind::LParen)?;
+    // An array `[| 1, 2 |]`, or a matrix when it has several rows separated by `;`
+    fn array(&mut self) -> Result<Expr, SyntaxError> {
+        let open = self.next(&Kind::LArray)?.pos;
+        let mut rows = vec![self.elements()?];
+        while self.peek_kind() == Some(Kind::SemiColon) {
+            self.next(&Kind::SemiColon)?;
+            rows.push(self.elements()?);
+        }
+        self.next(&Kind::RArray)?;
+
+        if rows.len() == 1 {
+            return Ok(Expr::Array(rows.pop().unwrap()));
+        }
+        if rows.iter().any(|row| row.len() != rows[0].len() || row.is_empty()) {
+            return Err(SyntaxError("The rows of a matrix must have the same, non-zero length.".to_owned(), open));
+        }
+        Ok(Expr::Matrix(rows))
+    }
+
+    // Either a record `{ a = 1, b = 2 }` or an update `{ r | a = 1 }`
+    fn record(&mut self) -> Result<Expr, SyntaxError> {
+        let open = self.next(&Kind::LBrace)?.pos;
+
+        if self.peek_kind() == Some(Kind::RBrace) {
+            self.next(&Kind::RBrace)?;
+            return Ok(Expr::Record(Vec::new()));
+        }
+
+        let first = self.if_expr()?;
+
+        if self.peek_kind() == Some(Kind::Bar) {
+            self.next(&Kind::Bar)?;
+            let fields = self.record_fields()?;
+            return Ok(Expr::Update {
+                record: Box::new(first),
+                fields,
+            });
+        }
+
+        // The first field name has been parsed as an expression
+        let Expr::Var(label, _) = first else {
+            return Err(SyntaxError("Expected a field name or a record update.".to_owned(), open));
+        };
+        self.next(&Kind::Eq)?;
+        let mut fields = vec![(label, self.if_expr()?)];
+        if self.peek_kind() == Some(Kind::Comma) {
+            self.next(&Kind::Comma)?;
+            fields.extend(self.record_fields()?);
+        } else {
+            self.next(&Kind::RBrace)?;
+        }
+        Ok(Expr::Record(fields))
+    }
 
-            while self.peek().unwrap().kind != Kind::RParen {
-                let param = self.conditional_expr()?;
-                params.push(param);
+    // Comma separated `label = expr` up to the closing brace
+    fn record_fields(&mut self) -> Result<Vec<(String, Expr)>, SyntaxError> {
+        let mut fields = Vec::new();
+        while self.peek_kind() != Some(Kind::RBrace) {
+            let label = self.next(&Kind::Ident)?.value.to_string();
+            self.next(&Kind::Eq)?;
+            fields.push((label, self.if_expr()?));
+            if self.peek_kind() != Some(Kind::Comma) {
+                break;
             }
-            self.next(&Kind::RParen)?;
+            self.next(&Kind::Comma)?;
+        }
+        self.next(&Kind::RBrace)?;
+        Ok(fields)
+    }
 
+    // Named hole `?name`, the anonymous `_` is handled by `ident`
+    fn hole(&mut self) -> Result<Expr, SyntaxError> {
+        let pos = self.next(&Kind::QMark)?.pos;
+        let name = self.next(&Kind::Ident)?.value.to_string();
+        Ok(Expr::Hole {
+            name: Some(name),
+            pos,
+        })
+    }
+
+    fn ident(&mut self) -> Result<Expr, SyntaxError> {
+        let token = self.next(&Kind::Ident)?;
+        let id = token.value.to_string();
+
+        if id == "_" {
+            return Ok(Expr::Hole {
+                name: None,
+                pos: token.pos,
+            });
+        }
+
+        if self.peek_kind() == Some(Kind::LParen) {
             return Ok(Expr::LamCall {
                 ident: id,
                 style: LamStyle::Prefix,
-                params,
+                params: self.call_args()?,
+                pos: token.pos,
             });
         }
 
-        Ok(Expr::Var(id))
+        Ok(Expr::Var(id, token.pos))
+    }
+
+    // The parenthesized arguments of a call
+    fn call_args(&mut self) -> Result<Vec<Expr>, SyntaxError> {
+        let mut params: Vec<Expr> = Vec::new();
+        self.next(&Kind::LParen)?;
+
+        while self.peek_kind() != Some(Kind::RParen) {
+            let param = self.if_expr()?;
+            params.push(param);
+            if self.peek_kind() != Some(Kind::Comma) {
+                break;
+            }
+            self.next(&Kind::Comma)?;
+        }
+        self.next(&Kind::RParen)?;
+        Ok(params)
     }
 
     fn peek(&self) -> Option<Token<'a>> {
         self.lexer.peeked.clone()
     }
 
+    fn peek_kind(&self) -> Option<Kind> {
+        self.lexer.peeked.as_ref().map(|tok| tok.kind)
+    }
+
     // Mutates the Lexer (and therefore the Parser) by its use of lexer.next()
-    fn next(&mut self, kind_target: &Kind) -> Result<Token, SyntaxError> {
+    fn next(&mut self, kind_target: &Kind) -> Result<Token<'a>, SyntaxError> {
 
         let next = self.lexer.next();
 
@@ -320,7 +1191,7 @@ impl<'a> Parser<'a> {
 
         if &t.kind != kind_target {
             return Err(
-                SyntaxError(format!("Expected token {:?}, got {:?}", vec![*kind_target], t.kind).to_owned(), self.lexer.coords)
+                SyntaxError(format!("Expected token {:?}, got {:?}", vec![*kind_target], t.kind).to_owned(), t.pos)
             );
         }
 
@@ -337,6 +1208,20 @@ impl Debug for Literal {
     }
 }
 
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |patterns: &[Pattern]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Var(name) => write!(f, "{name}"),
+            Pattern::Literal(literal) => write!(f, "{literal:?}"),
+            Pattern::List(elements) => write!(f, "[{}]", join(elements)),
+            Pattern::Cons(head, tail) => write!(f, "({head} :: {tail})"),
+            Pattern::Tuple(components) => write!(f, "({})", join(components)),
+        }
+    }
+}
+
 impl Debug for Program {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0
@@ -359,16 +1244,20 @@ mod tests {
                 ident: "fib".to_owned(),
                 style: LamStyle::Prefix,
                 params: vec!["n".to_owned()],
+                annotations: vec![None],
+                ret: None,
+                pure: false,
                 operation: Box::from(Expr::If {
                     cond: Box::from(Expr::LamCall {
                         ident: "<=".to_owned(),
                         style: LamStyle::Infix,
                         params: vec![
-                            Expr::Var("n".to_owned()),
-                            Expr::Literal(Literal::Int(1))
-                        ]
+                            Expr::Var("n".to_owned(), (1, 20)),
+                            Expr::Literal(Literal::Int(Int::from(1)))
+                        ],
+                        pos: (1, 22),
                     }),
-                    then: Box::from(Expr::Var("n".to_owned())),
+                    then: Box::from(Expr::Var("n".to_owned(), (1, 29))),
                     alter: Box::from(Expr::LamCall {
                         ident: "+".to_owned(),
                         style: LamStyle::Infix,
@@ -380,10 +1269,12 @@ mod tests {
                                     ident: "-".to_owned(),
                                     style: LamStyle::Infix,
                                     params: vec![
-                                        Expr::Var("n".to_owned()),
-                                        Expr::Literal(Literal::Int(1))
-                                    ]
-                                }]
+                                        Expr::Var("n".to_owned(), (1, 37)),
+                                        Expr::Literal(Literal::Int(Int::from(1)))
+                                    ],
+                                    pos: (1, 39),
+                                }],
+                                pos: (1, 33),
                             },
                             Expr::LamCall {
                                 ident: "fib".to_owned(),
@@ -392,15 +1283,113 @@ mod tests {
                                     ident: "-".to_owned(),
                                     style: LamStyle::Infix,
                                     params: vec![
-                                        Expr::Var("n".to_owned()),
-                                        Expr::Literal(Literal::Int(2))
-                                    ]
-                                }]
+                                        Expr::Var("n".to_owned(), (1, 50)),
+                                        Expr::Literal(Literal::Int(Int::from(2)))
+                                    ],
+                                    pos: (1, 52),
+                                }],
+                                pos: (1, 46),
                             }
                         ],
-                    })
-                })
+                        pos: (1, 44),
+                    }),
+                    pos: (1, 17),
+                }),
+                pos: (1, 5),
             }])
         );
     }
+
+    // The calls of `expr` as nested applications, `(op a b)`
+    fn shape(expr: &Expr) -> String {
+        match expr {
+            Expr::LamCall { ident, params, .. } => {
+                format!("({ident} {})", params.iter().map(shape).collect::<Vec<_>>().join(" "))
+            }
+            Expr::Var(name, _) => name.clone(),
+            Expr::Literal(literal) => format!("{literal:?}"),
+            Expr::Tuple(components) => format!("(, {})", components.iter().map(shape).collect::<Vec<_>>().join(" ")),
+            Expr::Match { scrutinee, arms, .. } => {
+                let arms = arms.iter().map(|(pattern, body)| format!("({pattern} {})", shape(body)));
+                format!("(match {} {})", shape(scrutinee), arms.collect::<Vec<_>>().join(" "))
+            }
+            _ => "...".to_owned(),
+        }
+    }
+
+    #[test]
+    fn unary_operators() {
+        let program = Parser::new(&mut Lexer::new("lam ~ :: x -> not -x * ~x == -1 + y;")).parse().unwrap();
+        let Expr::LamDef { ident, operation, .. } = &program.0[0] else { panic!() };
+        assert_eq!(ident, "~");
+        assert_eq!(shape(operation), "(not (== (* (negate x) (~ x)) (+ -1 y)))");
+
+        // A `-` following a builtin operator negates its right operand, and `=` is never part of an operator
+        let program = Parser::new(&mut Lexer::new("lam main -> f(2)*-1<-1; lam r -> {x=-1, y=~z};")).parse().unwrap();
+        let Expr::LamDef { operation, .. } = &program.0[0] else { panic!() };
+        assert_eq!(shape(operation), "(< (* (f 2) -1) -1)");
+        let Expr::LamDef { operation, .. } = &program.0[1] else { panic!() };
+        let Expr::Record(fields) = &**operation else { panic!() };
+        let fields: Vec<(&str, String)> = fields.iter().map(|(name, value)| (name.as_str(), shape(value))).collect();
+        assert_eq!(fields, vec![("x", "-1".to_string()), ("y", "(~ z)".to_string())]);
+    }
+
+    #[test]
+    fn logical_operators() {
+        let program = Parser::new(&mut Lexer::new("lam main -> not a && b || c < d && e |> f;")).parse().unwrap();
+        let Expr::LamDef { operation, .. } = &program.0[0] else { panic!() };
+        assert_eq!(shape(operation), "(f (|| (&& (not a) b) (&& (< c d) e)))");
+    }
+
+    #[test]
+    fn fixity_declarations() {
+        let source = "
+            lam main -> a <+> b <+> c * d <-> e plus f :: g;
+            infixr 6 <+>;
+            lam plus | :: x, y -> x;";
+        let program = Parser::new(&mut Lexer::new(source)).parse().unwrap();
+        let Expr::LamDef { operation, .. } = &program.0[0] else { panic!() };
+        assert_eq!(shape(operation), "(:: (<+> a (<+> b (* c (plus (<-> d e) f)))) g)");
+
+        let err = Parser::new(&mut Lexer::new("infixr 6 <+>; lam main -> a + b <+> c;")).parse().unwrap_err();
+        assert_eq!(err.1, (1, 33));
+    }
+
+    #[test]
+    fn layout() {
+        let layout = "#layout
+lam both :: xs, ys -> match xs with
+    | [] -> match ys with
+        | [] -> 0
+        | _ -> 1
+    | _ -> (match ys with [] -> 2 | _ -> 3, 4)
+lam main -> both([], [1])";
+        let explicit = "
+lam both :: xs, ys -> match xs with | [] -> (match ys with | [] -> 0 | _ -> 1) | _ -> (match ys with [] -> 2 | _ -> 3, 4);
+lam main -> both([], [1]);";
+        let shapes = |source| {
+            let program = Parser::new(&mut Lexer::new(source)).parse().unwrap();
+            program.0.iter().map(|def| match def {
+                Expr::LamDef { operation, .. } => shape(operation),
+                _ => panic!(),
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(shapes(layout), shapes(explicit));
+        assert_eq!(shapes(layout)[0], "(match xs ([] (match ys ([] 0) (_ 1))) (_ (, (match ys ([] 2) (_ 3)) 4)))");
+
+        // The definitions are only delimited by `;` outside of layout mode
+        let err = Parser::new(&mut Lexer::new(&layout[8..])).parse().unwrap_err();
+        assert_eq!(err.1, (6, 1));
+    }
+
+    #[test]
+    fn pipelines_are_calls() {
+        let program = Parser::new(&mut Lexer::new("lam main -> x |> f(a) |> g >> h << k;")).parse().unwrap();
+        let Expr::LamDef { operation, .. } = &program.0[0] else { panic!() };
+        assert_eq!(shape(operation), "(h (k (g (f a x))))");
+
+        let program = Parser::new(&mut Lexer::new("lam main -> 3 |> math.square |> math.add(1);")).parse().unwrap();
+        let Expr::LamDef { operation, .. } = &program.0[0] else { panic!() };
+        assert_eq!(shape(operation), "(math.add 1 (math.square 3))");
+    }
 }
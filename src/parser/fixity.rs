@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use crate::lexer::tokens::{Kind, Token};
+use crate::lexer::Lexer;
+
+/*
+    The fixities of the binary operators, which drive the precedence climbing of
+    `Parser::operator_expr`. A fixity is an associativity and a precedence from 0 to 9, the
+    operators of higher precedence binding tighter:
+
+        1  infixl   |>
+        2  infixr   ||
+        3  infixr   &&
+        4  infix    == != <= >= < >
+        5  infixr   ::
+        6  infixl   + - .+ .-
+        7  infixl   * / % .* @
+        8  infixr   >> <<
+
+    A module declares the fixities of its own operators with `infixl 6 <+>;`, `infixr` and
+    `infix` (which cannot be chained) anywhere on its toplevel, as the declarations are collected
+    before the module is parsed. The table is built for each module, an operator used by a module
+    without a declaration there being `infixl 9`. As a module is parsed before the modules it
+    uses, the loader requires it to repeat the fixities the modules defining its operators
+    declare. Named functions are used as operators when they are given a fixity or are declared
+    infix, as `lam plus | :: a, b -> a + b;`.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Assoc {
+    Left,
+    Right,
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fixity {
+    pub assoc: Assoc,
+    pub prec: u8,
+}
+
+impl std::fmt::Display for Fixity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keyword = match self.assoc {
+            Assoc::Left => "infixl",
+            Assoc::Right => "infixr",
+            Assoc::None => "infix",
+        };
+        write!(f, "{keyword} {}", self.prec)
+    }
+}
+
+/// The operand of `not` holds the comparisons: `not x == y` is `not (x == y)`
+pub const NOT_PREC: u8 = 4;
+
+const MAX_PREC: u8 = 9;
+
+// The fixity of the operators without a declaration
+const DEFAULT: Fixity = Fixity {
+    assoc: Assoc::Left,
+    prec: MAX_PREC,
+};
+
+const BUILTIN: [(&str, Assoc, u8); 21] = [
+    ("|>", Assoc::Left, 1),
+    ("||", Assoc::Right, 2),
+    ("&&", Assoc::Right, 3),
+    ("==", Assoc::None, 4),
+    ("!=", Assoc::None, 4),
+    ("<=", Assoc::None, 4),
+    (">=", Assoc::None, 4),
+    ("<", Assoc::None, 4),
+    (">", Assoc::None, 4),
+    ("::", Assoc::Right, 5),
+    ("+", Assoc::Left, 6),
+    ("-", Assoc::Left, 6),
+    (".+", Assoc::Left, 6),
+    (".-", Assoc::Left, 6),
+    ("*", Assoc::Left, 7),
+    ("/", Assoc::Left, 7),
+    ("%", Assoc::Left, 7),
+    (".*", Assoc::Left, 7),
+    ("@", Assoc::Left, 7),
+    (">>", Assoc::Right, 8),
+    ("<<", Assoc::Right, 8),
+];
+
+pub struct Fixities(HashMap<String, Fixity>);
+
+impl Fixities {
+    /// The fixities of the builtin operators, overridden by those declared in what remains to be
+    /// read by `lexer`
+    pub fn scan(lexer: &Lexer) -> Self {
+        let mut fixities: HashMap<String, Fixity> = BUILTIN
+            .iter()
+            .map(|&(op, assoc, prec)| (op.to_string(), Fixity { assoc, prec }))
+            .collect();
+
+        let mut tokens = lexer.clone();
+        while let Some(token) = tokens.next() {
+            match token.kind {
+                // infixl 6 <+>, <->
+                Kind::Fixity => {
+                    let Some(fixity) = tokens.next().and_then(|prec| Self::declared(token.value, prec.value)) else {
+                        continue;
+                    };
+                    while let Some(op) = tokens.next().filter(|op| Self::is_operator(op.kind)) {
+                        fixities.insert(op.value.to_string(), fixity);
+                        if tokens.peeked.as_ref().map(|t| t.kind) != Some(Kind::Comma) {
+                            break;
+                        }
+                        tokens.next();
+                    }
+                }
+                // lam plus | :: a, b
+                Kind::Lam => {
+                    let mut name = tokens.next();
+                    if name.as_ref().is_some_and(|name| name.kind == Kind::Pure) {
+                        name = tokens.next();
+                    }
+                    if let Some(name) = name.filter(|name| name.kind == Kind::Ident) {
+                        if tokens.peeked.as_ref().is_some_and(|bar| bar.kind == Kind::Bar) {
+                            fixities.entry(name.value.to_string()).or_insert(DEFAULT);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Fixities(fixities)
+    }
+
+    /// The fixity declared by the keyword `keyword` with the precedence `prec`, if it is valid
+    pub fn declared(keyword: &str, prec: &str) -> Option<Fixity> {
+        let assoc = match keyword {
+            "infixl" => Assoc::Left,
+            "infixr" => Assoc::Right,
+            _ => Assoc::None,
+        };
+        let prec = prec.parse().ok().filter(|prec| *prec <= MAX_PREC)?;
+        Some(Fixity { assoc, prec })
+    }
+
+    /// Whether a token of this kind can be declared an operator
+    pub fn is_operator(kind: Kind) -> bool {
+        matches!(
+            kind,
+            Kind::Operator
+                | Kind::Ident
+                | Kind::DoubleEq
+                | Kind::Neq
+                | Kind::Leq
+                | Kind::Geq
+                | Kind::Lt
+                | Kind::Gt
+                | Kind::DoubleColon
+                | Kind::Plus
+                | Kind::Min
+                | Kind::DotPlus
+                | Kind::DotMin
+                | Kind::Mul
+                | Kind::Div
+                | Kind::Mod
+                | Kind::DotMul
+                | Kind::At
+                | Kind::And
+                | Kind::Or
+                | Kind::Pipe
+                | Kind::Compose
+                | Kind::ComposeBack
+        )
+    }
+
+    /// The fixity of `token` if it is a binary operator
+    pub fn of(&self, token: &Token) -> Option<Fixity> {
+        match token.kind {
+            Kind::Operator => Some(self.0.get(token.value).copied().unwrap_or(DEFAULT)),
+            kind if Self::is_operator(kind) => self.0.get(token.value).copied(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+/*
+    The prelude, the definitions available to every program.
+    The functions which need the runtime, such as the IO ones, are builtins of the typechecker and
+    of the interpreter: print, printf, read_line, length, concat, substring, int_to_str, split, size
+    and to_array. The others, such as the map, filter and fold of lists, are written in Karm in
+    std/prelude.kr, a module of the standard library which every program and every module
+    implicitly uses, as if they began with `use "prelude";`.
+    The loader gives the names exported by the prelude to the modules which do not define them, a
+    definition of the same name hiding the one of the prelude. The prelude is namespaced like any
+    other module however, so that its own calls, such as those of `gcd` to `abs`, keep referring to
+    its definitions.
+*/
+
+/// The module of the standard library used by every other module
+pub const PRELUDE: &str = "prelude";
+
+// The modules of the standard library, by the name they are used with
+const STD_MODULES: [(&str, &str); 1] = [(PRELUDE, include_str!("../std/prelude.kr"))];
+
+/// The text of the module `name` of the standard library, if `name` is one rather than a file
+pub fn source(name: &str) -> Option<&'static str> {
+    STD_MODULES.iter().find(|(std, _)| *std == name).map(|(_, text)| *text)
+}
+
+/// Whether `path` names a module of the standard library rather than a file
+pub fn is_std(path: &str) -> bool {
+    source(path).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::modules::Loader;
+    use crate::parser::{Parser, Program};
+    use crate::typechecker::refinements::RefinementChecker;
+    use crate::typechecker::TypeChecker;
+
+    fn load(source: &str) -> Program {
+        let program = Parser::new(&mut Lexer::new(source)).parse().unwrap();
+        Loader::new(Vec::new()).load(Path::new("main.kr"), source, program).unwrap()
+    }
+
+    #[test]
+    fn prelude_is_checked_and_runs() {
+        let source = r#"
+            lam max :: a, b -> a;
+            lam main -> concat(repeat("ab", 2), int_to_str(max(pow(2, 10), abs(0 - 3))));"#;
+        let program = load(source);
+        let gamma = TypeChecker::new(&program).init().unwrap();
+        assert_eq!(RefinementChecker::new(&program, &gamma).check(), Vec::new());
+
+        // The definition of the program hides the one of the prelude
+        let result = Interpreter::new(&program).run().unwrap();
+        assert_eq!(result.to_string(), r#""abab1024""#);
+    }
+
+    #[test]
+    fn prelude_keeps_its_own_definitions() {
+        // Hiding `abs` does not change the `gcd` of the prelude, and its helper `pow_from` is not visible
+        let source = r#"
+            lam abs :: n: Int : Int -> 42;
+            lam pow_from :: s: Str -> s;
+            lam main -> (gcd(0 - 12, 18), abs(1), pow(2, 10), pow_from("x"));"#;
+        let program = load(source);
+        TypeChecker::new(&program).init().unwrap();
+        assert_eq!(Interpreter::new(&program).run().unwrap().to_string(), r#"(6, 42, 1024, "x")"#);
+    }
+
+    #[test]
+    fn list_combinators() {
+        let source = r#"
+            lam add :: a, b -> a + b;
+            lam main -> fold(add, 0, filter(is_even, map(length, split("a,bb,cccc", ","))));"#;
+        let program = load(source);
+        TypeChecker::new(&program).init().unwrap();
+        assert_eq!(Interpreter::new(&program).run().unwrap().to_string(), "6");
+    }
+}
@@ -1,5 +1,12 @@
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::lexer::Lexer;
+use crate::modules::Loader;
 use crate::parser;
+use crate::resolver::Resolver;
+use crate::typechecker::refinements::RefinementChecker;
+use crate::typechecker::TypeChecker;
 use std::io::{stdin, stdout, Stdout, Write};
+use std::path::Path;
 use termion::cursor::DetectCursorPos;
 use termion::event::Key;
 use termion::input::TermRead;
@@ -42,6 +49,11 @@ pub struct Repl {
     tbc: bool,
     /// false if the repl should exit, true otherwise
     running: bool,
+    /// The source of all the previously accepted commands, so that later commands
+    /// can use their definitions
+    definitions: String,
+    /// The number of toplevel expressions in *definitions*
+    definitions_count: usize,
 }
 
 fn newline(stdout: &mut Stdout) {
@@ -65,6 +77,8 @@ impl Repl {
             was_newline: false,
             tbc: false,
             running: true,
+            definitions: String::new(),
+            definitions_count: 0,
             prompt1,
             prompt2,
             history,
@@ -85,11 +99,7 @@ impl Repl {
                         // The current line ends a command
                         if self.current_line.ends_with(';') {
                             let full_command = self.history[self.first_command_line..].join("\n");
-                            let ast = parser::Parser::new(&full_command)
-                                .parse()
-                                .map(|x| format!("{:#?}", x))
-                                .map_err(|err| format!("{err}"));
-                            self.command_result = Some(ast);
+                            self.command_result = Some(self.check_command(&full_command));
                             self.first_command_line = self.hist_idx;
                             self.tbc = false;
                         }
@@ -100,19 +110,15 @@ impl Repl {
                     self.cursor_idx += 1;
                 }
             }
-            Key::Backspace => {
-                if self.cursor_idx != 0 {
-                    self.cursor_idx -= 1;
-                    self.current_line.remove(self.cursor_idx);
-                }
+            Key::Backspace if self.cursor_idx != 0 => {
+                self.cursor_idx -= 1;
+                self.current_line.remove(self.cursor_idx);
             }
             Key::Left => {
                 self.cursor_idx = self.cursor_idx.checked_sub(1).unwrap_or(self.cursor_idx)
             }
-            Key::Right => {
-                if self.cursor_idx < self.current_line.len() {
-                    self.cursor_idx += 1;
-                }
+            Key::Right if self.cursor_idx < self.current_line.len() => {
+                self.cursor_idx += 1;
             }
             Key::Up => {
                 if let Some(nhi) = self.hist_idx.checked_sub(1) {
@@ -120,25 +126,80 @@ impl Repl {
                     self.current_line = self.history[self.hist_idx].clone();
                 }
             }
-            Key::Down => {
-                if self.hist_idx + 1 < self.history.len() {
-                    self.hist_idx += 1;
-                    self.current_line = self.history[self.hist_idx].clone();
-                }
+            Key::Down if self.hist_idx + 1 < self.history.len() => {
+                self.hist_idx += 1;
+                self.current_line = self.history[self.hist_idx].clone();
             }
             Key::Ctrl('c') | Key::Ctrl('d') => self.running = false,
             _ => (),
         }
     }
+    /// Parses and typechecks `command` along with the previous definitions, returning the
+    /// types (or kinds) of the new definitions followed by its warnings and the reports of its
+    /// typed holes.
+    fn check_command(&mut self, command: &str) -> Result<String, String> {
+        let source = format!("{}{command}\n", self.definitions);
+        let mut lexer = Lexer::new(&source);
+        let ast = parser::Parser::new(&mut lexer)
+            .parse()
+            .map_err(|err| Diagnostic::from(&err).render(&source))?;
+        // The definitions of the modules used, then those of the prelude, follow those typed in
+        // the shell, whose modules are looked up in the working directory
+        let count = ast.0.len();
+        let mut loader = Loader::new(Vec::new());
+        let ast = loader
+            .load(Path::new("<shell>"), &source, ast)
+            .map_err(|err| err.render_in(&loader.source.text, &loader.source.files))?;
+        let (text, files) = (loader.source.text, loader.source.files);
+        // The lines of the command, before those of the modules
+        let command_lines = self.definitions.lines().count() + 1..files.get(1).map_or(usize::MAX, |(_, first)| *first);
+
+        let (errors, warnings): (Vec<_>, Vec<_>) = Resolver::new(&ast, count)
+            .check()
+            .into_iter()
+            .partition(|diagnostic| diagnostic.severity == Severity::Error);
+        if !errors.is_empty() {
+            return Err(errors.iter().map(|err| err.render_in(&text, &files)).collect::<Vec<_>>().join("\n"));
+        }
+
+        let mut checker = TypeChecker::new(&ast);
+        let gamma = checker
+            .init()
+            .map_err(|err| checker.explain(&err).render_in(&text, &files))?;
+        let errors = RefinementChecker::new(&ast, &gamma).check();
+        if !errors.is_empty() {
+            return Err(errors.iter().map(|err| err.render_in(&text, &files)).collect::<Vec<_>>().join("\n"));
+        }
+
+        let mut lines = Vec::new();
+        for expr in &ast.0[self.definitions_count..count] {
+            match expr {
+                parser::Expr::LamDef { ident, .. } => lines.push(format!("{ident} : {}", gamma[ident])),
+                parser::Expr::TypeDecl { ident, .. } => {
+                    if let Some(kind) = checker.kind_of(ident) {
+                        lines.push(format!("type {ident} : {kind}"));
+                    }
+                }
+                _ => {}
+            }
+        }
+        for report in warnings.iter().chain(&checker.hole_reports()) {
+            if report.pos.is_some_and(|(line, _)| command_lines.contains(&line)) {
+                lines.push(report.render_in(&text, &files));
+            }
+        }
+
+        self.definitions = source;
+        self.definitions_count = count;
+        Ok(lines.join("\n"))
+    }
+
     fn show(&self, stdout: &mut Stdout) {
         if self.was_newline {
             newline(stdout);
         }
         if let Some(result) = &self.command_result {
-            let result = result
-                .as_ref()
-                .map(|x| x.clone())
-                .unwrap_or_else(|x| format!("Error: {x}"));
+            let result = result.clone().unwrap_or_else(|x| x);
             for c in result.chars() {
                 if c == '\n' {
                     newline(stdout);
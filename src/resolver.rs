@@ -0,0 +1,275 @@
+use std::collections::HashSet;
+
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::interpreter::BUILTINS;
+use crate::modules::is_name;
+use crate::parser::{Expr, Program, TypeExpr};
+
+/*
+    Name resolution, run once the modules and the prelude are loaded, before the typechecker.
+    Every variable and every call is resolved to its binding: a parameter of the lambda it is in,
+    the binder of a refinement type, a toplevel definition (the definitions of the modules being
+    named `math.square`) or a builtin. A name bound by none of them is reported along with the
+    names in scope closest to it, so that a typo is caught before the program runs. Parameters
+    hiding a definition and bindings which are never used are only worth a warning, given for
+    the program itself and not for the modules and the prelude it uses.
+*/
+
+// The names most likely meant instead of an unbound one differ by at most a third of its letters
+const SUGGESTION_DISTANCE: usize = 3;
+
+// What a name refers to
+enum Binding<'a> {
+    // The index of a parameter or of a refinement binder in `locals`
+    Local(usize),
+    Definition(&'a str),
+    Builtin,
+}
+
+pub struct Resolver<'a> {
+    ast: &'a Program,
+    // The number of toplevel expressions of the program itself, those following it coming from
+    // its modules or from the prelude
+    own: usize,
+    definitions: HashSet<&'a str>,
+    // The parameters, the refinement binders and the pattern variables in scope, innermost last, and whether they are used
+    locals: Vec<(&'a str, bool)>,
+    used: HashSet<&'a str>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Resolver<'a> {
+    /// Prepares the resolution of `ast`, whose `own` first expressions are those of the program,
+    /// the only ones warned about.
+    pub fn new(ast: &'a Program, own: usize) -> Self {
+        let definitions = ast
+            .0
+            .iter()
+            .filter_map(|expr| match expr {
+                Expr::LamDef { ident, .. } => Some(ident.as_str()),
+                _ => None,
+            })
+            .collect();
+        Resolver {
+            ast,
+            own,
+            definitions,
+            locals: Vec::new(),
+            used: HashSet::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Resolves every name of the program, returning an error for each unbound one and the
+    /// warnings about shadowing and unused bindings, in the order of the program.
+    pub fn check(mut self) -> Vec<Diagnostic> {
+        for (index, expr) in self.ast.0.iter().enumerate() {
+            let own = index < self.own;
+            match expr {
+                Expr::LamDef {
+                    ident,
+                    params,
+                    annotations,
+                    ret,
+                    operation,
+                    pos,
+                    ..
+                } => {
+                    for (n, param) in params.iter().enumerate() {
+                        let hidden = if params[..n].contains(param) {
+                            Some("another parameter")
+                        } else if self.definitions.contains(param.as_str()) {
+                            Some("a definition")
+                        } else if BUILTINS.contains(&param.as_str()) {
+                            Some("a builtin")
+                        } else {
+                            None
+                        };
+                        if let Some(hidden) = hidden.filter(|_| own) {
+                            self.warn(format!("The parameter `{param}` of `{ident}` hides {hidden} named `{param}`."), *pos);
+                        }
+                    }
+
+                    self.locals.extend(params.iter().map(|param| (param.as_str(), false)));
+                    for annotation in annotations.iter().flatten().chain(ret) {
+                        self.ty(annotation);
+                    }
+                    self.expr(operation);
+                    for (param, used) in self.locals.split_off(0) {
+                        if own && !used && !param.starts_with('_') {
+                            self.warn(format!("The parameter `{param}` of `{ident}` is never used."), *pos);
+                        }
+                    }
+                }
+                Expr::TypeDecl { body, .. } => self.ty(body),
+                _ => {}
+            }
+        }
+
+        // A program without `main` may be a library, whose definitions are used elsewhere
+        let own = &self.ast.0[..self.own.min(self.ast.0.len())];
+        if own.iter().any(|expr| matches!(expr, Expr::LamDef { ident, .. } if ident == "main")) {
+            for expr in own {
+                if let Expr::LamDef { ident, pos, .. } = expr {
+                    if ident != "main" && !ident.starts_with('_') && !self.used.contains(ident.as_str()) {
+                        self.warn(format!("`{ident}` is defined but never used."), *pos);
+                    }
+                }
+            }
+        }
+        self.diagnostics
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Var(name, pos) | Expr::LamCall { ident: name, pos, .. } => self.resolve(name, *pos),
+            Expr::Pi { binder, domain, codomain } => {
+                self.expr(domain);
+                self.bound(binder, |resolver| resolver.expr(codomain));
+                return;
+            }
+            Expr::Sigma { binder, fst, snd } => {
+                self.expr(fst);
+                self.bound(binder, |resolver| resolver.expr(snd));
+                return;
+            }
+            // Patterns name the parts they take apart, whether or not they are used
+            Expr::Match { scrutinee, arms, .. } => {
+                self.expr(scrutinee);
+                for (pattern, body) in arms {
+                    let depth = self.locals.len();
+                    self.locals.extend(pattern.vars().into_iter().map(|var| (var.as_str(), true)));
+                    self.expr(body);
+                    self.locals.truncate(depth);
+                }
+                return;
+            }
+            _ => {}
+        }
+        for sub in expr.sub_exprs() {
+            self.expr(sub);
+        }
+    }
+
+    fn ty(&mut self, ty: &'a TypeExpr) {
+        match ty {
+            TypeExpr::Named { args, .. } => args.iter().for_each(|arg| self.ty(arg)),
+            TypeExpr::Record { fields, .. } => fields.iter().for_each(|(_, field)| self.ty(field)),
+            TypeExpr::Tuple(components) => components.iter().for_each(|component| self.ty(component)),
+            TypeExpr::Joined(from, to) => {
+                self.ty(from);
+                self.ty(to);
+            }
+            TypeExpr::Refined { var, base, pred } => {
+                self.ty(base);
+                self.bound(var, |resolver| resolver.expr(pred));
+            }
+            TypeExpr::Term(term) => self.expr(term),
+            TypeExpr::Dynamic => {}
+        }
+    }
+
+    // Runs `scope` with `binder` in scope, binders of types not being reported when unused
+    fn bound(&mut self, binder: &'a str, scope: impl FnOnce(&mut Self)) {
+        self.locals.push((binder, true));
+        scope(self);
+        self.locals.pop();
+    }
+
+    fn binding(&self, name: &str) -> Option<Binding<'a>> {
+        if let Some(index) = self.locals.iter().rposition(|(local, _)| *local == name) {
+            return Some(Binding::Local(index));
+        }
+        if let Some(definition) = self.definitions.get(name) {
+            return Some(Binding::Definition(definition));
+        }
+        BUILTINS.contains(&name).then_some(Binding::Builtin)
+    }
+
+    fn resolve(&mut self, name: &str, pos: (usize, usize)) {
+        match self.binding(name) {
+            Some(Binding::Local(index)) => self.locals[index].1 = true,
+            Some(Binding::Definition(definition)) => {
+                self.used.insert(definition);
+            }
+            Some(Binding::Builtin) => {}
+            None => {
+                let error = Diagnostic::new(Severity::Error, format!("`{name}` is not defined."), Some(pos));
+                let notes = self.suggestion(name).map(|name| format!("did you mean `{name}`?"));
+                self.diagnostics.push(error.with_notes(notes.into_iter().collect()));
+            }
+        }
+    }
+
+    // The name in scope closest to the unbound `name`, if it is close enough to be a typo. An
+    // identifier is only mistaken for an identifier, and an operator for an operator.
+    fn suggestion(&self, name: &str) -> Option<&'a str> {
+        let candidates = self
+            .locals
+            .iter()
+            .map(|(local, _)| *local)
+            .chain(self.definitions.iter().copied())
+            .chain(BUILTINS)
+            .filter(|candidate| is_name(candidate) == is_name(name));
+        let limit = (name.chars().count() / SUGGESTION_DISTANCE).max(1);
+        candidates
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= limit)
+            .min()
+            .map(|(_, candidate)| candidate)
+    }
+
+    fn warn(&mut self, message: String, pos: (usize, usize)) {
+        self.diagnostics.push(Diagnostic::new(Severity::Warning, message, Some(pos)));
+    }
+}
+
+// The Levenshtein distance, the number of characters to insert, remove or replace to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(ca != *cb);
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn unbound_names_and_warnings() {
+        let source = r#"
+            lam fib :: n -> if n <= 1 ? n : fibb(n - 1) + fib(n - 2);
+            lam length :: print, unused -> print;
+            lam helper :: _n -> 0;
+            lam main -> fib(lenght) + x;"#;
+        let program = Parser::new(&mut Lexer::new(source)).parse().unwrap();
+        let diagnostics = Resolver::new(&program, program.0.len()).check();
+        let messages: Vec<(Severity, &str, Vec<String>)> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.message.as_str(), d.notes.clone()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (Severity::Error, "`fibb` is not defined.", vec!["did you mean `fib`?".to_string()]),
+                (Severity::Warning, "The parameter `print` of `length` hides a builtin named `print`.", vec![]),
+                (Severity::Warning, "The parameter `unused` of `length` is never used.", vec![]),
+                (Severity::Error, "`lenght` is not defined.", vec!["did you mean `length`?".to_string()]),
+                (Severity::Error, "`x` is not defined.", vec![]),
+                (Severity::Warning, "`length` is defined but never used.", vec![]),
+                (Severity::Warning, "`helper` is defined but never used.", vec![]),
+            ]
+        );
+        assert_eq!(diagnostics[0].pos, Some((2, 45)));
+    }
+}
@@ -1,153 +1,1969 @@
-use core::hash::Hash;
-use std::{process::exit, collections::BTreeSet};
-use crate::errors::TypeError;
-
-use crate::{
-    lexer::tokens::Kind,
-    parser::{Expr, Literal, Program},
-};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Display;
 
-pub enum Sig {
-    // Used to represent function, A type Joined { i: Int, o: String } is equivalent to Int -> String
-    Joined {
-        i: Box<Sig>,
-        o: Box<Sig>
-    },
-    // Classic unique type
-    Unit(Type)
-}
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::errors::TypeError;
+use crate::parser::{is_type_var, Expr, Literal, Pattern, Program, TypeExpr};
 
-struct TypeScheme(Gamma, BTreeSet<Type>);
+pub mod dependent;
+pub mod refinements;
 
-type Gamma = BTreeSet<Assumption>;
+/// The types every program can name without declaring them
+pub const BUILTIN_TYPES: [&str; 6] = ["Int", "Str", "Bool", "Matrix", "List", "Array"];
 
 #[derive(PartialEq, Hash, Eq, PartialOrd, Ord, Clone, Debug)]
 pub enum Type {
     Int,
     Str,
     Bool,
+    // The dynamic type `?`, consistent with every type. Its values are checked at runtime by casts.
     Whatever,
     Invalid,
+    // Unknown type, to be resolved by unification
+    Var(usize),
+    // Used to represent function, A type Joined { i: Int, o: Str } is equivalent to Int -> Str.
+    // Functions with several parameters are curried: `lam add :: a, b -> ...` is Int -> Int -> Int.
+    // `eff` is the row of the effects performed by the call, such as IO, closed when it has no
+    // variable at its end. The effects of a function are opened when it is used, so that it can be
+    // called from any code allowed to perform at least these effects.
+    Joined {
+        i: Box<Type>,
+        o: Box<Type>,
+        eff: Box<Type>,
+    },
+    // A record, whose fields are described by a row
+    Record(Box<Type>),
+    // The row without any field
+    RowEmpty,
+    // The row with the field `label` of type `field` followed by the row `rest`.
+    // A row ending with a type variable is open: it can hold any other field.
+    RowExtend {
+        label: String,
+        field: Box<Type>,
+        rest: Box<Type>,
+    },
+    // The collections, of a type of elements: `List Int`, `Array Str`, and `Matrix` whose
+    // elements are integers
+    List(Box<Type>),
+    Array(Box<Type>),
+    Matrix,
+    // The type of the tuples of at least two components, `(Int, Str)`
+    Tuple(Vec<Type>),
 }
 
-#[derive(Eq, Ord, PartialEq, PartialOrd, Clone, Debug)]
-struct Assumption {
-    pub name: String,
-    pub hypothesis: BTreeSet<Type>
+/// The kind of a type: `*` for the types of values, `* -> *` for the types taking a type of
+/// kind `*` to give one, as `Pair Int` in `type Pair a b = { fst: a, snd: b }`, and so on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Kind {
+    Star,
+    // The kind of the rows ending records, as `r` in `{ name: Str | r }`
+    Row,
+    Arrow(Box<Kind>, Box<Kind>),
+    // Unknown kind, to be resolved by unification
+    Var(usize),
 }
 
-impl Assumption {
-    
+// The state of the kind inference, run on the type declarations and annotations before
+// the type inference.
+struct Kinds<'a> {
+    // The type declarations, by name
+    decls: HashMap<&'a str, &'a Expr>,
+    subst: HashMap<usize, Kind>,
+    next_var: usize,
+    // The kinds of the declarations inferred so far
+    declared: HashMap<String, Kind>,
+    // The declarations being inferred, to reject the ones defined in terms of themselves
+    in_progress: Vec<&'a str>,
 }
 
-pub struct TypeChecker {
-    ast: Program,
+// A kind error, at the position of the faulty type when it has one
+type KindError = (Option<(usize, usize)>, String);
+
+/// A type generalized over some of its type variables, as in `forall 'a. 'a -> 'a`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeScheme(pub BTreeSet<usize>, pub Type);
+
+/// The typing environment, mapping every visible name to its type scheme.
+pub type Gamma = BTreeMap<String, TypeScheme>;
+
+/// The structure holding the state of the Hindley-Milner inference
+pub struct TypeChecker<'a> {
+    /// The program being checked
+    ast: &'a Program,
+    /// The resolved type variables, a variable absent from it is still unknown
+    subst: HashMap<usize, Type>,
+    /// The next unused type variable
+    next_var: usize,
+    /// The parameters in scope with their types, innermost last
+    scope: Vec<(String, Type)>,
+    /// The typed holes met so far
+    holes: Vec<Hole>,
+    /// Whether the unannotated parameters are dynamically typed (see `TypeChecker::gradual`)
+    gradual: bool,
+    /// The calls met so far, whose arguments may need a cast
+    calls: Vec<Call>,
+    /// The other values met so far which may need a cast, with their type and the expected one
+    expected: Vec<(Site, Type, Type)>,
+    /// The effects performed by the body being checked, as a row
+    effect: Type,
+    /// The constraint that resolved each type variable of `subst`
+    origins: HashMap<usize, Reason>,
+    /// What the type variables stand for in the program, such as a parameter
+    descriptions: HashMap<usize, String>,
+    /// The types that could not be unified, during a unification
+    conflict: Option<(Type, Type)>,
+    /// The explanation of the unification that failed
+    failure: Option<Failure>,
+    /// The declared types, with their parameters and body
+    aliases: HashMap<&'a str, (&'a [String], &'a TypeExpr)>,
+    /// The kinds of the declared types
+    kinds: HashMap<String, Kind>,
 }
 
-impl TypeChecker {
-    pub fn new(ast: Program) -> Self {
-        TypeChecker { ast }
+// Why two types must be equal, pointing at the expression requiring it when there is one
+#[derive(Clone, Debug, PartialEq)]
+struct Reason {
+    pos: Option<(usize, usize)>,
+    what: String,
+}
+
+// A failed unification, along with the resolutions of type variables that led to it
+struct Failure {
+    reason: Reason,
+    trace: Vec<(Option<(usize, usize)>, String)>,
+}
+
+// Where a value of type `?` may meet a static type, and be cast to it
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Site {
+    // The argument `n` of the call at `pos`
+    Arg((usize, usize), usize),
+    // The condition of the if at `pos`
+    Cond((usize, usize)),
+    // The body of the lambda defined at `pos`, whose return type is annotated
+    Body((usize, usize)),
+}
+
+// A call along with the types of its callee and arguments, as inferred
+struct Call {
+    pos: (usize, usize),
+    callee: Type,
+    args: Vec<Type>,
+}
+
+// A typed hole along with what is known at its location
+struct Hole {
+    name: Option<String>,
+    pos: (usize, usize),
+    expected: Type,
+    scope: Vec<(String, Type)>,
+}
+
+impl Type {
+    // A pure function
+    fn joined(i: Type, o: Type) -> Type {
+        Type::joined_with(i, o, Type::RowEmpty)
+    }
+
+    fn joined_with(i: Type, o: Type, eff: Type) -> Type {
+        Type::Joined {
+            i: Box::new(i),
+            o: Box::new(o),
+            eff: Box::new(eff),
+        }
+    }
+
+    fn row_extend(label: &str, field: Type, rest: Type) -> Type {
+        Type::RowExtend {
+            label: label.to_string(),
+            field: Box::new(field),
+            rest: Box::new(rest),
+        }
+    }
+
+    fn children(&self) -> Vec<&Type> {
+        match self {
+            Type::Joined { i, o, eff } => vec![i, o, eff],
+            Type::Record(row) => vec![row],
+            Type::RowExtend { field, rest, .. } => vec![field, rest],
+            Type::List(element) | Type::Array(element) => vec![element],
+            Type::Tuple(components) => components.iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Rebuilds the type, replacing each variable with the result of `f`.
+    fn map_vars(&self, f: &mut impl FnMut(usize) -> Type) -> Type {
+        match self {
+            Type::Var(v) => f(*v),
+            Type::Joined { i, o, eff } => Type::joined_with(i.map_vars(f), o.map_vars(f), eff.map_vars(f)),
+            Type::Record(row) => Type::Record(Box::new(row.map_vars(f))),
+            Type::RowExtend { label, field, rest } => Type::row_extend(label, field.map_vars(f), rest.map_vars(f)),
+            Type::List(element) => Type::List(Box::new(element.map_vars(f))),
+            Type::Array(element) => Type::Array(Box::new(element.map_vars(f))),
+            Type::Tuple(components) => Type::Tuple(components.iter().map(|c| c.map_vars(f)).collect()),
+            _ => self.clone(),
+        }
+    }
+
+    // The variables in order of appearance, except those of the effects which are not shown
+    fn vars_in_order(&self, acc: &mut Vec<usize>) {
+        match self {
+            Type::Var(v) if !acc.contains(v) => acc.push(*v),
+            Type::Joined { i, o, .. } => {
+                i.vars_in_order(acc);
+                o.vars_in_order(acc);
+            }
+            _ => self.children().iter().for_each(|t| t.vars_in_order(acc)),
+        }
+    }
+
+    fn free_vars(&self, acc: &mut BTreeSet<usize>) {
+        match self {
+            Type::Var(v) => {
+                acc.insert(*v);
+            }
+            _ => self.children().iter().for_each(|t| t.free_vars(acc)),
+        }
+    }
+
+    // Whether some of the values of this type are only checked at runtime
+    fn is_dynamic(&self) -> bool {
+        *self == Type::Whatever || self.children().iter().any(|t| t.is_dynamic())
+    }
+
+    // Names the unknown types 'a, 'b, ... in order of appearance
+    fn name_vars<'t>(types: impl IntoIterator<Item = &'t Type>) -> HashMap<usize, String> {
+        let mut names = HashMap::new();
+        for ty in types {
+            let mut vars = Vec::new();
+            ty.vars_in_order(&mut vars);
+            for v in vars {
+                let next = format!("'{}", (b'a' + (names.len() % 26) as u8) as char);
+                names.entry(v).or_insert(next);
+            }
+        }
+        names
+    }
+
+    // The labels of an effect row, such as IO
+    fn effect_labels(&self) -> Vec<&str> {
+        match self {
+            Type::RowExtend { label, rest, .. } => {
+                let mut labels = rest.effect_labels();
+                labels.insert(0, label);
+                labels
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl TypeScheme {
+    fn mono(ty: Type) -> Self {
+        TypeScheme(BTreeSet::new(), ty)
+    }
+}
+
+impl<'a> TypeChecker<'a> {
+    pub fn new(ast: &'a Program) -> Self {
+        TypeChecker {
+            ast,
+            subst: HashMap::new(),
+            next_var: 0,
+            scope: Vec::new(),
+            holes: Vec::new(),
+            gradual: false,
+            calls: Vec::new(),
+            expected: Vec::new(),
+            effect: Type::RowEmpty,
+            origins: HashMap::new(),
+            descriptions: HashMap::new(),
+            conflict: None,
+            failure: None,
+            aliases: HashMap::new(),
+            kinds: HashMap::new(),
+        }
+    }
+
+    /// Gives the type `?` to the unannotated parameters instead of inferring their types,
+    /// so that untyped code is checked at runtime (see `TypeChecker::insert_casts`).
+    pub fn gradual(mut self) -> Self {
+        self.gradual = true;
+        self
+    }
+
+    /// Infers the type of every toplevel definition and returns them.
+    /// Definitions are checked by groups of mutually recursive lambdas, each group being
+    /// checked after all the groups it depends on, so the source order does not matter.
+    pub fn init(&mut self) -> Result<Gamma, TypeError> {
+        self.check_kinds()?;
+        let defs = self.toplevel_defs()?;
+        let mut gamma = self.builtins();
+
+        for group in Self::recursive_groups(&defs) {
+            self.type_check_group(&group, &defs, &mut gamma)
+                .map_err(|TypeError(msg)| TypeError(format!("In {}: {msg}", Self::describe_group(&group, &defs))))?;
+        }
+
+        Ok(defs
+            .iter()
+            .map(|(id, _)| (id.to_string(), gamma[*id].clone()))
+            .collect())
+    }
+
+    /// The kind of the declared type `name`, once `init` has checked the declarations
+    pub fn kind_of(&self, name: &str) -> Option<&Kind> {
+        self.kinds.get(name)
+    }
+
+    // Infers the kinds of the type declarations and checks that every annotation is a type of
+    // kind `*`, so that neither `Pair Int Int Int` nor a bare `Pair` reach the type inference.
+    fn check_kinds(&mut self) -> Result<(), TypeError> {
+        let mut kinds = Kinds {
+            decls: HashMap::new(),
+            subst: HashMap::new(),
+            next_var: 0,
+            declared: HashMap::new(),
+            in_progress: Vec::new(),
+        };
+        for expr in &self.ast.0 {
+            if let Expr::TypeDecl { ident, params, body, pos } = expr {
+                if kinds.decls.contains_key(ident.as_str()) || matches!(ident.as_str(), "Int" | "Str" | "Bool") {
+                    return Err(self.kind_error((Some(*pos), format!("The type `{ident}` is defined more than once."))));
+                }
+                kinds.decls.insert(ident, expr);
+                self.aliases.insert(ident, (params, body));
+            }
+        }
+
+        for expr in &self.ast.0 {
+            let result = match expr {
+                Expr::TypeDecl { ident, .. } => kinds.decl_kind(ident).map(|_| ()),
+                Expr::LamDef { ident, annotations, ret, .. } => {
+                    // The type variables are shared by the annotations of a definition
+                    let mut vars = HashMap::new();
+                    annotations
+                        .iter()
+                        .chain(std::iter::once(ret))
+                        .flatten()
+                        .try_for_each(|annotation| kinds.expect_star(annotation, &mut vars, true))
+                        .map_err(|(pos, msg)| (pos, format!("In `{ident}`: {msg}")))
+                }
+                _ => Ok(()),
+            };
+            result.map_err(|err| self.kind_error(err))?;
+        }
+        self.kinds = kinds.declared;
+        Ok(())
+    }
+
+    // Kind errors are explained by their position only
+    fn kind_error(&mut self, (pos, msg): KindError) -> TypeError {
+        self.failure = Some(Failure {
+            reason: Self::reason(pos, "the kinds of the types".to_string()),
+            trace: Vec::new(),
+        });
+        TypeError(msg)
+    }
+
+    /// Reports the expected type of every typed hole and the variables in scope at its location.
+    /// Meant to be called after `init`, once the types are as resolved as they can be.
+    pub fn hole_reports(&self) -> Vec<Diagnostic> {
+        self.holes
+            .iter()
+            .map(|hole| {
+                let expected = self.resolve(&hole.expected);
+                let scope: Vec<Type> = hole.scope.iter().map(|(_, ty)| self.resolve(ty)).collect();
+
+                let names = Type::name_vars(std::iter::once(&expected).chain(&scope));
+
+                let hole_name = match &hole.name {
+                    Some(name) => format!("`?{name}`"),
+                    None => "`_`".to_string(),
+                };
+                let mut notes: Vec<String> = hole
+                    .scope
+                    .iter()
+                    .zip(&scope)
+                    .map(|((id, _), ty)| format!("{id} : {}", ty.pretty(&names)))
+                    .collect();
+                if notes.is_empty() {
+                    notes.push("nothing is in scope".to_string());
+                }
+
+                Diagnostic::new(
+                    Severity::Info,
+                    format!("hole {hole_name} has type {}", expected.pretty(&names)),
+                    Some(hole.pos),
+                )
+                .with_notes(notes)
+            })
+            .collect()
+    }
+
+    /// Renders `err`, returned by `init`, pointing at the expression whose constraint failed along
+    /// with the constraints that gave their types to the variables involved.
+    pub fn explain(&self, err: &TypeError) -> Diagnostic {
+        let diagnostic = Diagnostic::from(err);
+        let Some(failure) = &self.failure else {
+            return diagnostic;
+        };
+
+        let mut labels = Vec::new();
+        let mut notes = Vec::new();
+        if failure.reason.pos.is_none() {
+            notes.push(format!("required by {}", failure.reason.what));
+        }
+        for (pos, message) in &failure.trace {
+            match pos {
+                Some(pos) => labels.push((*pos, message.clone())),
+                None => notes.push(message.clone()),
+            }
+        }
+        Diagnostic::new(diagnostic.severity, diagnostic.message, failure.reason.pos)
+            .with_labels(labels)
+            .with_notes(notes)
+    }
+
+    /// Returns the program with casts wherever a value of a dynamic type, such as a `?`
+    /// parameter, is used where a static type is expected: the arguments of calls, the
+    /// conditions of ifs and the bodies of the lambdas with an annotated return type.
+    /// Meant to be called after `init`, once the types are as resolved as they can be.
+    pub fn insert_casts(&self) -> Program {
+        let mut casts: HashMap<Site, TypeExpr> = HashMap::new();
+        let mut cast = |site: Site, from: &Type, to: &Type, pos: (usize, usize)| {
+            let (from, to) = (self.resolve(from), self.resolve(to));
+            // Passing a value to a `?` cannot fail, as every value is a `?`
+            if from.is_dynamic() && from != to && to != Type::Whatever {
+                casts.insert(site, to.to_type_expr(pos));
+            }
+        };
+        for call in &self.calls {
+            let mut callee = self.resolve(&call.callee);
+            for (n, arg) in call.args.iter().enumerate() {
+                let Type::Joined { i, o, .. } = callee else {
+                    break;
+                };
+                cast(Site::Arg(call.pos, n), arg, &i, call.pos);
+                callee = *o;
+            }
+        }
+        for (site, from, to) in &self.expected {
+            let (Site::Arg(pos, _) | Site::Cond(pos) | Site::Body(pos)) = *site;
+            cast(*site, from, to, pos);
+        }
+
+        let mut program = self.ast.clone();
+        for expr in &mut program.0 {
+            Self::wrap_casts(expr, &casts);
+        }
+        program
     }
 
-    pub fn init(&self) {
+    fn wrap_casts(expr: &mut Expr, casts: &HashMap<Site, TypeExpr>) {
+        for sub in expr.sub_exprs_mut() {
+            Self::wrap_casts(sub, casts);
+        }
+        let wrap = |expr: &mut Expr, site: Site, pos: (usize, usize)| {
+            if let Some(target) = casts.get(&site) {
+                let inner = std::mem::replace(expr, Expr::Record(Vec::new()));
+                *expr = Expr::Cast {
+                    expr: Box::new(inner),
+                    target: target.clone(),
+                    pos,
+                };
+            }
+        };
+        match expr {
+            Expr::LamCall { params, pos, .. } => {
+                for (n, param) in params.iter_mut().enumerate() {
+                    wrap(param, Site::Arg(*pos, n), *pos);
+                }
+            }
+            Expr::If { cond, pos, .. } => wrap(cond, Site::Cond(*pos), *pos),
+            Expr::LamDef { operation, pos, .. } => wrap(operation, Site::Body(*pos), *pos),
+            _ => {}
+        }
+    }
+
+    // The types of the operators produced by the parser as infix `LamCall`s, and of the native
+    // functions of the prelude (see `prelude`).
+    fn builtins(&mut self) -> Gamma {
+        let int_op = |o: Type| TypeScheme::mono(Type::joined(Type::Int, Type::joined(Type::Int, o)));
+        let Type::Var(a) = self.fresh() else { unreachable!() };
+        let eq_op = TypeScheme(
+            BTreeSet::from([a]),
+            Type::joined(Type::Var(a), Type::joined(Type::Var(a), Type::Bool)),
+        );
+
+        let mut gamma = Gamma::new();
+        for op in ["+", "-", "*", "/", "%"] {
+            gamma.insert(op.to_string(), int_op(Type::Int));
+        }
+        let unit = Type::Record(Box::new(Type::RowEmpty));
+        let io = || Type::row_extend("IO", Type::RowEmpty, Type::RowEmpty);
+        gamma.insert("print".to_string(), TypeScheme::mono(Type::joined_with(Type::Str, unit.clone(), io())));
+        // The type of a call to printf depends on its format, see `printf_type`
+        gamma.insert("printf".to_string(), TypeScheme::mono(Self::printf_type(&[])));
+        // Effects are performed by calls, so read_line takes the unit record
+        gamma.insert("read_line".to_string(), TypeScheme::mono(Type::joined_with(unit, Type::Str, io())));
+        gamma.insert("length".to_string(), TypeScheme::mono(Type::joined(Type::Str, Type::Int)));
+        gamma.insert("int_to_str".to_string(), TypeScheme::mono(Type::joined(Type::Int, Type::Str)));
+        gamma.insert("negate".to_string(), TypeScheme::mono(Type::joined(Type::Int, Type::Int)));
+        gamma.insert("not".to_string(), TypeScheme::mono(Type::joined(Type::Bool, Type::Bool)));
+        for op in ["&&", "||"] {
+            gamma.insert(op.to_string(), TypeScheme::mono(Type::joined(Type::Bool, Type::joined(Type::Bool, Type::Bool))));
+        }
+        gamma.insert(
+            "concat".to_string(),
+            TypeScheme::mono(Type::joined(Type::Str, Type::joined(Type::Str, Type::Str))),
+        );
+        gamma.insert(
+            "substring".to_string(),
+            TypeScheme::mono(Type::joined(Type::Str, Type::joined(Type::Int, Type::joined(Type::Int, Type::Str)))),
+        );
+        for op in ["<=", ">=", "<", ">"] {
+            gamma.insert(op.to_string(), int_op(Type::Bool));
+        }
+        for op in ["==", "!="] {
+            gamma.insert(op.to_string(), eq_op.clone());
+        }
+
+        // f >> g and g << f: (a -> b ! e) -> (b -> c ! e) -> a -> c ! e
+        let (Type::Var(b), Type::Var(c), Type::Var(e)) = (self.fresh(), self.fresh(), self.fresh()) else { unreachable!() };
+        let function = |i: usize, o: usize| Type::joined_with(Type::Var(i), Type::Var(o), Type::Var(e));
+        let composition = |first: Type, second: Type| Type::joined(first, Type::joined(second, function(a, c)));
+        let vars = BTreeSet::from([a, b, c, e]);
+        gamma.insert(">>".to_string(), TypeScheme(vars.clone(), composition(function(a, b), function(b, c))));
+        gamma.insert("<<".to_string(), TypeScheme(vars, composition(function(b, c), function(a, b))));
+
+        // The collections
+        let poly = |ty: Type| TypeScheme(BTreeSet::from([a]), ty);
+        let list = || Type::List(Box::new(Type::Var(a)));
+        gamma.insert("::".to_string(), poly(Type::joined(Type::Var(a), Type::joined(list(), list()))));
+        gamma.insert("size".to_string(), poly(Type::joined(Type::Array(Box::new(Type::Var(a))), Type::Int)));
+        gamma.insert("to_array".to_string(), poly(Type::joined(list(), Type::Array(Box::new(Type::Var(a))))));
+        gamma.insert(
+            "split".to_string(),
+            TypeScheme::mono(Type::joined(Type::Str, Type::joined(Type::Str, Type::List(Box::new(Type::Str))))),
+        );
+        for op in [".+", ".-", ".*", "@"] {
+            gamma.insert(op.to_string(), TypeScheme::mono(Type::joined(Type::Matrix, Type::joined(Type::Matrix, Type::Matrix))));
+        }
+        gamma
+    }
+
+    // Collects the toplevel lambdas in source order, rejecting duplicated names at their second
+    // definition.
+    fn toplevel_defs(&mut self) -> Result<Vec<(&'a str, &'a Expr)>, TypeError> {
+        let mut defs: Vec<(&str, &Expr)> = Vec::new();
         for expr in &self.ast.0 {
-            match self.type_check(expr) {
-                Ok(_) => continue,
-                Err(e) => {
-                    println!("{:?}", e);
-                    exit(1);
+            if let Expr::LamDef { ident, pos, .. } = expr {
+                if defs.iter().any(|(id, _)| id == ident) {
+                    self.failure = Some(Failure {
+                        reason: Self::reason(Some(*pos), format!("the definitions of `{ident}`")),
+                        trace: Vec::new(),
+                    });
+                    return Err(TypeError(format!("`{ident}` is defined more than once.")));
+                }
+                defs.push((ident, expr));
+            }
+        }
+        Ok(defs)
+    }
+
+    /// Splits the toplevel definitions into strongly connected components of the call graph
+    /// (Tarjan's algorithm). A group is returned after every group it calls into.
+    fn recursive_groups(defs: &[(&str, &Expr)]) -> Vec<Vec<usize>> {
+        let index_of: HashMap<&str, usize> = defs.iter().enumerate().map(|(i, (id, _))| (*id, i)).collect();
+
+        let edges: Vec<Vec<usize>> = defs
+            .iter()
+            .map(|(_, def)| {
+                let mut called = BTreeSet::new();
+                if let Expr::LamDef { params, operation, .. } = def {
+                    Self::references(operation, params, &mut called);
                 }
+                called.iter().filter_map(|id| index_of.get(id.as_str()).copied()).collect()
+            })
+            .collect();
+
+        let mut tarjan = Tarjan {
+            edges: &edges,
+            index: vec![None; defs.len()],
+            lowlink: vec![0; defs.len()],
+            on_stack: vec![false; defs.len()],
+            stack: Vec::new(),
+            counter: 0,
+            groups: Vec::new(),
+        };
+        for node in 0..defs.len() {
+            if tarjan.index[node].is_none() {
+                tarjan.visit(node);
             }
         }
+        tarjan.groups
     }
 
-    fn type_check(&self, expr: &Expr) -> Result<TypeScheme, TypeError> {
+    // Collects the free identifiers used by `expr`, `bound` being the parameters in scope.
+    fn references(expr: &Expr, bound: &[String], acc: &mut BTreeSet<String>) {
         match expr {
-            Expr::LamDef {
-                ident: id,
-                params: _,
-                style: _,
-                operation,
-            } => self.type_check_function(id, &operation),
-            Expr::Var(id) => self.type_check_args(id),
-            Expr::Literal(l) => self.type_check_literal(l),
-            Expr::If { cond, then, alter } => self.type_check_ifs(
-                cond,
-                then,
-                alter,
+            Expr::Var(id, _) if !bound.contains(id) => {
+                acc.insert(id.clone());
+            }
+            Expr::LamCall { ident, .. } if !bound.contains(ident) => {
+                acc.insert(ident.clone());
+            }
+            _ => {}
+        }
+        for sub in expr.sub_exprs() {
+            Self::references(sub, bound, acc);
+        }
+    }
+
+    fn describe_group(group: &[usize], defs: &[(&str, &Expr)]) -> String {
+        let names: Vec<String> = group.iter().map(|i| format!("`{}`", defs[*i].0)).collect();
+        let recursive = group.len() > 1 || {
+            let mut called = BTreeSet::new();
+            if let Expr::LamDef { params, operation, .. } = defs[group[0]].1 {
+                Self::references(operation, params, &mut called);
+            }
+            called.contains(defs[group[0]].0)
+        };
+
+        if recursive {
+            format!("recursive group {}", names.join(", "))
+        } else {
+            names.join(", ")
+        }
+    }
+
+    // Infers a group of mutually recursive definitions together: inside the group every member
+    // is monomorphic, and the members are only generalized once the whole group is solved.
+    fn type_check_group(&mut self, group: &[usize], defs: &[(&str, &Expr)], gamma: &mut Gamma) -> Result<(), TypeError> {
+        let mut local = gamma.clone();
+        let members: Vec<Type> = group
+            .iter()
+            .map(|i| {
+                let ty = self.described(format!("`{}`", defs[*i].0));
+                local.insert(defs[*i].0.to_string(), TypeScheme::mono(ty.clone()));
+                ty
+            })
+            .collect();
+
+        for (i, member) in group.iter().zip(&members) {
+            let ty = self.type_check(defs[*i].1, &local)?;
+            let because = Self::reason(None, format!("the definition of `{}`", defs[*i].0));
+            self.unify(member, &ty, &because)?;
+        }
+
+        for (i, member) in group.iter().zip(members) {
+            let scheme = self.generalize(&member, gamma);
+            gamma.insert(defs[*i].0.to_string(), scheme);
+        }
+        Ok(())
+    }
+
+    fn type_check(&mut self, expr: &Expr, gamma: &Gamma) -> Result<Type, TypeError> {
+        match expr {
+            Expr::LamDef { .. } => self.type_check_function(expr, gamma),
+            Expr::Var(id, _) => self.type_check_var(id, gamma),
+            Expr::Literal(l) => Ok(self.type_check_literal(l)),
+            Expr::LamCall { ident, params, pos, .. } => self.type_check_call(ident, params, *pos, gamma),
+            Expr::If { cond, then, alter, pos } => self.type_check_ifs(cond, then, alter, *pos, gamma),
+            Expr::Use { .. } | Expr::Export { .. } | Expr::Fixity { .. } | Expr::TypeDecl { .. } => Ok(Type::Invalid),
+            Expr::Hole { name, pos } => Ok(self.type_check_hole(name, *pos)),
+            Expr::Record(fields) => self.type_check_record(fields, gamma),
+            Expr::Field { record, field } => self.type_check_field(record, field, gamma),
+            Expr::Update { record, fields } => self.type_check_update(record, fields, gamma),
+            Expr::Cast { expr, target, pos } => {
+                let ty = self.type_check(expr, gamma)?;
+                let target = self.annotation_type(target, &mut HashMap::new())?;
+                self.unify(&target, &ty, &Self::reason(Some(*pos), "a cast".to_string()))?;
+                Ok(target)
+            }
+            Expr::List(elements) => Ok(Type::List(Box::new(self.type_check_elements(elements, gamma)?))),
+            Expr::Array(elements) => Ok(Type::Array(Box::new(self.type_check_elements(elements, gamma)?))),
+            Expr::Matrix(rows) => {
+                for element in rows.iter().flatten() {
+                    let ty = self.type_check(element, gamma)?;
+                    self.unify(&Type::Int, &ty, &Self::reason(None, "the elements of a matrix".to_string()))
+                        .map_err(|TypeError(msg)| TypeError(format!("A matrix holds integers: {msg}")))?;
+                }
+                Ok(Type::Matrix)
+            }
+            Expr::Tuple(components) => Ok(Type::Tuple(
+                components.iter().map(|c| self.type_check(c, gamma)).collect::<Result<_, _>>()?,
+            )),
+            Expr::Project { tuple, index, .. } => self.type_check_project(tuple, *index, gamma),
+            Expr::Index { collection, index, pos } => self.type_check_index(collection, index, *pos, gamma),
+            Expr::Match { scrutinee, arms, pos } => self.type_check_match(scrutinee, arms, *pos, gamma),
+            Expr::Pi { .. } | Expr::Sigma { .. } => Err(TypeError(
+                "Dependent types are only understood by the dependent core (--dependent).".to_owned(),
+            )),
+        }
+    }
+
+    fn type_check_record(&mut self, fields: &[(String, Expr)], gamma: &Gamma) -> Result<Type, TypeError> {
+        let mut row = Type::RowEmpty;
+        for (n, (label, expr)) in fields.iter().enumerate().rev() {
+            if fields[..n].iter().any(|(other, _)| other == label) {
+                return Err(TypeError(format!("The field `{label}` is given twice.")));
+            }
+            row = Type::row_extend(label, self.type_check(expr, gamma)?, row);
+        }
+        Ok(Type::Record(Box::new(row)))
+    }
+
+    // `r.label` only requires `r` to have the field `label`, whatever its other fields are.
+    fn type_check_field(&mut self, record: &Expr, label: &str, gamma: &Gamma) -> Result<Type, TypeError> {
+        let record_type = self.type_check(record, gamma)?;
+        let (field, rest) = (self.fresh(), self.fresh());
+        let because = Self::reason(None, format!("the access to the field `{label}`"));
+        self.unify(&record_type, &Type::Record(Box::new(Type::row_extend(label, field.clone(), rest))), &because)
+            .map_err(|TypeError(msg)| TypeError(format!("Cannot access the field `{label}`: {msg}")))?;
+        Ok(field)
+    }
+
+    // An update keeps the type of the record, so the updated fields must exist with the same types.
+    fn type_check_update(&mut self, record: &Expr, fields: &[(String, Expr)], gamma: &Gamma) -> Result<Type, TypeError> {
+        let record_type = self.type_check(record, gamma)?;
+        for (label, expr) in fields {
+            let field = self.type_check(expr, gamma)?;
+            let rest = self.fresh();
+            let because = Self::reason(None, format!("the update of the field `{label}`"));
+            self.unify(&record_type, &Type::Record(Box::new(Type::row_extend(label, field, rest))), &because)
+                .map_err(|TypeError(msg)| TypeError(format!("Cannot update the field `{label}`: {msg}")))?;
+        }
+        Ok(record_type)
+    }
+
+    // The type shared by the elements of a list or an array
+    fn type_check_elements(&mut self, elements: &[Expr], gamma: &Gamma) -> Result<Type, TypeError> {
+        let element = self.fresh();
+        for expr in elements {
+            let ty = self.type_check(expr, gamma)?;
+            self.unify(&element, &ty, &Self::reason(None, "the elements of a collection".to_string()))
+                .map_err(|TypeError(msg)| TypeError(format!("The elements must have the same type: {msg}")))?;
+        }
+        Ok(element)
+    }
+
+    // The number of components of the tuple must be known from what precedes the projection,
+    // as no type stands for the tuples of any length.
+    fn type_check_project(&mut self, tuple: &Expr, index: usize, gamma: &Gamma) -> Result<Type, TypeError> {
+        let ty = self.type_check(tuple, gamma)?;
+        match self.resolve(&ty) {
+            Type::Tuple(components) if index < components.len() => Ok(components[index].clone()),
+            Type::Tuple(components) => Err(TypeError(format!(
+                "A tuple of {} components has no component {index}.",
+                components.len()
+            ))),
+            Type::Var(_) => Err(TypeError(
+                "The type of the tuple must be known before its projection, annotate it or match it with a pattern.".to_owned(),
+            )),
+            ty => Err(TypeError(format!("Only tuples can be projected, this value has the type {ty}."))),
+        }
+    }
+
+    // Indexing a matrix gives one of its rows, the collection is an array otherwise. Arrays and
+    // matrices are told apart by the type inferred so far, as they cannot be unified.
+    fn type_check_index(&mut self, collection: &Expr, index: &Expr, pos: (usize, usize), gamma: &Gamma) -> Result<Type, TypeError> {
+        let collection = self.type_check(collection, gamma)?;
+        let index = self.type_check(index, gamma)?;
+        self.unify(&Type::Int, &index, &Self::reason(Some(pos), "an index".to_string()))
+            .map_err(|TypeError(msg)| TypeError(format!("An index must be an integer: {msg}")))?;
+        if self.resolve(&collection) == Type::Matrix {
+            return Ok(Type::Array(Box::new(Type::Int)));
+        }
+        let element = self.fresh();
+        self.unify(&Type::Array(Box::new(element.clone())), &collection, &Self::reason(Some(pos), "an indexing".to_string()))
+            .map_err(|TypeError(msg)| TypeError(format!("Only arrays and matrices can be indexed: {msg}")))?;
+        Ok(element)
+    }
+
+    // Each pattern has the type of the scrutinee and binds its variables in the body of its arm,
+    // the bodies having the type of the match.
+    fn type_check_match(&mut self, scrutinee: &Expr, arms: &[(Pattern, Expr)], pos: (usize, usize), gamma: &Gamma) -> Result<Type, TypeError> {
+        let scrutinee = self.type_check(scrutinee, gamma)?;
+        let result = self.described("the result of the match".to_string());
+        for (pattern, body) in arms {
+            let mut bound = Vec::new();
+            let ty = self.type_check_pattern(pattern, &mut bound)?;
+            self.unify(&scrutinee, &ty, &Self::reason(Some(pos), "the patterns of the match".to_string()))
+                .map_err(|TypeError(msg)| TypeError(format!("The pattern does not have the type of the matched value: {msg}")))?;
+
+            let mut local = gamma.clone();
+            for (name, ty) in &bound {
+                local.insert(name.clone(), TypeScheme::mono(ty.clone()));
+            }
+            let scope_len = self.scope.len();
+            self.scope.extend(bound);
+            let body = self.type_check(body, &local);
+            self.scope.truncate(scope_len);
+            self.unify(&result, &body?, &Self::reason(Some(pos), "the arms of the match".to_string()))
+                .map_err(|TypeError(msg)| TypeError(format!("The arms of the match have different types: {msg}")))?;
+        }
+        Ok(result)
+    }
+
+    // The type of the values matched by `pattern`, adding the variables it binds to `bound`
+    fn type_check_pattern(&mut self, pattern: &Pattern, bound: &mut Vec<(String, Type)>) -> Result<Type, TypeError> {
+        match pattern {
+            Pattern::Wildcard => Ok(self.fresh()),
+            Pattern::Var(name) => {
+                if bound.iter().any(|(other, _)| other == name) {
+                    return Err(TypeError(format!("`{name}` is bound twice by the pattern.")));
+                }
+                let ty = self.described(format!("`{name}`"));
+                bound.push((name.clone(), ty.clone()));
+                Ok(ty)
+            }
+            Pattern::Literal(literal) => Ok(self.type_check_literal(literal)),
+            Pattern::List(elements) => {
+                let element = self.fresh();
+                for pattern in elements {
+                    let ty = self.type_check_pattern(pattern, bound)?;
+                    self.unify(&element, &ty, &Self::reason(None, "the elements of a list pattern".to_string()))?;
+                }
+                Ok(Type::List(Box::new(element)))
+            }
+            Pattern::Tuple(components) => Ok(Type::Tuple(
+                components
+                    .iter()
+                    .map(|pattern| self.type_check_pattern(pattern, bound))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Pattern::Cons(head, tail) => {
+                let head = self.type_check_pattern(head, bound)?;
+                let tail = self.type_check_pattern(tail, bound)?;
+                let list = Type::List(Box::new(head));
+                self.unify(&list, &tail, &Self::reason(None, "the tail of a `::` pattern".to_string()))?;
+                Ok(list)
+            }
+        }
+    }
+
+    // A hole fits anywhere, its type is whatever its context expects.
+    fn type_check_hole(&mut self, name: &Option<String>, pos: (usize, usize)) -> Type {
+        let expected = match name {
+            Some(name) => self.described(format!("the hole `?{name}`")),
+            None => self.described("the hole `_`".to_string()),
+        };
+        self.holes.push(Hole {
+            name: name.clone(),
+            pos,
+            expected: expected.clone(),
+            scope: self.scope.clone(),
+        });
+        expected
+    }
+
+    fn type_check_function(&mut self, def: &Expr, gamma: &Gamma) -> Result<Type, TypeError> {
+        let Expr::LamDef {
+            params,
+            annotations,
+            ret,
+            pure,
+            operation: body,
+            pos,
+            ..
+        } = def
+        else {
+            unreachable!()
+        };
+        let mut local = gamma.clone();
+        // Type variables named in the annotations, shared by the whole definition
+        let mut named = HashMap::new();
+        let mut params_types: Vec<Type> = Vec::new();
+        for (param, annotation) in params.iter().zip(annotations) {
+            let ty = match annotation {
+                // Bound to a variable resolved by the annotation, which explains the errors about it
+                Some(annotation) => {
+                    let ty = self.annotation_type(annotation, &mut named)?;
+                    let var = self.described(format!("`{param}`"));
+                    let because = Self::reason(annotation.pos().or(Some(*pos)), format!("the annotation of `{param}`"));
+                    self.unify(&var, &ty, &because)?;
+                    var
+                }
+                None if self.gradual => Type::Whatever,
+                None => self.described(format!("`{param}`")),
+            };
+            local.insert(param.clone(), TypeScheme::mono(ty.clone()));
+            params_types.push(ty);
+        }
+
+        let scope_len = self.scope.len();
+        self.scope
+            .extend(params.iter().cloned().zip(params_types.iter().cloned()));
+        let body_effect = self.fresh();
+        let outer_effect = std::mem::replace(&mut self.effect, body_effect);
+        let body_type = self.type_check(body, &local);
+        self.scope.truncate(scope_len);
+        let effect = std::mem::replace(&mut self.effect, outer_effect);
+
+        let body_type = body_type?;
+        if let Some(ret) = ret {
+            let ret = self.annotation_type(ret, &mut named)?;
+            self.unify(&ret, &body_type, &Self::reason(None, "the annotated return type".to_string()))
+                .map_err(|TypeError(msg)| TypeError(format!("The body does not have the annotated type: {msg}")))?;
+            self.expected.push((Site::Body(*pos), body_type.clone(), ret));
+        }
+        if *pure {
+            let performed = self.resolve(&effect);
+            if !performed.effect_labels().is_empty() {
+                return Err(TypeError(format!(
+                    "The function is declared pure but performs {}.",
+                    performed.effect_labels().join(", ")
+                )));
+            }
+            self.unify(&effect, &Type::RowEmpty, &Self::reason(None, "the `pure` declaration".to_string()))?;
+        }
+
+        // Only the last call performs the effects of the body, applying the other parameters does nothing
+        let mut ty = body_type;
+        for (n, i) in params_types.into_iter().enumerate().rev() {
+            let eff = if n + 1 == params.len() { effect.clone() } else { self.fresh() };
+            ty = Type::joined_with(i, ty, eff);
+        }
+        Ok(ty)
+    }
+
+    // Translates an annotation, lowercase names being type variables and declared types being
+    // replaced with their definition.
+    fn annotation_type(&mut self, annotation: &TypeExpr, named: &mut HashMap<String, Type>) -> Result<Type, TypeError> {
+        match annotation {
+            TypeExpr::Named { name, args, .. } => match name.as_str() {
+                "Int" => Ok(Type::Int),
+                "Str" => Ok(Type::Str),
+                "Bool" => Ok(Type::Bool),
+                "Matrix" => Ok(Type::Matrix),
+                "List" | "Array" if args.len() == 1 => {
+                    let element = Box::new(self.annotation_type(&args[0], named)?);
+                    Ok(if name == "List" { Type::List(element) } else { Type::Array(element) })
+                }
+                _ if is_type_var(name) && args.is_empty() => Ok(self.named_var(name, named)),
+                _ if is_type_var(name) => Err(TypeError(format!(
+                    "The type variable `{name}` is applied to types, which the inference does not support."
+                ))),
+                _ => match self.aliases.get(name.as_str()) {
+                    Some((params, body)) => {
+                        let expanded = Self::substitute(body, params, args);
+                        self.annotation_type(&expanded, named)
+                    }
+                    None => Err(TypeError(format!("Unknown type `{name}`."))),
+                },
+            },
+            TypeExpr::Record { fields, rest } => {
+                let mut row = match rest {
+                    Some(rest) => self.named_var(rest, named),
+                    None => Type::RowEmpty,
+                };
+                for (label, field) in fields.iter().rev() {
+                    row = Type::row_extend(label, self.annotation_type(field, named)?, row);
+                }
+                Ok(Type::Record(Box::new(row)))
+            }
+            TypeExpr::Tuple(components) => Ok(Type::Tuple(
+                components.iter().map(|c| self.annotation_type(c, named)).collect::<Result<_, _>>()?,
+            )),
+            // The effects of a function parameter are those of the function given to it
+            TypeExpr::Joined(i, o) => Ok(Type::joined_with(
+                self.annotation_type(i, named)?,
+                self.annotation_type(o, named)?,
+                self.fresh(),
+            )),
+            TypeExpr::Dynamic => Ok(Type::Whatever),
+            // The predicate is checked by the refinement checker, once the types are inferred
+            TypeExpr::Refined { var, base, .. } => match self.annotation_type(base, named)? {
+                Type::Int => Ok(Type::Int),
+                base => Err(TypeError(format!("`{var}` is refined, which is only supported for Int, not {base}."))),
+            },
+            TypeExpr::Term(_) => Err(TypeError(
+                "Terms as types are only understood by the dependent core (--dependent).".to_owned(),
+            )),
+        }
+    }
+
+    fn named_var(&mut self, name: &str, named: &mut HashMap<String, Type>) -> Type {
+        if !named.contains_key(name) {
+            let var = self.fresh();
+            named.insert(name.to_string(), var);
+        }
+        named[name].clone()
+    }
+
+    // Replaces the parameters of a declared type with its arguments in its body.
+    // The kinds ensure that a parameter applied to types is given a named type.
+    fn substitute(ty: &TypeExpr, params: &[String], args: &[TypeExpr]) -> TypeExpr {
+        let arg_of = |name: &str| params.iter().position(|p| p == name).map(|i| &args[i]);
+        match ty {
+            TypeExpr::Named { name, args: own, pos } => {
+                let own: Vec<TypeExpr> = own.iter().map(|t| Self::substitute(t, params, args)).collect();
+                match arg_of(name) {
+                    Some(TypeExpr::Named { name, args: given, pos }) => TypeExpr::Named {
+                        name: name.clone(),
+                        args: given.iter().cloned().chain(own).collect(),
+                        pos: *pos,
+                    },
+                    Some(arg) => arg.clone(),
+                    None => TypeExpr::Named {
+                        name: name.clone(),
+                        args: own,
+                        pos: *pos,
+                    },
+                }
+            }
+            TypeExpr::Record { fields, rest } => TypeExpr::Record {
+                fields: fields
+                    .iter()
+                    .map(|(label, t)| (label.clone(), Self::substitute(t, params, args)))
+                    .collect(),
+                rest: match rest.as_deref().and_then(arg_of) {
+                    Some(TypeExpr::Named { name, .. }) => Some(name.clone()),
+                    _ => rest.clone(),
+                },
+            },
+            TypeExpr::Joined(i, o) => TypeExpr::Joined(
+                Box::new(Self::substitute(i, params, args)),
+                Box::new(Self::substitute(o, params, args)),
             ),
+            TypeExpr::Tuple(components) => {
+                TypeExpr::Tuple(components.iter().map(|c| Self::substitute(c, params, args)).collect())
+            }
+            TypeExpr::Refined { var, base, pred } => TypeExpr::Refined {
+                var: var.clone(),
+                base: Box::new(Self::substitute(base, params, args)),
+                pred: pred.clone(),
+            },
+            TypeExpr::Dynamic | TypeExpr::Term(_) => ty.clone(),
+        }
+    }
+
+    fn type_check_var(&mut self, id: &String, gamma: &Gamma) -> Result<Type, TypeError> {
+        match gamma.get(id) {
+            Some(scheme) => {
+                let ty = self.instantiate(scheme);
+                Ok(self.open_effects(&ty))
+            }
+            None => Err(TypeError(format!("`{id}` is not defined."))),
+        }
+    }
+
+    fn type_check_literal(&self, literal: &Literal) -> Type {
+        match literal {
+            Literal::Int(_) => Type::Int,
+            Literal::Str(_) => Type::Str,
+        }
+    }
+
+    // A call `f(a, b)` constrains `f` to be a function taking the arguments one by one,
+    // whose effects are performed by the body being checked.
+    fn type_check_call(&mut self, ident: &String, params: &[Expr], pos: (usize, usize), gamma: &Gamma) -> Result<Type, TypeError> {
+        let mut callee = self.type_check_var(ident, gamma)?;
+        // The builtin printf, unless a definition hides it
+        if ident == "printf" && gamma.get(ident) == Some(&TypeScheme::mono(Self::printf_type(&[]))) {
+            callee = self.printf_call_type(params)?;
+        }
+
+        let mut args = Vec::new();
+        for param in params {
+            args.push(self.type_check(param, gamma)?);
+        }
+
+        let ret = self.described(format!("the result of `{ident}`"));
+        let expected = args
+            .iter()
+            .cloned()
+            .rev()
+            .fold(ret.clone(), |o, i| Type::joined_with(i, o, self.effect.clone()));
+
+        let because = Self::reason(Some(pos), format!("the call to `{ident}`"));
+        self.unify(&callee, &expected, &because).map_err(|TypeError(msg)| {
+            TypeError(format!(
+                "Cannot call `{ident}` of type {} with {} argument(s): {msg}",
+                self.resolve(&callee),
+                params.len()
+            ))
+        })?;
+        self.calls.push(Call { pos, callee, args });
+        Ok(ret)
+    }
+
+    // Str -> a -> b -> ... -> {} ! IO, with a parameter for each value formatted
+    fn printf_type(specifiers: &[Type]) -> Type {
+        let io = Type::row_extend("IO", Type::RowEmpty, Type::RowEmpty);
+        let unit = Type::Record(Box::new(Type::RowEmpty));
+        let mut ty = unit;
+        for (n, specifier) in specifiers.iter().enumerate().rev() {
+            let eff = if n + 1 == specifiers.len() { io.clone() } else { Type::RowEmpty };
+            ty = Type::joined_with(specifier.clone(), ty, eff);
+        }
+        let eff = if specifiers.is_empty() { io } else { Type::RowEmpty };
+        Type::joined_with(Type::Str, ty, eff)
+    }
 
-            _ => Ok(TypeScheme(BTreeSet::new(), BTreeSet::new())),
+    // The type of `printf(format, ...)`, the literal format giving the type of each value:
+    // `{i}` for an Int, `{b}` for a Bool and `{s}` for a Str.
+    fn printf_call_type(&mut self, params: &[Expr]) -> Result<Type, TypeError> {
+        let Some(Expr::Literal(Literal::Str(format))) = params.first() else {
+            return Err(TypeError("The format of `printf` must be a string literal.".to_owned()));
+        };
+        let mut specifiers = Vec::new();
+        let mut rest = format.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else { break };
+            specifiers.push(match &rest[start + 1..start + end] {
+                "i" => Type::Int,
+                "b" => Type::Bool,
+                "s" => Type::Str,
+                other => {
+                    return Err(TypeError(format!(
+                        "Unknown format specifier `{{{other}}}` in `printf`, expected `{{i}}`, `{{b}}` or `{{s}}`."
+                    )))
+                }
+            });
+            rest = &rest[start + end + 1..];
         }
+        Ok(self.open_effects(&Self::printf_type(&specifiers)))
     }
 
-    fn type_check_function(&self, ident: &String, body: &Expr) -> Result<TypeScheme, TypeError> {
-        let TypeScheme(body_in_type, body_out_type) = self.type_check(body)?;
-        println!("Args of the func: {:?}\n Return type: {:?}", body_in_type, body_out_type);
-        Ok(TypeScheme(body_in_type, body_out_type))
+    // The result of the if is given its type by the first branch, so that a mismatch of the
+    // second one points at both.
+    fn type_check_ifs(&mut self, cond_expr: &Expr, then_expr: &Expr, alter_expr: &Expr, pos: (usize, usize), gamma: &Gamma) -> Result<Type, TypeError> {
+        let cond_type = self.type_check(cond_expr, gamma)?;
+        let then_type = self.type_check(then_expr, gamma)?;
+        let alter_type = self.type_check(alter_expr, gamma)?;
+
+        let because = Self::reason(cond_expr.pos().or(Some(pos)), "the condition of an if".to_string());
+        self.unify(&Type::Bool, &cond_type, &because)
+            .map_err(|TypeError(msg)| TypeError(format!("The condition of an if must be a Bool: {msg}")))?;
+        self.expected.push((Site::Cond(pos), cond_type, Type::Bool));
+
+        let result = self.described("the result of the if".to_string());
+        let because = Self::reason(then_expr.pos().or(Some(pos)), "the first branch of the if".to_string());
+        self.unify(&result, &then_type, &because)?;
+        let because = Self::reason(alter_expr.pos().or(Some(pos)), "the second branch of the if".to_string());
+        self.unify(&result, &alter_type, &because)
+            .map_err(|TypeError(msg)| TypeError(format!("Cannot return two different types: {msg}")))?;
+
+        Ok(result)
+    }
+
+    // Replaces the end of the closed effect rows of the calls to `ty` with fresh variables.
+    // The functions taken as parameters keep their effects, which the callee relies on.
+    fn open_effects(&mut self, ty: &Type) -> Type {
+        match self.resolve(ty) {
+            Type::Joined { i, o, eff } => {
+                let o = self.open_effects(&o);
+                let eff = self.open_row(&eff);
+                Type::joined_with(*i, o, eff)
+            }
+            // Left unresolved, the variables of `ty` keep explaining where its type comes from
+            _ => ty.clone(),
+        }
+    }
+
+    fn open_row(&mut self, row: &Type) -> Type {
+        match row {
+            Type::RowEmpty => self.fresh(),
+            Type::RowExtend { label, field, rest } => Type::row_extend(label, (**field).clone(), self.open_row(rest)),
+            _ => row.clone(),
+        }
     }
 
-    fn type_check_args(&self, id: &String) -> Result<TypeScheme, TypeError> {
-        Ok(TypeScheme(
-            BTreeSet::from([
-                Assumption { name: id.clone(), hypothesis: BTreeSet::from([Type::Int, Type::Str, Type::Bool]) }
-            ]),
-            BTreeSet::from([Type::Int, Type::Str, Type::Bool])
-        ))
+    fn fresh(&mut self) -> Type {
+        self.next_var += 1;
+        Type::Var(self.next_var - 1)
     }
 
-    fn type_check_literal(&self, literal: &Literal) -> Result<TypeScheme, TypeError> {
-        Ok(match literal {
-            Literal::Int(_) => TypeScheme(BTreeSet::new(), BTreeSet::from([Type::Int])),
-            Literal::Str(_) => TypeScheme(BTreeSet::new(), BTreeSet::from([Type::Str])),
+    // A fresh type variable for what `description` describes, as in "`n`", to explain errors
+    fn described(&mut self, description: String) -> Type {
+        let var = self.fresh();
+        if let Type::Var(v) = var {
+            self.descriptions.insert(v, description);
+        }
+        var
+    }
+
+    fn reason(pos: Option<(usize, usize)>, what: String) -> Reason {
+        Reason { pos, what }
+    }
+
+    /// Applies the current substitution to `ty` until no resolved variable is left.
+    pub fn resolve(&self, ty: &Type) -> Type {
+        ty.map_vars(&mut |v| match self.subst.get(&v) {
+            Some(t) => self.resolve(t),
+            None => Type::Var(v),
+        })
+    }
+
+    // Unifies `left` with `right` as required by `because`, explaining the failure if they do not unify
+    fn unify(&mut self, left: &Type, right: &Type, because: &Reason) -> Result<(), TypeError> {
+        self.conflict = None;
+        self.unify_types(left, right, because).inspect_err(|_| {
+            self.failure = Some(self.explain_failure(left, right, because));
         })
     }
 
-    fn type_check_ifs(&self, cond_expr: &Expr, then_expr: &Expr, alter_expr: &Expr) -> Result<TypeScheme, TypeError> {
-        let TypeScheme(cond_in_type, cond_out_type) = self.type_check(cond_expr)?;
-        let TypeScheme(then_in_type, then_out_type) = self.type_check(then_expr)?;
-        let TypeScheme(alter_in_type, alter_out_type) = self.type_check(alter_expr)?;
-        
-        if cond_out_type != BTreeSet::from([Type::Bool]) {
-            return Err(TypeError(
-                "Cannot use an expression that is not of type boolean as condition.".to_owned(),
+    // Lists how the variables of `left` and `right` were resolved to the conflicting types
+    fn explain_failure(&self, left: &Type, right: &Type, because: &Reason) -> Failure {
+        let mut trace = Vec::new();
+        let Some((a, b)) = &self.conflict else {
+            return Failure {
+                reason: because.clone(),
+                trace,
+            };
+        };
+        let names = Type::name_vars([a, b]);
+        let infinite = match (a, b) {
+            (Type::Var(v), _) | (_, Type::Var(v)) => Some(*v),
+            _ => None,
+        };
+
+        // The variables of each side explain the type found on their side
+        for (side, found) in [(left, a), (right, b)] {
+            let mut pending = BTreeSet::new();
+            side.free_vars(&mut pending);
+            let mut pending: Vec<usize> = pending.into_iter().collect();
+            let mut seen = BTreeSet::new();
+            while let Some(v) = pending.pop() {
+                if !seen.insert(v) {
+                    continue;
+                }
+                let Some(bound) = self.subst.get(&v) else {
+                    continue;
+                };
+                let mut next = BTreeSet::new();
+                bound.free_vars(&mut next);
+                pending.extend(next);
+
+                let resolved = self.resolve(&Type::Var(v));
+                let mut vars = BTreeSet::new();
+                resolved.free_vars(&mut vars);
+                let relevant = resolved == *found || infinite.is_some_and(|v| vars.contains(&v));
+                match (self.descriptions.get(&v), self.origins.get(&v)) {
+                    (Some(description), Some(origin)) if relevant && origin != because => trace.push((
+                        origin.pos,
+                        format!("{description} is {} because of {}", resolved.pretty(&names), origin.what),
+                    )),
+                    _ => {}
+                }
+            }
+        }
+        trace.sort_by_key(|(pos, _)| *pos);
+
+        if let (Type::Var(v), other) | (other, Type::Var(v)) = (a, b) {
+            let var = Type::Var(*v).pretty(&names);
+            let description = self.descriptions.get(v).cloned().unwrap_or(var.clone());
+            trace.push((
+                None,
+                format!("{description} would have to be {}, which contains {var} itself", other.pretty(&names)),
             ));
         }
+        Failure {
+            reason: because.clone(),
+            trace,
+        }
+    }
+
+    fn unify_types(&mut self, left: &Type, right: &Type, because: &Reason) -> Result<(), TypeError> {
+        let (left, right) = (self.resolve(left), self.resolve(right));
+
+        match (&left, &right) {
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            (Type::Var(v), other) | (other, Type::Var(v)) => {
+                let mut occurring = BTreeSet::new();
+                other.free_vars(&mut occurring);
+                if occurring.contains(v) {
+                    self.conflict = Some((Type::Var(*v), other.clone()));
+                    let names = Type::name_vars([&left, &right]);
+                    return Err(TypeError(format!(
+                        "Cannot construct the infinite type {} = {}",
+                        left.pretty(&names),
+                        right.pretty(&names)
+                    )));
+                }
+                self.subst.insert(*v, other.clone());
+                self.origins.insert(*v, because.clone());
+                Ok(())
+            }
+            // Checked at runtime instead, by the casts inserted around the arguments
+            (Type::Whatever, _) | (_, Type::Whatever) => Ok(()),
+            (Type::Joined { i: i1, o: o1, eff: e1 }, Type::Joined { i: i2, o: o2, eff: e2 }) => {
+                self.unify_types(i1, i2, because)?;
+                self.unify_types(o1, o2, because)?;
+                self.unify_types(e1, e2, because).map_err(|_| {
+                    TypeError(format!(
+                        "Expected a function performing {}, got one performing {}",
+                        Self::describe_effects(&self.resolve(e1)),
+                        Self::describe_effects(&self.resolve(e2))
+                    ))
+                })
+            }
+            (Type::Record(r1), Type::Record(r2)) => self.unify_types(r1, r2, because),
+            (Type::List(e1), Type::List(e2)) | (Type::Array(e1), Type::Array(e2)) => self.unify_types(e1, e2, because),
+            (Type::Tuple(c1), Type::Tuple(c2)) if c1.len() == c2.len() => {
+                c1.iter().zip(c2).try_for_each(|(t1, t2)| self.unify_types(t1, t2, because))
+            }
+            (Type::RowExtend { label, field, rest }, row) | (row, Type::RowExtend { label, field, rest })
+                if matches!(row, Type::RowExtend { .. } | Type::RowEmpty) =>
+            {
+                // Both rows must have `label`, the remaining fields being unified as rows
+                let (row_tail, rest_tail) = (Self::row_tail(row), Self::row_tail(rest));
+                let (other_field, other_rest) = self.rewrite_row(row, label, because)?;
+                // Extending the tail of `row` with `label` would extend `rest` as well, forever
+                if row_tail.is_some_and(|v| self.subst.contains_key(&v)) && row_tail == rest_tail {
+                    return Err(TypeError(format!("Cannot construct the infinite record {left} = {right}")));
+                }
+                self.unify_types(field, &other_field, because)?;
+                self.unify_types(rest, &other_rest, because)
+            }
+            _ if left == right => Ok(()),
+            _ => {
+                self.conflict = Some((left.clone(), right.clone()));
+                let names = Type::name_vars([&left, &right]);
+                Err(TypeError(format!(
+                    "Expected type {}, got {}",
+                    left.pretty(&names),
+                    right.pretty(&names)
+                )))
+            }
+        }
+    }
+
+    // Finds the field `label` in `row`, returning its type and the row without it.
+    // An open row is extended with the field when it does not have it yet.
+    fn rewrite_row(&mut self, row: &Type, label: &str, because: &Reason) -> Result<(Type, Type), TypeError> {
+        match self.resolve(row) {
+            Type::RowExtend { label: l, field, rest } if l == label => Ok((*field, *rest)),
+            Type::RowExtend { label: l, field, rest } => {
+                let (found, rest) = self.rewrite_row(&rest, label, because)?;
+                Ok((found, Type::row_extend(&l, *field, rest)))
+            }
+            Type::Var(v) => {
+                let (field, rest) = (self.fresh(), self.fresh());
+                self.subst.insert(v, Type::row_extend(label, field.clone(), rest.clone()));
+                self.origins.insert(v, because.clone());
+                Ok((field, rest))
+            }
+            _ => Err(TypeError(format!("The record has no field `{label}`"))),
+        }
+    }
+
+    fn describe_effects(row: &Type) -> String {
+        match row.effect_labels().as_slice() {
+            [] => "no effect".to_string(),
+            labels => labels.join(", "),
+        }
+    }
+
+    // The variable ending an open row
+    fn row_tail(row: &Type) -> Option<usize> {
+        match row {
+            Type::RowExtend { rest, .. } => Self::row_tail(rest),
+            Type::Var(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    fn instantiate(&mut self, TypeScheme(vars, ty): &TypeScheme) -> Type {
+        let renaming: HashMap<usize, Type> = vars.iter().map(|v| (*v, self.fresh())).collect();
+        Self::rename(ty, &renaming)
+    }
+
+    fn rename(ty: &Type, renaming: &HashMap<usize, Type>) -> Type {
+        ty.map_vars(&mut |v| renaming.get(&v).cloned().unwrap_or(Type::Var(v)))
+    }
+
+    // Quantifies over the variables of `ty` that are not bound in the environment.
+    fn generalize(&self, ty: &Type, gamma: &Gamma) -> TypeScheme {
+        let ty = self.resolve(ty);
+
+        let mut in_env = BTreeSet::new();
+        for TypeScheme(bound, scheme) in gamma.values() {
+            let mut vars = BTreeSet::new();
+            self.resolve(scheme).free_vars(&mut vars);
+            in_env.extend(vars.difference(bound));
+        }
+
+        let mut vars = BTreeSet::new();
+        ty.free_vars(&mut vars);
+        TypeScheme(vars.difference(&in_env).copied().collect(), ty)
+    }
+}
+
+impl<'a> Kinds<'a> {
+    fn fresh(&mut self) -> Kind {
+        self.next_var += 1;
+        Kind::Var(self.next_var - 1)
+    }
+
+    fn resolve(&self, kind: &Kind) -> Kind {
+        match kind {
+            Kind::Var(v) => match self.subst.get(v) {
+                Some(k) => self.resolve(k),
+                None => kind.clone(),
+            },
+            Kind::Arrow(a, b) => Kind::Arrow(Box::new(self.resolve(a)), Box::new(self.resolve(b))),
+            _ => kind.clone(),
+        }
+    }
+
+    // The kinds left unknown are `*`, as in `type Const a b = a`
+    fn default(&self, kind: &Kind) -> Kind {
+        match self.resolve(kind) {
+            Kind::Var(_) => Kind::Star,
+            Kind::Arrow(a, b) => Kind::Arrow(Box::new(self.default(&a)), Box::new(self.default(&b))),
+            kind => kind,
+        }
+    }
+
+    fn occurs(&self, v: usize, kind: &Kind) -> bool {
+        match self.resolve(kind) {
+            Kind::Var(w) => v == w,
+            Kind::Arrow(a, b) => self.occurs(v, &a) || self.occurs(v, &b),
+            _ => false,
+        }
+    }
+
+    fn unify(&mut self, left: &Kind, right: &Kind) -> bool {
+        match (self.resolve(left), self.resolve(right)) {
+            (Kind::Var(a), Kind::Var(b)) if a == b => true,
+            (Kind::Var(v), other) | (other, Kind::Var(v)) => {
+                if self.occurs(v, &other) {
+                    return false;
+                }
+                self.subst.insert(v, other);
+                true
+            }
+            (Kind::Arrow(a1, b1), Kind::Arrow(a2, b2)) => self.unify(&a1, &a2) && self.unify(&b1, &b2),
+            (left, right) => left == right,
+        }
+    }
 
-        if then_out_type != alter_out_type {
-            return Err(TypeError("Cannot return two different types".to_owned()));
+    // The kind of the declared type `name`, inferred from its body the first time it is needed
+    fn decl_kind(&mut self, name: &'a str) -> Result<Kind, KindError> {
+        if let Some(kind) = self.declared.get(name) {
+            return Ok(kind.clone());
+        }
+        let Expr::TypeDecl { params, body, pos, .. } = self.decls[name] else {
+            unreachable!("only type declarations are collected")
+        };
+        let pos = *pos;
+        if self.in_progress.contains(&name) {
+            return Err((Some(pos), format!("The type `{name}` is defined in terms of itself.")));
         }
 
-        let intersected_in_types: BTreeSet<Assumption> = self.intersect_assumption_types(&cond_in_type, &then_in_type); 
-        let twice_intersected_in_types: BTreeSet<Assumption> = self.intersect_assumption_types(&intersected_in_types, &alter_in_type);
+        self.in_progress.push(name);
+        let mut vars: HashMap<String, Kind> = params.iter().map(|p| (p.clone(), self.fresh())).collect();
+        self.expect_star(body, &mut vars, false)
+            .map_err(|(at, msg)| (at.or(Some(pos)), format!("In the type `{name}`: {msg}")))?;
+        self.in_progress.pop();
 
-        Ok(TypeScheme(twice_intersected_in_types, alter_out_type))
+        let kind = params
+            .iter()
+            .rev()
+            .fold(Kind::Star, |acc, p| Kind::Arrow(Box::new(vars[p].clone()), Box::new(acc)));
+        let kind = self.default(&kind);
+        self.declared.insert(name.to_string(), kind.clone());
+        Ok(kind)
     }
 
-    /* 
-    fn intersect_assumption_types(&self, left: &BTreeSet<Assumption>, right: &BTreeSet<Assumption>) -> BTreeSet<Assumption> {
-        let mut set = BTreeSet::new();    
-        for i in left {
-            println!("{:?}", left);
-            for j in right {
-                if i.hypothesis.is_empty()  {
-                    return 
+    // Checks that `ty` is the type of values, `bind` introducing its unknown type variables
+    fn expect_star(&mut self, ty: &TypeExpr, vars: &mut HashMap<String, Kind>, bind: bool) -> Result<(), KindError> {
+        let kind = self.infer(ty, vars, bind)?;
+        if self.unify(&kind, &Kind::Star) {
+            return Ok(());
+        }
+        let kind = self.default(&kind);
+        Err(match ty {
+            TypeExpr::Named { name, pos, .. } => (
+                Some(*pos),
+                format!("`{name}` is missing type arguments, it has the kind {kind}."),
+            ),
+            _ => (None, format!("Expected a type of kind *, got one of kind {kind}.")),
+        })
+    }
+
+    fn infer(&mut self, ty: &TypeExpr, vars: &mut HashMap<String, Kind>, bind: bool) -> Result<Kind, KindError> {
+        match ty {
+            TypeExpr::Named { name, args, pos } => {
+                let head = match name.as_str() {
+                    "Int" | "Str" | "Bool" | "Matrix" => Kind::Star,
+                    "List" | "Array" => Kind::Arrow(Box::new(Kind::Star), Box::new(Kind::Star)),
+                    _ if is_type_var(name) => self.var_kind(name, *pos, vars, bind)?,
+                    _ => match self.decls.get_key_value(name.as_str()) {
+                        Some((name, _)) => self.decl_kind(name)?,
+                        None => return Err((Some(*pos), format!("Unknown type `{name}`."))),
+                    },
+                };
+
+                let mut kind = head.clone();
+                for arg in args {
+                    let (arg_kind, result) = (self.infer(arg, vars, bind)?, self.fresh());
+                    if !self.unify(&kind, &Kind::Arrow(Box::new(arg_kind), Box::new(result.clone()))) {
+                        let msg = match self.resolve(&head) {
+                            Kind::Var(_) => format!("`{name}` cannot be applied to itself, its kind would be infinite."),
+                            head => format!(
+                                "`{name}` of kind {} cannot be applied to {} type argument(s).",
+                                self.default(&head),
+                                args.len()
+                            ),
+                        };
+                        return Err((Some(*pos), msg));
+                    }
+                    kind = result;
                 }
-                if i.name == j.name {
-                    let hypothesis = i.hypothesis.intersection(&j.hypothesis).cloned().collect();
-                    if hypothesis.is_empty() {
-                        return Err(TypeError(""));
+                Ok(kind)
+            }
+            TypeExpr::Record { fields, rest } => {
+                for (_, field) in fields {
+                    self.expect_star(field, vars, bind)?;
+                }
+                if let Some(rest) = rest {
+                    let kind = self.var_kind(rest, (0, 0), vars, bind).map_err(|(_, msg)| (None, msg))?;
+                    if !self.unify(&kind, &Kind::Row) {
+                        return Err((None, format!("`{rest}` ends a record, but is used as a type elsewhere.")));
                     }
-                    set.insert(Assumption { name: i.name.clone(), hypothesis });
-                } else {
-                    set.insert(i.clone());
-                    set.insert(j.clone());
                 }
+                Ok(Kind::Star)
+            }
+            TypeExpr::Joined(i, o) => {
+                self.expect_star(i, vars, bind)?;
+                self.expect_star(o, vars, bind)?;
+                Ok(Kind::Star)
+            }
+            TypeExpr::Tuple(components) => {
+                for component in components {
+                    self.expect_star(component, vars, bind)?;
+                }
+                Ok(Kind::Star)
+            }
+            TypeExpr::Refined { base, .. } => {
+                self.expect_star(base, vars, bind)?;
+                Ok(Kind::Star)
+            }
+            TypeExpr::Dynamic | TypeExpr::Term(_) => Ok(Kind::Star),
+        }
+    }
+
+    fn var_kind(
+        &mut self,
+        name: &str,
+        pos: (usize, usize),
+        vars: &mut HashMap<String, Kind>,
+        bind: bool,
+    ) -> Result<Kind, KindError> {
+        match vars.get(name) {
+            Some(kind) => Ok(kind.clone()),
+            None if bind => {
+                let kind = self.fresh();
+                vars.insert(name.to_string(), kind.clone());
+                Ok(kind)
+            }
+            None => Err((Some(pos), format!("The type variable `{name}` is not a parameter."))),
+        }
+    }
+}
+
+// State of Tarjan's strongly connected components algorithm over the call graph
+struct Tarjan<'e> {
+    edges: &'e [Vec<usize>],
+    index: Vec<Option<usize>>,
+    lowlink: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    counter: usize,
+    groups: Vec<Vec<usize>>,
+}
+
+impl Tarjan<'_> {
+    fn visit(&mut self, node: usize) {
+        self.index[node] = Some(self.counter);
+        self.lowlink[node] = self.counter;
+        self.counter += 1;
+        self.stack.push(node);
+        self.on_stack[node] = true;
+
+        for &next in self.edges[node].iter() {
+            match self.index[next] {
+                None => {
+                    self.visit(next);
+                    self.lowlink[node] = self.lowlink[node].min(self.lowlink[next]);
+                }
+                Some(index) if self.on_stack[next] => {
+                    self.lowlink[node] = self.lowlink[node].min(index);
+                }
+                _ => {}
             }
         }
-        println!("Set: {:?}", set);
-        set
+
+        if Some(self.lowlink[node]) == self.index[node] {
+            let mut group = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                self.on_stack[member] = false;
+                group.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            group.sort();
+            self.groups.push(group);
+        }
     }
-    */
+}
 
+impl Type {
+    // Writes the type, naming each variable with `names` when it has an entry.
+    fn pretty(&self, names: &HashMap<usize, String>) -> String {
+        match self {
+            Type::Int => "Int".to_string(),
+            Type::Str => "Str".to_string(),
+            Type::Bool => "Bool".to_string(),
+            Type::Whatever => "?".to_string(),
+            Type::Invalid => "Invalid".to_string(),
+            Type::Var(v) => names.get(v).cloned().unwrap_or_else(|| format!("'t{v}")),
+            // The effects are written after the result, as in `Str -> {} ! IO`
+            Type::Joined { i, o, eff } => {
+                let i = match **i {
+                    Type::Joined { .. } => format!("({})", i.pretty(names)),
+                    _ => i.pretty(names),
+                };
+                match eff.effect_labels().as_slice() {
+                    [] => format!("{i} -> {}", o.pretty(names)),
+                    labels => format!("{i} -> {} ! {}", o.pretty(names), labels.join(", ")),
+                }
+            }
+            Type::Record(row) => match **row {
+                Type::RowEmpty => "{}".to_string(),
+                _ => format!("{{ {} }}", row.pretty(names)),
+            },
+            Type::RowEmpty => String::new(),
+            Type::RowExtend { label, field, rest } => {
+                let field = format!("{label}: {}", field.pretty(names));
+                match &**rest {
+                    Type::RowEmpty => field,
+                    Type::RowExtend { .. } => format!("{field}, {}", rest.pretty(names)),
+                    _ => format!("{field} | {}", rest.pretty(names)),
+                }
+            }
+            Type::List(element) | Type::Array(element) => {
+                let name = if matches!(self, Type::List(_)) { "List" } else { "Array" };
+                match **element {
+                    Type::Joined { .. } | Type::List(_) | Type::Array(_) => format!("{name} ({})", element.pretty(names)),
+                    _ => format!("{name} {}", element.pretty(names)),
+                }
+            }
+            Type::Matrix => "Matrix".to_string(),
+            Type::Tuple(components) => {
+                let components: Vec<String> = components.iter().map(|c| c.pretty(names)).collect();
+                format!("({})", components.join(", "))
+            }
+        }
+    }
+}
+
+impl Type {
+    // The annotation of the type, unknown types being named type variables, `pos` being the
+    // position of the expression it is the type of
+    fn to_type_expr(&self, pos: (usize, usize)) -> TypeExpr {
+        let named = |name: String| TypeExpr::Named {
+            name,
+            args: Vec::new(),
+            pos,
+        };
+        match self {
+            Type::Int => named("Int".to_string()),
+            Type::Str => named("Str".to_string()),
+            Type::Bool => named("Bool".to_string()),
+            Type::Var(v) => named(format!("t{v}")),
+            Type::Joined { i, o, .. } => TypeExpr::Joined(Box::new(i.to_type_expr(pos)), Box::new(o.to_type_expr(pos))),
+            Type::Record(row) => {
+                let mut fields = Vec::new();
+                let mut row = &**row;
+                while let Type::RowExtend { label, field, rest } = row {
+                    fields.push((label.clone(), field.to_type_expr(pos)));
+                    row = rest;
+                }
+                let rest = match row {
+                    Type::Var(v) => Some(format!("t{v}")),
+                    _ => None,
+                };
+                TypeExpr::Record { fields, rest }
+            }
+            Type::List(element) | Type::Array(element) => TypeExpr::Named {
+                name: if matches!(self, Type::List(_)) { "List" } else { "Array" }.to_string(),
+                args: vec![element.to_type_expr(pos)],
+                pos,
+            },
+            Type::Matrix => named("Matrix".to_string()),
+            Type::Tuple(components) => TypeExpr::Tuple(components.iter().map(|c| c.to_type_expr(pos)).collect()),
+            _ => TypeExpr::Dynamic,
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pretty(&HashMap::new()))
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Star => write!(f, "*"),
+            Kind::Row => write!(f, "Row"),
+            Kind::Arrow(a, b) if matches!(**a, Kind::Arrow(..)) => write!(f, "({a}) -> {b}"),
+            Kind::Arrow(a, b) => write!(f, "{a} -> {b}"),
+            Kind::Var(v) => write!(f, "'k{v}"),
+        }
+    }
+}
+
+impl Display for TypeScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Quantified variables are named 'a, 'b, ... in order of appearance
+        let mut vars = Vec::new();
+        self.1.vars_in_order(&mut vars);
+        let names: HashMap<usize, String> = vars
+            .iter()
+            .filter(|v| self.0.contains(v))
+            .enumerate()
+            .map(|(n, v)| (*v, format!("'{}", (b'a' + (n % 26) as u8) as char)))
+            .collect();
+        write!(f, "{}", self.1.pretty(&names))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn infer(program: &str) -> Result<Vec<(String, String)>, TypeError> {
+        let ast = Parser::new(&mut Lexer::new(program)).program().unwrap();
+        let gamma = TypeChecker::new(&ast).init()?;
+        Ok(gamma.into_iter().map(|(id, scheme)| (id, scheme.to_string())).collect())
+    }
+
+    #[test]
+    fn mutual_recursion_and_forward_calls() {
+        let types = infer(
+            r#"
+            lam main -> if is_even(10) ? id("even") : id("odd");
+            lam is_even :: n -> if n == 0 ? n == n : is_odd(n - 1);
+            lam is_odd :: n -> if n == 0 ? n != n : is_even(n - 1);
+            lam id :: x -> x;
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            types,
+            vec![
+                ("id".to_owned(), "'a -> 'a".to_owned()),
+                ("is_even".to_owned(), "Int -> Bool".to_owned()),
+                ("is_odd".to_owned(), "Int -> Bool".to_owned()),
+                ("main".to_owned(), "Str".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn holes_report_expected_type_and_scope() {
+        let ast = Parser::new(&mut Lexer::new("lam f :: n, s -> if n <= 1 ? ?base : _;"))
+            .program()
+            .unwrap();
+        let mut checker = TypeChecker::new(&ast);
+        checker.init().unwrap();
+
+        let reports = checker.hole_reports();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].message, "hole `?base` has type 'a");
+        assert_eq!(reports[0].pos, Some((1, 30)));
+        assert_eq!(reports[0].notes, vec!["n : Int", "s : 'b"]);
+        assert_eq!(reports[1].message, "hole `_` has type 'a");
+    }
+
+    #[test]
+    fn row_polymorphic_records() {
+        let types = infer(
+            r#"
+            lam name_of :: p: { name: Str | r } -> p.name;
+            lam older :: p -> { p | age = p.age + 1 };
+            lam main -> name_of(older({ name = "Karm", age = 3, admin = 1 == 1 }));
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            types,
+            vec![
+                ("main".to_owned(), "Str".to_owned()),
+                ("name_of".to_owned(), "{ name: Str | 'a } -> Str".to_owned()),
+                ("older".to_owned(), "{ age: Int | 'a } -> { age: Int | 'a }".to_owned()),
+            ]
+        );
+
+        let TypeError(msg) = infer(
+            r#"
+            lam name_of :: p: { name: Str | r } -> p.name;
+            lam main -> name_of({ age = 3 });
+            "#,
+        )
+        .unwrap_err();
+        assert!(msg.contains("no field `name`"), "{msg}");
+
+        assert!(infer(r#"lam f :: p: { name: Str } -> p; lam main -> f({ name = "a", age = 3 });"#).is_err());
+    }
+
+    #[test]
+    fn errors_name_the_recursive_group() {
+        let TypeError(msg) = infer(
+            r#"
+            lam ping :: n -> pong(n) + 1;
+            lam pong :: n -> if ping(n) ? 1 : 2;
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(msg.starts_with("In recursive group `ping`, `pong`:"), "{msg}");
+    }
+
+    #[test]
+    fn gradual_typing_inserts_casts() {
+        let program = r#"
+            lam succ :: n -> n + 1;
+            lam twice :: f: (Int -> Int), n: Int : Int -> f(f(n));
+            lam four : Int -> twice(succ, 2);
+        "#;
+        let ast = Parser::new(&mut Lexer::new(program)).program().unwrap();
+        let mut checker = TypeChecker::new(&ast).gradual();
+        let gamma = checker.init().unwrap();
+        assert_eq!(gamma["succ"].to_string(), "? -> Int");
+
+        // `n` is cast to Int where `+` is called, `succ` to Int -> Int where `twice` is called
+        let Program(defs) = checker.insert_casts();
+        let Expr::LamDef { operation, .. } = &defs[0] else { unreachable!() };
+        let Expr::LamCall { params, .. } = &**operation else { unreachable!() };
+        assert!(matches!(&params[0], Expr::Cast { target: TypeExpr::Named { name, .. }, pos: (2, 32), .. } if name == "Int"));
+        let Expr::LamDef { operation, .. } = &defs[2] else { unreachable!() };
+        let Expr::LamCall { params, .. } = &**operation else { unreachable!() };
+        assert!(matches!(&params[0], Expr::Cast { target: TypeExpr::Joined(..), .. }));
+        assert!(matches!(&params[1], Expr::Literal(_)));
+
+        // Static types are still checked statically
+        assert!(infer("lam f :: x: ? -> x; lam g : Int -> f(1) + 1; lam h : Int -> g + \"a\";").is_err());
+        assert!(infer("lam f :: x: ? -> x; lam g : Int -> f(1) + 1;").is_ok());
+    }
+
+    #[test]
+    fn io_effects() {
+        let program = r#"
+            lam greet :: name: Str -> print(name);
+            lam twice :: f: (Int -> Int), n: Int : Int -> f(f(n));
+            lam noisy :: n: Int : Int -> { log = print("noisy"), n = n }.n;
+            lam succ :: n: Int : Int -> n + 1;
+            lam pure quiet :: n: Int : Int -> twice(succ, n);
+            lam loud :: n: Int : Int -> twice(noisy, n);
+            lam pure apply :: f: (Str -> {}), s: Str -> f(s);
+        "#;
+        let types: BTreeMap<String, String> = infer(program).unwrap().into_iter().collect();
+        assert_eq!(types["greet"], "Str -> {} ! IO");
+        assert_eq!(types["twice"], "(Int -> Int) -> Int -> Int");
+        assert_eq!(types["quiet"], "Int -> Int");
+        assert_eq!(types["loud"], "Int -> Int ! IO");
+
+        let TypeError(msg) = infer(&format!("{program} lam pure shout :: s: Str -> greet(s);")).unwrap_err();
+        assert_eq!(msg, "In `shout`: The function is declared pure but performs IO.");
+        assert!(infer(&format!("{program} lam pure bad :: n: Int : Int -> twice(noisy, n);")).is_err());
+        assert!(infer(&format!("{program} lam bad :: s: Str -> apply(print, s);")).is_err());
+    }
+
+    #[test]
+    fn errors_explain_their_constraints() {
+        let explain = |program: &str| {
+            let ast = Parser::new(&mut Lexer::new(program)).program().unwrap();
+            let mut checker = TypeChecker::new(&ast);
+            let err = checker.init().unwrap_err();
+            checker.explain(&err)
+        };
+
+        let diagnostic = explain("lam length :: s: Str : Int -> 3;\nlam f :: n -> n + length(n);");
+        assert_eq!(diagnostic.pos, Some((2, 17)));
+        assert_eq!(
+            diagnostic.labels,
+            vec![((2, 19), "`n` is Str because of the call to `length`".to_string())]
+        );
+
+        // Whichever use comes first, and whether the type comes from a call or an annotation
+        for (program, pos, label) in [
+            ("lam f :: n -> length(n) + n;", (1, 25), ((1, 15), "`n` is Str because of the call to `length`")),
+            ("lam f :: n -> (concat(n, \"a\"), n + 1);", (1, 34), ((1, 16), "`n` is Str because of the call to `concat`")),
+            ("lam f :: n -> concat(n, \"a\") == int_to_str(n + 1);", (1, 46), ((1, 15), "`n` is Str because of the call to `concat`")),
+            ("lam f :: n: Str -> n + 1;", (1, 22), ((1, 13), "`n` is Str because of the annotation of `n`")),
+        ] {
+            let diagnostic = explain(program);
+            assert_eq!(diagnostic.pos, Some(pos), "{program}");
+            assert_eq!(diagnostic.labels, vec![(label.0, label.1.to_string())], "{program}");
+        }
+
+        // The condition and the branches of an if
+        let diagnostic = explain("lam f :: n -> if n ? 1 : 2;\nlam g :: n -> if n + 1 ? 1 : 2;");
+        assert!(diagnostic.message.ends_with("The condition of an if must be a Bool: Expected type Bool, got Int"));
+        assert_eq!(diagnostic.pos, Some((2, 20)));
+        let diagnostic = explain("lam f :: n -> if n > 1 ? n : concat(\"a\", \"b\");");
+        assert_eq!(diagnostic.pos, Some((1, 30)));
+        assert_eq!(
+            diagnostic.labels,
+            vec![
+                ((1, 26), "the result of the if is Int because of the first branch of the if".to_string()),
+                ((1, 30), "the result of `concat` is Str because of the call to `concat`".to_string()),
+            ]
+        );
+
+        // A lambda defined twice, at its second definition
+        let diagnostic = explain("lam f :: n -> n;\nlam f :: n -> n + 1;");
+        assert!(diagnostic.message.ends_with("`f` is defined more than once."));
+        assert_eq!(diagnostic.pos, Some((2, 5)));
+
+        let diagnostic = explain("lam loop :: g -> g(g);");
+        assert!(diagnostic.message.ends_with("Cannot construct the infinite type 'a = 'a -> 'b"));
+        assert_eq!(diagnostic.notes, vec!["`g` would have to be 'a -> 'b, which contains 'a itself"]);
+    }
+
+    #[test]
+    fn kinds_of_type_declarations() {
+        let program = r#"
+            type Pair a b = { fst: a, snd: b };
+            type Apply f a = f a;
+            type Ext r = { tag: Int | r };
+            lam swap :: p: Pair a b : Pair b a -> { fst = p.snd, snd = p.fst };
+            lam first :: p: Apply (Pair Int) Str : Int -> p.fst;
+            lam tag :: e: Ext r : Int -> e.tag;
+        "#;
+        let ast = Parser::new(&mut Lexer::new(program)).program().unwrap();
+        let mut checker = TypeChecker::new(&ast);
+        let gamma = checker.init().unwrap();
+        assert_eq!(checker.kind_of("Pair").unwrap().to_string(), "* -> * -> *");
+        assert_eq!(checker.kind_of("Apply").unwrap().to_string(), "(* -> *) -> * -> *");
+        assert_eq!(checker.kind_of("Ext").unwrap().to_string(), "Row -> *");
+        assert_eq!(gamma["swap"].to_string(), "{ fst: 'a, snd: 'b } -> { fst: 'b, snd: 'a }");
+        assert_eq!(gamma["first"].to_string(), "{ fst: Int, snd: Str } -> Int");
+
+        for (bad, msg) in [
+            ("lam f :: p: Pair Int Int Int -> 1;", "`Pair` of kind * -> * -> * cannot be applied to 3 type argument(s)."),
+            ("lam f :: p: Pair -> 1;", "`Pair` is missing type arguments, it has the kind * -> * -> *."),
+            ("type Loop a = Loop a -> a;", "The type `Loop` is defined in terms of itself."),
+            ("type Self f = f f;", "`f` cannot be applied to itself, its kind would be infinite."),
+            ("type Ext2 r = { tag: r | r };", "`r` ends a record, but is used as a type elsewhere."),
+        ] {
+            let TypeError(err) = infer(&format!("{program} {bad}")).unwrap_err();
+            assert!(err.ends_with(msg), "{err}");
+        }
+    }
 }
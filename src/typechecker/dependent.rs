@@ -0,0 +1,961 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::rc::Rc;
+
+use crate::bigint::Int;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::errors::{SyntaxError, TypeError};
+use crate::lexer::Lexer;
+use crate::parser::{Expr, LamStyle, Literal, Parser, Program, TypeExpr};
+
+// Transport and congruence, derived from J
+const STD_PATHS: &str = include_str!("../../std/paths.kr");
+
+/*
+    Experimental dependently typed core, selected with `karm --dependent build`.
+    Types are terms: `Type`, `Type1`, ... form a hierarchy of universes, `(x: A) -> B` and
+    `(x: A) * B` are the Pi and Sigma types. Terms are checked bidirectionally and compared by
+    normalization (evaluation to values, then comparison of the values).
+
+    A definition must annotate all its parameters and can only use the definitions above it,
+    as unrestricted recursion would make checking undecidable.
+
+    Equality is the identity type `Id(A, x, y)`, inhabited by `refl(x)` when x and y normalize to
+    the same value and eliminated by path induction `J`. The definitions of std/paths.kr are
+    loaded before the program.
+*/
+
+// Terms of the core, elaborated from the Karm expressions
+#[derive(Clone, Debug)]
+enum Term {
+    Var(String),
+    // The variable bound at this depth, produced by quoting a value and shown by its name. Unlike
+    // `Var`, it cannot be captured by a binder of the same name once evaluated again.
+    Bound(usize, String),
+    Universe(usize),
+    Int,
+    Str,
+    Bool,
+    IntLit(Int),
+    StrLit(String),
+    // Only produced by evaluation, there is no syntax for booleans yet
+    BoolLit(bool),
+    Pi(String, Rc<Term>, Rc<Term>),
+    Sigma(String, Rc<Term>, Rc<Term>),
+    Lam(String, Rc<Term>),
+    App(Rc<Term>, Rc<Term>),
+    Pair(Rc<Term>, Rc<Term>),
+    Fst(Rc<Term>),
+    Snd(Rc<Term>),
+    // Builtin binary operator, such as `+` or `==`
+    Op(String, Rc<Term>, Rc<Term>),
+    If(Rc<Term>, Rc<Term>, Rc<Term>),
+    Hole(Option<String>, (usize, usize)),
+    // Id(A, x, y), the type of the proofs that x and y are equal
+    Id(Rc<Term>, Rc<Term>, Rc<Term>),
+    // refl(x) : Id(A, x, x)
+    Refl(Rc<Term>),
+    // Path induction, J(A, P, d, x, y, p) : P(x, y, p) given P : (x: A) -> (y: A) -> Id(A, x, y) -> Type
+    // and d : (x: A) -> P(x, x, refl(x))
+    J(Rc<[Term; 6]>),
+}
+
+// Terms evaluated as far as possible
+#[derive(Clone, Debug)]
+enum Value {
+    Universe(usize),
+    Int,
+    Str,
+    Bool,
+    IntLit(Int),
+    StrLit(String),
+    BoolLit(bool),
+    Pi(String, Rc<Value>, Closure),
+    Sigma(String, Rc<Value>, Closure),
+    Lam(String, Closure),
+    Pair(Rc<Value>, Rc<Value>),
+    Id(Rc<Value>, Rc<Value>, Rc<Value>),
+    Refl(Rc<Value>),
+    Neutral(Rc<Neutral>),
+}
+
+// Computations blocked on an unknown variable
+#[derive(Clone, Debug)]
+enum Neutral {
+    // A variable, identified by the depth at which it was bound
+    Var(usize, String),
+    App(Rc<Neutral>, Value),
+    Fst(Rc<Neutral>),
+    Snd(Rc<Neutral>),
+    Op(String, Value, Value),
+    If(Rc<Neutral>, Value, Value),
+    // J(A, P, d, x, y, p) blocked on the proof p
+    J(Rc<[Value; 5]>, Rc<Neutral>),
+}
+
+// A term waiting for the value of its binder
+#[derive(Clone, Debug)]
+struct Closure {
+    env: Env,
+    body: Rc<Term>,
+}
+
+type Env = Vec<(String, Value)>;
+
+// What is known while checking a term: the values and the types of the bound variables
+#[derive(Clone, Default)]
+struct Ctx {
+    env: Env,
+    types: Vec<(String, Value)>,
+}
+
+/// A checked toplevel definition
+struct Definition {
+    ty: Value,
+    value: Value,
+}
+
+/// The checker of the dependent core
+pub struct DependentChecker<'a> {
+    ast: &'a Program,
+    /// The definitions checked so far
+    globals: HashMap<String, Definition>,
+    /// The goals of the typed holes met so far
+    holes: Vec<Diagnostic>,
+}
+
+impl Ctx {
+    fn level(&self) -> usize {
+        self.env.len()
+    }
+
+    // Binds `name` of type `ty` to a fresh variable
+    fn bind(&self, name: &str, ty: Value) -> Ctx {
+        let mut ctx = self.clone();
+        let var = Value::Neutral(Rc::new(Neutral::Var(self.level(), name.to_string())));
+        ctx.env.push((name.to_string(), var));
+        ctx.types.push((name.to_string(), ty));
+        ctx
+    }
+}
+
+impl<'a> DependentChecker<'a> {
+    pub fn new(ast: &'a Program) -> Self {
+        DependentChecker {
+            ast,
+            globals: HashMap::new(),
+            holes: Vec::new(),
+        }
+    }
+
+    /// Checks the standard library, then the toplevel definitions in order, returning their types.
+    pub fn init(&mut self) -> Result<Vec<(String, String)>, TypeError> {
+        let std = Parser::new(&mut Lexer::new(STD_PATHS))
+            .dependent()
+            .program()
+            .map_err(|SyntaxError(msg, _)| TypeError(format!("In std/paths.kr: {msg}")))?;
+        self.check_program(&std)
+            .map_err(|TypeError(msg)| TypeError(format!("In std/paths.kr: {msg}")))?;
+        self.check_program(self.ast)
+    }
+
+    fn check_program(&mut self, program: &Program) -> Result<Vec<(String, String)>, TypeError> {
+        let mut types = Vec::new();
+        for expr in &program.0 {
+            if let Expr::LamDef {
+                ident,
+                params,
+                annotations,
+                ret,
+                operation,
+                ..
+            } = expr
+            {
+                if self.globals.contains_key(ident) {
+                    return Err(TypeError(format!("`{ident}` is already defined.")));
+                }
+                let def = self
+                    .check_definition(params, annotations, ret, operation)
+                    .map_err(|TypeError(msg)| TypeError(format!("In `{ident}`: {msg}")))?;
+                types.push((ident.clone(), self.quote(0, &def.ty).to_string()));
+                self.globals.insert(ident.clone(), def);
+            }
+        }
+        Ok(types)
+    }
+
+    /// Reports the expected type of every typed hole and the variables in scope at its location.
+    pub fn hole_reports(&self) -> Vec<Diagnostic> {
+        self.holes.clone()
+    }
+
+    fn check_definition(
+        &mut self,
+        params: &[String],
+        annotations: &[Option<TypeExpr>],
+        ret: &Option<TypeExpr>,
+        body: &Expr,
+    ) -> Result<Definition, TypeError> {
+        let mut ctx = Ctx::default();
+        let mut domains = Vec::new();
+        for (param, annotation) in params.iter().zip(annotations) {
+            let Some(TypeExpr::Term(annotation)) = annotation else {
+                return Err(TypeError(format!("The parameter `{param}` needs a type annotation.")));
+            };
+            let domain = self.elaborate(annotation)?;
+            self.check_type(&ctx, &domain)?;
+            let domain_value = self.eval(&ctx.env, &domain);
+            ctx = ctx.bind(param, domain_value);
+            domains.push(domain);
+        }
+
+        let body = self.elaborate(body)?;
+        let codomain = match ret {
+            Some(TypeExpr::Term(ret)) => {
+                let codomain = self.elaborate(ret)?;
+                self.check_type(&ctx, &codomain)?;
+                let expected = self.eval(&ctx.env, &codomain);
+                self.check(&ctx, &body, &expected)?;
+                codomain
+            }
+            _ => {
+                let ty = self.synth(&ctx, &body)?;
+                self.quote(ctx.level(), &ty)
+            }
+        };
+
+        let ty = params
+            .iter()
+            .zip(domains)
+            .rev()
+            .fold(codomain, |acc, (param, domain)| {
+                Term::Pi(param.clone(), Rc::new(domain), Rc::new(acc))
+            });
+        let value = params
+            .iter()
+            .rev()
+            .fold(body, |acc, param| Term::Lam(param.clone(), Rc::new(acc)));
+
+        Ok(Definition {
+            ty: self.eval(&Vec::new(), &ty),
+            value: self.eval(&Vec::new(), &value),
+        })
+    }
+
+    // Translates a Karm expression into a core term
+    fn elaborate(&self, expr: &Expr) -> Result<Term, TypeError> {
+        let rc = |e: &Expr| self.elaborate(e).map(Rc::new);
+        Ok(match expr {
+            Expr::Var(id, _) => match id.as_str() {
+                "Type" => Term::Universe(0),
+                "Int" => Term::Int,
+                "Str" => Term::Str,
+                "Bool" => Term::Bool,
+                _ if id.starts_with("Type") && id[4..].parse::<usize>().is_ok() => Term::Universe(id[4..].parse().unwrap()),
+                _ => Term::Var(id.clone()),
+            },
+            Expr::Literal(Literal::Int(n)) => Term::IntLit(n.clone()),
+            Expr::Literal(Literal::Str(s)) => Term::StrLit(s.clone()),
+            Expr::LamCall {
+                ident,
+                style: LamStyle::Infix,
+                params,
+                ..
+            } if params.len() == 2 => Term::Op(ident.clone(), rc(&params[0])?, rc(&params[1])?),
+            Expr::LamCall { ident, params, .. } => match (ident.as_str(), params.as_slice()) {
+                ("pair", [a, b]) => Term::Pair(rc(a)?, rc(b)?),
+                ("fst", [p]) => Term::Fst(rc(p)?),
+                ("snd", [p]) => Term::Snd(rc(p)?),
+                ("Id", [a, x, y]) => Term::Id(rc(a)?, rc(x)?, rc(y)?),
+                ("refl", [x]) => Term::Refl(rc(x)?),
+                // Extra arguments are given to the result of J, which is often a function
+                ("J", [a, p, d, x, y, proof, rest @ ..]) => {
+                    let mut term = Term::J(Rc::new([
+                        self.elaborate(a)?,
+                        self.elaborate(p)?,
+                        self.elaborate(d)?,
+                        self.elaborate(x)?,
+                        self.elaborate(y)?,
+                        self.elaborate(proof)?,
+                    ]));
+                    for param in rest {
+                        term = Term::App(Rc::new(term), rc(param)?);
+                    }
+                    term
+                }
+                ("Id" | "refl" | "J", _) => {
+                    return Err(TypeError(format!("`{ident}` is not given the right number of arguments.")))
+                }
+                _ => {
+                    let mut term = Term::Var(ident.clone());
+                    for param in params {
+                        term = Term::App(Rc::new(term), rc(param)?);
+                    }
+                    term
+                }
+            },
+            Expr::If { cond, then, alter, .. } => Term::If(rc(cond)?, rc(then)?, rc(alter)?),
+            Expr::Pi {
+                binder,
+                domain,
+                codomain,
+            } => Term::Pi(binder.clone(), rc(domain)?, rc(codomain)?),
+            Expr::Sigma { binder, fst, snd } => Term::Sigma(binder.clone(), rc(fst)?, rc(snd)?),
+            Expr::Hole { name, pos } => Term::Hole(name.clone(), *pos),
+            _ => return Err(TypeError("This expression is not part of the dependent core.".to_owned())),
+        })
+    }
+
+    fn eval(&self, env: &Env, term: &Term) -> Value {
+        let closure = |body: &Rc<Term>| Closure {
+            env: env.clone(),
+            body: body.clone(),
+        };
+        match term {
+            Term::Var(id) => match env.iter().rev().find(|(name, _)| name == id) {
+                Some((_, value)) => value.clone(),
+                None => self.globals[id].value.clone(),
+            },
+            Term::Bound(level, _) => env[*level].1.clone(),
+            Term::Universe(n) => Value::Universe(*n),
+            Term::Int => Value::Int,
+            Term::Str => Value::Str,
+            Term::Bool => Value::Bool,
+            Term::IntLit(n) => Value::IntLit(n.clone()),
+            Term::StrLit(s) => Value::StrLit(s.clone()),
+            Term::BoolLit(b) => Value::BoolLit(*b),
+            Term::Pi(x, a, b) => Value::Pi(x.clone(), Rc::new(self.eval(env, a)), closure(b)),
+            Term::Sigma(x, a, b) => Value::Sigma(x.clone(), Rc::new(self.eval(env, a)), closure(b)),
+            Term::Lam(x, body) => Value::Lam(x.clone(), closure(body)),
+            Term::App(f, a) => self.apply(self.eval(env, f), self.eval(env, a)),
+            Term::Pair(a, b) => Value::Pair(Rc::new(self.eval(env, a)), Rc::new(self.eval(env, b))),
+            Term::Fst(p) => Self::fst(self.eval(env, p)),
+            Term::Snd(p) => Self::snd(self.eval(env, p)),
+            Term::Id(a, x, y) => Value::Id(
+                Rc::new(self.eval(env, a)),
+                Rc::new(self.eval(env, x)),
+                Rc::new(self.eval(env, y)),
+            ),
+            Term::Refl(x) => Value::Refl(Rc::new(self.eval(env, x))),
+            Term::J(args) => {
+                let [a, p, d, x, y, proof] = &**args;
+                match self.eval(env, proof) {
+                    // J(A, P, d, x, x, refl(x)) computes to d(x)
+                    Value::Refl(_) => self.apply(self.eval(env, d), self.eval(env, x)),
+                    Value::Neutral(n) => {
+                        let args = [a, p, d, x, y].map(|t| self.eval(env, t));
+                        Value::Neutral(Rc::new(Neutral::J(Rc::new(args), n)))
+                    }
+                    _ => unreachable!("ill-typed path induction"),
+                }
+            }
+            Term::Op(op, a, b) => Self::operator(op, self.eval(env, a), self.eval(env, b)),
+            Term::If(c, t, e) => match self.eval(env, c) {
+                Value::BoolLit(b) => self.eval(env, if b { t } else { e }),
+                Value::Neutral(n) => Value::Neutral(Rc::new(Neutral::If(n, self.eval(env, t), self.eval(env, e)))),
+                _ => unreachable!("ill-typed condition"),
+            },
+            Term::Hole(..) => Value::Neutral(Rc::new(Neutral::Var(usize::MAX, "_".to_string()))),
+        }
+    }
+
+    fn instantiate(&self, closure: &Closure, binder: &str, arg: Value) -> Value {
+        let mut env = closure.env.clone();
+        env.push((binder.to_string(), arg));
+        self.eval(&env, &closure.body)
+    }
+
+    fn apply(&self, f: Value, arg: Value) -> Value {
+        match f {
+            Value::Lam(x, body) => self.instantiate(&body, &x, arg),
+            Value::Neutral(n) => Value::Neutral(Rc::new(Neutral::App(n, arg))),
+            _ => unreachable!("ill-typed application"),
+        }
+    }
+
+    fn fst(p: Value) -> Value {
+        match p {
+            Value::Pair(a, _) => (*a).clone(),
+            Value::Neutral(n) => Value::Neutral(Rc::new(Neutral::Fst(n))),
+            _ => unreachable!("ill-typed projection"),
+        }
+    }
+
+    fn snd(p: Value) -> Value {
+        match p {
+            Value::Pair(_, b) => (*b).clone(),
+            Value::Neutral(n) => Value::Neutral(Rc::new(Neutral::Snd(n))),
+            _ => unreachable!("ill-typed projection"),
+        }
+    }
+
+    // Builtin operators compute on literals and are stuck otherwise
+    fn operator(op: &str, a: Value, b: Value) -> Value {
+        match (op, &a, &b) {
+            ("+", Value::IntLit(x), Value::IntLit(y)) => Value::IntLit(x.add(y)),
+            ("-", Value::IntLit(x), Value::IntLit(y)) => Value::IntLit(x.sub(y)),
+            ("*", Value::IntLit(x), Value::IntLit(y)) => Value::IntLit(x.mul(y)),
+            ("/", Value::IntLit(x), Value::IntLit(y)) if x.div(y).is_some() => Value::IntLit(x.div(y).unwrap()),
+            ("%", Value::IntLit(x), Value::IntLit(y)) if x.rem(y).is_some() => Value::IntLit(x.rem(y).unwrap()),
+            ("<=", Value::IntLit(x), Value::IntLit(y)) => Value::BoolLit(x <= y),
+            ("<", Value::IntLit(x), Value::IntLit(y)) => Value::BoolLit(x < y),
+            (">", Value::IntLit(x), Value::IntLit(y)) => Value::BoolLit(x > y),
+            (">=", Value::IntLit(x), Value::IntLit(y)) => Value::BoolLit(x >= y),
+            ("==", Value::IntLit(x), Value::IntLit(y)) => Value::BoolLit(x == y),
+            ("!=", Value::IntLit(x), Value::IntLit(y)) => Value::BoolLit(x != y),
+            ("==", Value::StrLit(x), Value::StrLit(y)) => Value::BoolLit(x == y),
+            ("!=", Value::StrLit(x), Value::StrLit(y)) => Value::BoolLit(x != y),
+            ("&&", Value::BoolLit(x), _) => if *x { b } else { a },
+            ("||", Value::BoolLit(x), _) => if *x { a } else { b },
+            _ => Value::Neutral(Rc::new(Neutral::Op(op.to_string(), a, b))),
+        }
+    }
+
+    // Reads a value back into a term, `level` being the number of bound variables
+    fn quote(&self, level: usize, value: &Value) -> Term {
+        let fresh = |x: &str| Value::Neutral(Rc::new(Neutral::Var(level, x.to_string())));
+        match value {
+            Value::Universe(n) => Term::Universe(*n),
+            Value::Int => Term::Int,
+            Value::Str => Term::Str,
+            Value::Bool => Term::Bool,
+            Value::IntLit(n) => Term::IntLit(n.clone()),
+            Value::StrLit(s) => Term::StrLit(s.clone()),
+            Value::BoolLit(b) => Term::BoolLit(*b),
+            Value::Pi(x, a, b) => Term::Pi(
+                x.clone(),
+                Rc::new(self.quote(level, a)),
+                Rc::new(self.quote(level + 1, &self.instantiate(b, x, fresh(x)))),
+            ),
+            Value::Sigma(x, a, b) => Term::Sigma(
+                x.clone(),
+                Rc::new(self.quote(level, a)),
+                Rc::new(self.quote(level + 1, &self.instantiate(b, x, fresh(x)))),
+            ),
+            Value::Lam(x, body) => Term::Lam(
+                x.clone(),
+                Rc::new(self.quote(level + 1, &self.instantiate(body, x, fresh(x)))),
+            ),
+            Value::Pair(a, b) => Term::Pair(Rc::new(self.quote(level, a)), Rc::new(self.quote(level, b))),
+            Value::Id(a, x, y) => Term::Id(
+                Rc::new(self.quote(level, a)),
+                Rc::new(self.quote(level, x)),
+                Rc::new(self.quote(level, y)),
+            ),
+            Value::Refl(x) => Term::Refl(Rc::new(self.quote(level, x))),
+            Value::Neutral(n) => self.quote_neutral(level, n),
+        }
+    }
+
+    fn quote_neutral(&self, level: usize, neutral: &Neutral) -> Term {
+        match neutral {
+            // The variable of a hole, which is not bound anywhere
+            Neutral::Var(usize::MAX, _) => Term::Hole(None, (0, 0)),
+            Neutral::Var(level, x) => Term::Bound(*level, x.clone()),
+            Neutral::App(f, a) => Term::App(Rc::new(self.quote_neutral(level, f)), Rc::new(self.quote(level, a))),
+            Neutral::Fst(p) => Term::Fst(Rc::new(self.quote_neutral(level, p))),
+            Neutral::Snd(p) => Term::Snd(Rc::new(self.quote_neutral(level, p))),
+            Neutral::J(args, proof) => {
+                let [a, p, d, x, y] = &**args;
+                Term::J(Rc::new([
+                    self.quote(level, a),
+                    self.quote(level, p),
+                    self.quote(level, d),
+                    self.quote(level, x),
+                    self.quote(level, y),
+                    self.quote_neutral(level, proof),
+                ]))
+            }
+            Neutral::Op(op, a, b) => Term::Op(op.clone(), Rc::new(self.quote(level, a)), Rc::new(self.quote(level, b))),
+            Neutral::If(c, t, e) => Term::If(
+                Rc::new(self.quote_neutral(level, c)),
+                Rc::new(self.quote(level, t)),
+                Rc::new(self.quote(level, e)),
+            ),
+        }
+    }
+
+    /// Definitional equality: both values have the same normal form, up to eta for functions and pairs.
+    fn conv(&self, level: usize, a: &Value, b: &Value) -> bool {
+        let fresh = |x: &str| Value::Neutral(Rc::new(Neutral::Var(level, x.to_string())));
+        match (a, b) {
+            (Value::Universe(i), Value::Universe(j)) => i == j,
+            (Value::Int, Value::Int) | (Value::Str, Value::Str) | (Value::Bool, Value::Bool) => true,
+            (Value::IntLit(x), Value::IntLit(y)) => x == y,
+            (Value::StrLit(x), Value::StrLit(y)) => x == y,
+            (Value::BoolLit(x), Value::BoolLit(y)) => x == y,
+            (Value::Pi(x, a1, b1), Value::Pi(y, a2, b2)) | (Value::Sigma(x, a1, b1), Value::Sigma(y, a2, b2)) => {
+                self.conv(level, a1, a2)
+                    && self.conv(
+                        level + 1,
+                        &self.instantiate(b1, x, fresh(x)),
+                        &self.instantiate(b2, y, fresh(y)),
+                    )
+            }
+            (Value::Lam(x, body), other) | (other, Value::Lam(x, body)) => self.conv(
+                level + 1,
+                &self.instantiate(body, x, fresh(x)),
+                &self.apply(other.clone(), fresh(x)),
+            ),
+            (Value::Pair(a1, b1), other) | (other, Value::Pair(a1, b1)) => {
+                self.conv(level, a1, &Self::fst(other.clone())) && self.conv(level, b1, &Self::snd(other.clone()))
+            }
+            (Value::Id(a1, x1, y1), Value::Id(a2, x2, y2)) => {
+                self.conv(level, a1, a2) && self.conv(level, x1, x2) && self.conv(level, y1, y2)
+            }
+            (Value::Refl(x1), Value::Refl(x2)) => self.conv(level, x1, x2),
+            (Value::Neutral(n1), Value::Neutral(n2)) => self.conv_neutral(level, n1, n2),
+            _ => false,
+        }
+    }
+
+    fn conv_neutral(&self, level: usize, a: &Neutral, b: &Neutral) -> bool {
+        match (a, b) {
+            (Neutral::Var(i, _), Neutral::Var(j, _)) => i == j && *i != usize::MAX,
+            (Neutral::App(f1, a1), Neutral::App(f2, a2)) => self.conv_neutral(level, f1, f2) && self.conv(level, a1, a2),
+            (Neutral::Fst(p1), Neutral::Fst(p2)) | (Neutral::Snd(p1), Neutral::Snd(p2)) => self.conv_neutral(level, p1, p2),
+            (Neutral::Op(o1, a1, b1), Neutral::Op(o2, a2, b2)) => {
+                o1 == o2 && self.conv(level, a1, a2) && self.conv(level, b1, b2)
+            }
+            (Neutral::If(c1, t1, e1), Neutral::If(c2, t2, e2)) => {
+                self.conv_neutral(level, c1, c2) && self.conv(level, t1, t2) && self.conv(level, e1, e2)
+            }
+            (Neutral::J(args1, p1), Neutral::J(args2, p2)) => {
+                self.conv_neutral(level, p1, p2) && args1.iter().zip(args2.iter()).all(|(a, b)| self.conv(level, a, b))
+            }
+            _ => false,
+        }
+    }
+
+    // Cumulativity: a type of Type_i is also a type of Type_j for i <= j
+    fn subtype(&self, level: usize, a: &Value, b: &Value) -> bool {
+        let fresh = |x: &str| Value::Neutral(Rc::new(Neutral::Var(level, x.to_string())));
+        match (a, b) {
+            (Value::Universe(i), Value::Universe(j)) => i <= j,
+            (Value::Pi(x, a1, b1), Value::Pi(y, a2, b2)) => {
+                self.conv(level, a1, a2)
+                    && self.subtype(
+                        level + 1,
+                        &self.instantiate(b1, x, fresh(x)),
+                        &self.instantiate(b2, y, fresh(y)),
+                    )
+            }
+            _ => self.conv(level, a, b),
+        }
+    }
+
+    fn show(&self, ctx: &Ctx, value: &Value) -> String {
+        self.quote(ctx.level(), value).to_string()
+    }
+
+    fn check(&mut self, ctx: &Ctx, term: &Term, expected: &Value) -> Result<(), TypeError> {
+        match (term, expected) {
+            (Term::Lam(x, body), Value::Pi(y, a, b)) => {
+                let inner = ctx.bind(x, (**a).clone());
+                let codomain = self.instantiate(b, y, inner.env.last().unwrap().1.clone());
+                self.check(&inner, body, &codomain)
+            }
+            (Term::Pair(a, b), Value::Sigma(x, fst, snd)) => {
+                self.check(ctx, a, fst)?;
+                let snd = self.instantiate(snd, x, self.eval(&ctx.env, a));
+                self.check(ctx, b, &snd)
+            }
+            (Term::Refl(t), Value::Id(a, x, y)) => {
+                self.check(ctx, t, a)?;
+                let t = self.eval(&ctx.env, t);
+                if self.conv(ctx.level(), &t, x) && self.conv(ctx.level(), &t, y) {
+                    return Ok(());
+                }
+                Err(TypeError(format!(
+                    "{} does not prove {}, as {} and {} are not equal",
+                    term,
+                    self.show(ctx, expected),
+                    self.show(ctx, x),
+                    self.show(ctx, y)
+                )))
+            }
+            (Term::If(c, t, e), _) => {
+                self.check(ctx, c, &Value::Bool)?;
+                self.check(ctx, t, expected)?;
+                self.check(ctx, e, expected)
+            }
+            (Term::Hole(name, pos), _) => {
+                let name = match name {
+                    Some(name) => format!("`?{name}`"),
+                    None => "`_`".to_string(),
+                };
+                let context = ctx
+                    .types
+                    .iter()
+                    .enumerate()
+                    .map(|(level, (x, ty))| format!("{x} : {}", self.quote(level, ty)))
+                    .collect();
+                let goal = format!("hole {name} has type {}", self.show(ctx, expected));
+                self.holes
+                    .push(Diagnostic::new(Severity::Info, goal, Some(*pos)).with_notes(context));
+                Ok(())
+            }
+            _ => {
+                let actual = self.synth(ctx, term)?;
+                if self.subtype(ctx.level(), &actual, expected) {
+                    Ok(())
+                } else {
+                    Err(TypeError(format!(
+                        "Expected type {}, got {} for {}",
+                        self.show(ctx, expected),
+                        self.show(ctx, &actual),
+                        term
+                    )))
+                }
+            }
+        }
+    }
+
+    // Checks that `term` is a type, returning the universe it lives in
+    fn check_type(&mut self, ctx: &Ctx, term: &Term) -> Result<usize, TypeError> {
+        match self.synth(ctx, term)? {
+            Value::Universe(n) => Ok(n),
+            other => Err(TypeError(format!("{term} is not a type, it has type {}", self.show(ctx, &other)))),
+        }
+    }
+
+    fn synth(&mut self, ctx: &Ctx, term: &Term) -> Result<Value, TypeError> {
+        match term {
+            Term::Var(id) => match ctx.types.iter().rev().find(|(name, _)| name == id) {
+                Some((_, ty)) => Ok(ty.clone()),
+                None => match self.globals.get(id) {
+                    Some(def) => Ok(def.ty.clone()),
+                    None if self.is_toplevel(id) => Err(TypeError(format!(
+                        "`{id}` is not defined yet: definitions can only use the ones above them."
+                    ))),
+                    None => Err(TypeError(format!("`{id}` is not defined."))),
+                },
+            },
+            Term::Bound(level, _) => Ok(ctx.types[*level].1.clone()),
+            Term::Universe(n) => Ok(Value::Universe(n + 1)),
+            Term::Int | Term::Str | Term::Bool => Ok(Value::Universe(0)),
+            Term::IntLit(_) => Ok(Value::Int),
+            Term::StrLit(_) => Ok(Value::Str),
+            Term::BoolLit(_) => Ok(Value::Bool),
+            Term::Pi(x, a, b) | Term::Sigma(x, a, b) => {
+                let i = self.check_type(ctx, a)?;
+                let inner = ctx.bind(x, self.eval(&ctx.env, a));
+                let j = self.check_type(&inner, b)?;
+                Ok(Value::Universe(i.max(j)))
+            }
+            Term::App(f, arg) => match self.synth(ctx, f)? {
+                Value::Pi(x, a, b) => {
+                    self.check(ctx, arg, &a)?;
+                    Ok(self.instantiate(&b, &x, self.eval(&ctx.env, arg)))
+                }
+                other => Err(TypeError(format!(
+                    "{f} is not a function, it has type {}",
+                    self.show(ctx, &other)
+                ))),
+            },
+            Term::Fst(p) | Term::Snd(p) => match self.synth(ctx, p)? {
+                Value::Sigma(x, a, b) => match term {
+                    Term::Fst(_) => Ok((*a).clone()),
+                    _ => Ok(self.instantiate(&b, &x, Self::fst(self.eval(&ctx.env, p)))),
+                },
+                other => Err(TypeError(format!("{p} is not a pair, it has type {}", self.show(ctx, &other)))),
+            },
+            Term::Pair(a, b) => {
+                // Without an expected type, the pair is not dependent
+                let fst = self.synth(ctx, a)?;
+                let snd = self.synth(ctx, b)?;
+                Ok(Value::Sigma(
+                    "_".to_string(),
+                    Rc::new(fst),
+                    Closure {
+                        env: ctx.env.clone(),
+                        body: Rc::new(self.quote(ctx.level(), &snd)),
+                    },
+                ))
+            }
+            Term::Op(op, a, b) => match op.as_str() {
+                "+" | "-" | "*" | "/" | "%" => {
+                    self.check(ctx, a, &Value::Int)?;
+                    self.check(ctx, b, &Value::Int)?;
+                    Ok(Value::Int)
+                }
+                "<=" | ">=" | "<" | ">" => {
+                    self.check(ctx, a, &Value::Int)?;
+                    self.check(ctx, b, &Value::Int)?;
+                    Ok(Value::Bool)
+                }
+                "&&" | "||" => {
+                    self.check(ctx, a, &Value::Bool)?;
+                    self.check(ctx, b, &Value::Bool)?;
+                    Ok(Value::Bool)
+                }
+                "==" | "!=" => {
+                    let ty = self.synth(ctx, a)?;
+                    self.check(ctx, b, &ty)?;
+                    Ok(Value::Bool)
+                }
+                _ => Err(TypeError(format!("Unknown operator `{op}`."))),
+            },
+            Term::If(c, t, e) => {
+                self.check(ctx, c, &Value::Bool)?;
+                let ty = self.synth(ctx, t)?;
+                self.check(ctx, e, &ty)?;
+                Ok(ty)
+            }
+            Term::Id(a, x, y) => {
+                let level = self.check_type(ctx, a)?;
+                let a = self.eval(&ctx.env, a);
+                self.check(ctx, x, &a)?;
+                self.check(ctx, y, &a)?;
+                Ok(Value::Universe(level))
+            }
+            Term::Refl(x) => {
+                let a = self.synth(ctx, x)?;
+                let x = Rc::new(self.eval(&ctx.env, x));
+                Ok(Value::Id(Rc::new(a), x.clone(), x))
+            }
+            Term::J(args) => self.synth_j(ctx, args),
+            Term::Lam(..) | Term::Hole(..) => Err(TypeError(format!(
+                "Cannot guess the type of {term}, it needs an annotation."
+            ))),
+        }
+    }
+
+    fn synth_j(&mut self, ctx: &Ctx, args: &[Term; 6]) -> Result<Value, TypeError> {
+        let [a, p, d, x, y, proof] = args;
+        self.check_type(ctx, a)?;
+        let a_value = self.eval(&ctx.env, a);
+
+        // P : (x: A) -> (y: A) -> Id(A, x, y) -> Type_i, for the universe it actually lives in
+        let p_type = self.synth(ctx, p)?;
+        let universe = self.motive_universe(ctx.level(), &p_type).ok_or_else(|| {
+            TypeError(format!(
+                "The motive {p} of J must have a type (x: A) -> (y: A) -> Id(A, x, y) -> Type, not {}",
+                self.show(ctx, &p_type)
+            ))
+        })?;
+        // Built from quoted terms, which the binders `x` and `y` cannot capture
+        let level = ctx.level();
+        let a_term = Rc::new(self.quote(level, &a_value));
+        let var = |level: usize, x: &str| Rc::new(Term::Bound(level, x.to_string()));
+        let motive_type = Term::Pi(
+            "x".to_string(),
+            a_term.clone(),
+            Rc::new(Term::Pi(
+                "y".to_string(),
+                a_term.clone(),
+                Rc::new(Term::Pi(
+                    "_".to_string(),
+                    Rc::new(Term::Id(a_term, var(level, "x"), var(level + 1, "y"))),
+                    Rc::new(Term::Universe(universe)),
+                )),
+            )),
+        );
+        let motive_type = self.eval(&ctx.env, &motive_type);
+        self.check(ctx, p, &motive_type)?;
+        let p_value = self.eval(&ctx.env, p);
+
+        // d : (x: A) -> P(x, x, refl(x))
+        let inner = ctx.bind("x", a_value.clone());
+        let x_var = inner.env.last().unwrap().1.clone();
+        let base = self.motive_at(&p_value, x_var.clone(), x_var.clone(), Value::Refl(Rc::new(x_var)));
+        let base_type = Value::Pi(
+            "x".to_string(),
+            Rc::new(a_value.clone()),
+            Closure {
+                env: ctx.env.clone(),
+                body: Rc::new(self.quote(inner.level(), &base)),
+            },
+        );
+        self.check(ctx, d, &base_type)?;
+
+        self.check(ctx, x, &a_value)?;
+        self.check(ctx, y, &a_value)?;
+        let (x, y) = (self.eval(&ctx.env, x), self.eval(&ctx.env, y));
+        self.check(ctx, proof, &Value::Id(Rc::new(a_value), Rc::new(x.clone()), Rc::new(y.clone())))?;
+
+        Ok(self.motive_at(&p_value, x, y, self.eval(&ctx.env, proof)))
+    }
+
+    fn motive_at(&self, p: &Value, x: Value, y: Value, proof: Value) -> Value {
+        let applied = self.apply(p.clone(), x);
+        let applied = self.apply(applied, y);
+        self.apply(applied, proof)
+    }
+
+    // The universe at the end of the type of a motive, after its three parameters
+    fn motive_universe(&self, level: usize, ty: &Value) -> Option<usize> {
+        let mut ty = ty.clone();
+        for depth in 0..3 {
+            let Value::Pi(x, _, b) = ty else {
+                return None;
+            };
+            ty = self.instantiate(&b, &x, Value::Neutral(Rc::new(Neutral::Var(level + depth, x.clone()))));
+        }
+        match ty {
+            Value::Universe(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn is_toplevel(&self, id: &str) -> bool {
+        self.ast
+            .0
+            .iter()
+            .any(|expr| matches!(expr, Expr::LamDef { ident, .. } if ident == id))
+    }
+}
+
+impl Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Term::Var(x) | Term::Bound(_, x) => write!(f, "{x}"),
+            Term::Universe(0) => write!(f, "Type"),
+            Term::Universe(n) => write!(f, "Type{n}"),
+            Term::Int => write!(f, "Int"),
+            Term::Str => write!(f, "Str"),
+            Term::Bool => write!(f, "Bool"),
+            Term::IntLit(n) => write!(f, "{n}"),
+            Term::StrLit(s) => write!(f, "{s}"),
+            Term::BoolLit(b) => write!(f, "{b}"),
+            Term::Pi(x, a, b) if x == "_" && matches!(**a, Term::Pi(..) | Term::Sigma(..)) => {
+                write!(f, "({a}) -> {b}")
+            }
+            Term::Pi(x, a, b) if x == "_" => write!(f, "{a} -> {b}"),
+            Term::Pi(x, a, b) => write!(f, "({x}: {a}) -> {b}"),
+            Term::Sigma(x, a, b) => write!(f, "({x}: {a}) * {b}"),
+            Term::Lam(x, body) => write!(f, "(\\{x}. {body})"),
+            Term::App(..) => {
+                // Applications are shown as calls, f(a, b)
+                let mut args = Vec::new();
+                let mut head = self;
+                while let Term::App(g, a) = head {
+                    args.push(a.to_string());
+                    head = g;
+                }
+                args.reverse();
+                write!(f, "{head}({})", args.join(", "))
+            }
+            Term::Pair(a, b) => write!(f, "pair({a}, {b})"),
+            Term::Fst(p) => write!(f, "fst({p})"),
+            Term::Snd(p) => write!(f, "snd({p})"),
+            Term::Op(op, a, b) => write!(f, "({a} {op} {b})"),
+            Term::If(c, t, e) => write!(f, "(if {c} ? {t} : {e})"),
+            Term::Id(a, x, y) => write!(f, "Id({a}, {x}, {y})"),
+            Term::Refl(x) => write!(f, "refl({x})"),
+            Term::J(args) => {
+                let args: Vec<String> = args.iter().map(|t| t.to_string()).collect();
+                write!(f, "J({})", args.join(", "))
+            }
+            Term::Hole(Some(name), _) => write!(f, "?{name}"),
+            Term::Hole(None, _) => write!(f, "_"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(program: &str) -> Result<Vec<(String, String)>, TypeError> {
+        let ast = Parser::new(&mut Lexer::new(program)).dependent().program().unwrap();
+        DependentChecker::new(&ast).init()
+    }
+
+    #[test]
+    fn polymorphism_through_pi_types() {
+        let types = check(
+            r#"
+            lam id :: A: Type, x: A : A -> x;
+            lam apply :: A: Type, B: (A -> Type), f: ((x: A) -> B(x)), x: A : B(x) -> f(x);
+            lam AlwaysInt :: x: Int : Type -> Int;
+            lam five : Int -> apply(Int, AlwaysInt, id(Int), 5);
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(types[0].1, "(A: Type) -> (x: A) -> A");
+        assert_eq!(types[1].1, "(A: Type) -> (B: A -> Type) -> (f: (x: A) -> B(x)) -> (x: A) -> B(x)");
+        assert_eq!(types[3].1, "Int");
+    }
+
+    #[test]
+    fn definitional_equality_by_normalization() {
+        let program = r#"
+            lam Pick :: b: Bool : Type -> if b ? Int : Str;
+            lam Tagged : Type -> (n: Int) * Pick(n == 0);
+            lam zero : Tagged -> pair(0, 42);
+            lam one : Tagged -> pair(1, "one");
+        "#;
+        assert!(check(program).is_ok());
+
+        let TypeError(msg) = check(&format!("{program} lam bad : Tagged -> pair(1 + 1, 2);")).unwrap_err();
+        assert!(msg.starts_with("In `bad`: Expected type Str, got Int"), "{msg}");
+    }
+
+    #[test]
+    fn binders_are_compared_up_to_renaming() {
+        let program = r#"
+            lam F :: n: Int : Type -> Int;
+            lam f :: g: ((x: Int) -> F(x)) : Int -> g(1);
+            lam h :: y: Int : F(y) -> y;
+            lam main : Int -> f(h);
+            lam Fam :: A: Type, a: A : Type -> Id(A, a, a);
+            lam at :: A: Type, g: ((x: A) -> Fam(A, x)), y: A : Id(A, y, y) -> g(y);
+            lam r :: A: Type, b: A : Fam(A, b) -> refl(b);
+            lam one : Id(Int, 1, 1) -> at(Int, r(Int), 1);
+        "#;
+        assert!(check(program).is_ok());
+
+        let TypeError(msg) = check(&format!("{program} lam bad : Int -> f(r(Int));")).unwrap_err();
+        assert!(msg.starts_with("In `bad`: Expected type (x: Int) -> Int, got (b: Int) -> Id(Int, b, b)"), "{msg}");
+    }
+
+    #[test]
+    fn universe_hierarchy() {
+        assert!(check("lam U : Type1 -> Type;").is_ok());
+        assert!(check("lam U : Type -> Type;").is_err());
+        assert!(check("lam Endo :: A: Type : Type -> (x: A) -> A; lam Big : Type1 -> Endo(Type);").is_err());
+    }
+
+    #[test]
+    fn equality_proofs() {
+        let program = r#"
+            lam SymFamily :: A: Type, x: A, z: A : Type -> Id(A, z, x);
+            lam sym :: A: Type, x: A, y: A, p: Id(A, x, y) : Id(A, y, x) -> transport(A, SymFamily(A, x), x, y, p, refl(x));
+            lam TransFamily :: A: Type, x: A, w: A : Type -> Id(A, x, w);
+            lam trans :: A: Type, x: A, y: A, z: A, p: Id(A, x, y), q: Id(A, y, z) : Id(A, x, z) -> transport(A, TransFamily(A, x), y, z, q, p);
+            lam two : Id(Int, 1 + 1, 2) -> refl(2);
+            lam succ :: n: Int : Int -> n + 1;
+            lam three : Id(Int, succ(1 + 1), 3) -> cong(Int, Int, succ, 1 + 1, 2, two);
+        "#;
+        let types = check(program).unwrap();
+        assert_eq!(types[1].1, "(A: Type) -> (x: A) -> (y: A) -> (p: Id(A, x, y)) -> Id(A, y, x)");
+
+        for bogus in [
+            "lam bogus :: A: Type, x: A, y: A : Id(A, x, y) -> refl(x);",
+            "lam bad_sym :: A: Type, x: A, y: A, p: Id(A, x, y) : Id(A, y, x) -> p;",
+            "lam one_is_two : Id(Int, 1, 2) -> refl(1);",
+            "lam transport :: A: Type : Type -> A;",
+        ] {
+            assert!(check(&format!("{program} {bogus}")).is_err(), "{bogus}");
+        }
+    }
+
+    #[test]
+    fn path_induction_with_any_binder_names() {
+        // The motive does not name its parameters `x` and `y`, and the type of the path is `x`
+        let program = r#"
+            lam Swap :: A: Type, u: A, v: A, e: Id(A, u, v) : Type -> Id(A, v, u);
+            lam refl_at :: A: Type, a: A : Id(A, a, a) -> refl(a);
+            lam flip :: A: Type, u: A, v: A, e: Id(A, u, v) : Id(A, v, u) -> J(A, Swap(A), refl_at(A), u, v, e);
+            lam flip_x :: x: Type, u: x, v: x, e: Id(x, u, v) : Id(x, v, u) -> J(x, Swap(x), refl_at(x), u, v, e);
+        "#;
+        let types = check(program).unwrap();
+        assert_eq!(types[3].1, "(x: Type) -> (u: x) -> (v: x) -> (e: Id(x, u, v)) -> Id(x, v, u)");
+
+        let bogus = "lam Same :: A: Type, u: A, v: A, e: Id(A, u, v) : Type -> Id(A, u, u);
+            lam bad :: A: Type, u: A, v: A, e: Id(A, u, v) : Id(A, v, u) -> J(A, Same(A), refl_at(A), u, v, e);";
+        assert!(check(&format!("{program} {bogus}")).is_err());
+    }
+}
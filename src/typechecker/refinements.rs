@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::parser::{Expr, LamStyle, Literal, Program, TypeExpr};
+
+use self::solver::{Formula, Linear, Outcome};
+use super::{Gamma, Type};
+
+pub mod solver;
+
+/*
+    Refinement types such as `{v: Int | v != 0}`, checked once the types are inferred.
+    Every argument given to a refined parameter, and every result of a function with a refined
+    return type, must satisfy the refinement, knowing the refinements of the parameters of the
+    enclosing function and the conditions of the `if`s around it. As the arguments are checked
+    where the function is called, a function with refined parameters can be neither used as a
+    value nor partially applied without its refined arguments. These verification conditions
+    are linear integer arithmetic, decided by the solver which finds a counterexample to the
+    ones which do not hold.
+*/
+
+pub struct RefinementChecker<'a> {
+    ast: &'a Program,
+    gamma: &'a Gamma,
+    // The parameters and result refinements of each toplevel function
+    signatures: HashMap<&'a str, Signature<'a>>,
+    // The number of unknown values named so far, such as the results of calls
+    unknowns: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+struct Signature<'a> {
+    params: &'a [String],
+    refinements: Vec<Option<Refinement<'a>>>,
+    ret: Option<Refinement<'a>>,
+}
+
+// {var: Int | pred}
+#[derive(Clone, Copy)]
+struct Refinement<'a> {
+    var: &'a str,
+    pred: &'a Expr,
+}
+
+// What is known at some point of a function body
+#[derive(Clone, Default)]
+struct Facts {
+    // The integer variables in scope with their value
+    env: HashMap<String, Linear>,
+    // The parameters of the function, shadowing the toplevel functions
+    locals: Vec<String>,
+    // The refinements of the parameters and the conditions leading there
+    known: Vec<Formula>,
+}
+
+impl<'a> RefinementChecker<'a> {
+    /// `gamma` is the environment inferred for `ast` by the `TypeChecker`
+    pub fn new(ast: &'a Program, gamma: &'a Gamma) -> Self {
+        RefinementChecker {
+            ast,
+            gamma,
+            signatures: HashMap::new(),
+            unknowns: 0,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Checks the refinements of the program, returning an error for each one that could not
+    /// be proven.
+    pub fn check(mut self) -> Vec<Diagnostic> {
+        // The aliases of refined types, as `type NonZero = {v: Int | v != 0}`
+        let aliases: HashMap<&str, &TypeExpr> = self
+            .ast
+            .0
+            .iter()
+            .filter_map(|expr| match expr {
+                Expr::TypeDecl { ident, params, body, .. } if params.is_empty() => Some((ident.as_str(), body)),
+                _ => None,
+            })
+            .collect();
+        let refinement = |mut ty: &'a TypeExpr| loop {
+            match ty {
+                TypeExpr::Refined { var, pred, .. } => return Some(Refinement { var, pred }),
+                TypeExpr::Named { name, args, .. } if args.is_empty() && aliases.contains_key(name.as_str()) => {
+                    ty = aliases[name.as_str()]
+                }
+                _ => return None,
+            }
+        };
+
+        for expr in &self.ast.0 {
+            if let Expr::LamDef { ident, params, annotations, ret, .. } = expr {
+                let signature = Signature {
+                    params,
+                    refinements: annotations.iter().map(|a| a.as_ref().and_then(refinement)).collect(),
+                    ret: ret.as_ref().and_then(refinement),
+                };
+                self.signatures.insert(ident, signature);
+            }
+        }
+
+        for expr in &self.ast.0 {
+            if let Expr::LamDef { ident, params, operation, .. } = expr {
+                self.check_function(ident, params, operation);
+            }
+        }
+        self.diagnostics
+    }
+
+    fn check_function(&mut self, ident: &str, params: &[String], body: &Expr) {
+        let ints = self.int_params(ident);
+        let mut facts = Facts {
+            locals: params.to_vec(),
+            ..Facts::default()
+        };
+        for (i, param) in params.iter().enumerate() {
+            if ints.get(i).copied().unwrap_or(false) {
+                facts.env.insert(param.clone(), Linear::var(param));
+            }
+        }
+
+        let signature = &self.signatures[ident];
+        let (refinements, ret) = (signature.refinements.clone(), signature.ret);
+        for (param, refinement) in params.iter().zip(refinements) {
+            let Some(refinement) = refinement else { continue };
+            let mut env = facts.env.clone();
+            env.insert(refinement.var.to_string(), Linear::var(param));
+            match self.predicate(refinement, env) {
+                Some(fact) => facts.known.push(fact),
+                None => self.not_linear(refinement),
+            }
+        }
+        if let Some(refinement) = ret {
+            let mut env = facts.env.clone();
+            env.insert(refinement.var.to_string(), Linear::var(refinement.var));
+            if self.predicate(refinement, env).is_none() {
+                self.not_linear(refinement);
+            }
+        }
+
+        self.walk(body, &facts);
+        if let Some(refinement) = ret {
+            self.check_result(ident, body, &facts, refinement);
+        }
+    }
+
+    // Whether each parameter of the toplevel function `ident` is an Int
+    fn int_params(&self, ident: &str) -> Vec<bool> {
+        let mut ty = self.gamma.get(ident).map(|scheme| &scheme.1);
+        let mut ints = Vec::new();
+        while let Some(Type::Joined { i, o, .. }) = ty {
+            ints.push(**i == Type::Int);
+            ty = Some(o);
+        }
+        ints
+    }
+
+    // Checks the calls of `expr` to functions with refined parameters
+    fn walk(&mut self, expr: &Expr, facts: &Facts) {
+        match expr {
+            Expr::If { cond, then, alter, .. } => {
+                self.walk(cond, facts);
+                let (then_facts, alter_facts) = self.branches(cond, facts);
+                self.walk(then, &then_facts);
+                self.walk(alter, &alter_facts);
+            }
+            // The names bound by a pattern hide those of the same name, about which nothing is known
+            Expr::Match { scrutinee, arms, .. } => {
+                self.walk(scrutinee, facts);
+                for (pattern, body) in arms {
+                    let mut facts = facts.clone();
+                    for var in pattern.vars() {
+                        facts.env.remove(var);
+                        facts.locals.push(var.clone());
+                    }
+                    self.walk(body, &facts);
+                }
+            }
+            Expr::LamCall { ident, params, pos, .. } => {
+                for param in params {
+                    self.walk(param, facts);
+                }
+                if !facts.locals.contains(ident) && self.signatures.contains_key(ident.as_str()) {
+                    match self.unchecked(ident, params.len()) {
+                        Some(n) => self.error(
+                            format!("`{ident}` cannot be partially applied, the refinement of its argument {n} would not be checked."),
+                            *pos,
+                        ),
+                        None => self.check_call(ident, params, *pos, facts),
+                    }
+                }
+            }
+            Expr::Var(name, pos) if !facts.locals.contains(name) && self.unchecked(name, 0).is_some() => self.error(
+                format!("`{name}` cannot be used as a value, the refinements of its parameters are only checked where it is called."),
+                *pos,
+            ),
+            _ => {
+                for sub in expr.sub_exprs() {
+                    self.walk(sub, facts);
+                }
+            }
+        }
+    }
+
+    // The first refined parameter of the toplevel function `ident` which a call with `given`
+    // arguments leaves unchecked, counted from 1
+    fn unchecked(&self, ident: &str, given: usize) -> Option<usize> {
+        let signature = self.signatures.get(ident)?;
+        (signature.refinements.iter().skip(given)).position(Option::is_some).map(|n| given + n + 1)
+    }
+
+    fn error(&mut self, message: String, pos: (usize, usize)) {
+        self.diagnostics.push(Diagnostic::new(Severity::Error, message, Some(pos)));
+    }
+
+    // What is known in each branch of `if cond`
+    fn branches(&mut self, cond: &Expr, facts: &Facts) -> (Facts, Facts) {
+        let mut facts = facts.clone();
+        let cond = self.formula(cond, &mut facts);
+        let (mut then_facts, mut alter_facts) = (facts.clone(), facts);
+        if let Some(cond) = cond {
+            alter_facts.known.push(Formula::Not(Box::new(cond.clone())));
+            then_facts.known.push(cond);
+        }
+        (then_facts, alter_facts)
+    }
+
+    fn check_call(&mut self, ident: &str, args: &[Expr], pos: (usize, usize), facts: &Facts) {
+        let mut facts = facts.clone();
+        let values: Vec<Linear> = args.iter().map(|arg| self.value(arg, &mut facts)).collect();
+        let signature = &self.signatures[ident];
+        let env = self.call_env(signature, &values);
+        let refinements: Vec<_> = signature.refinements.iter().take(args.len()).copied().collect();
+
+        for (i, refinement) in refinements.into_iter().enumerate() {
+            let Some(refinement) = refinement else { continue };
+            let mut env = env.clone();
+            env.insert(refinement.var.to_string(), values[i].clone());
+            if let Some(goal) = self.predicate(refinement, env) {
+                let what = format!("argument {} of `{ident}`", i + 1);
+                self.prove(&facts, goal, refinement, Some(pos), what, Some((ident, &values)));
+            }
+        }
+    }
+
+    fn check_result(&mut self, ident: &str, body: &Expr, facts: &Facts, refinement: Refinement) {
+        if let Expr::If { cond, then, alter, .. } = body {
+            let (then_facts, alter_facts) = self.branches(cond, facts);
+            self.check_result(ident, then, &then_facts, refinement);
+            self.check_result(ident, alter, &alter_facts, refinement);
+            return;
+        }
+        let mut facts = facts.clone();
+        let value = self.value(body, &mut facts);
+        let mut env = facts.env.clone();
+        env.insert(refinement.var.to_string(), value);
+        if let Some(goal) = self.predicate(refinement, env) {
+            self.prove(&facts, goal, refinement, pos_in(body), format!("the result of `{ident}`"), None);
+        }
+    }
+
+    // Reports the failure to prove that `goal` follows from `facts`, with a counterexample. When
+    // checking an argument of the `call` to a function, the arguments are shown when the
+    // counterexample does not involve the parameters of the caller.
+    fn prove(
+        &mut self,
+        facts: &Facts,
+        goal: Formula,
+        refinement: Refinement,
+        pos: Option<(usize, usize)>,
+        what: String,
+        call: Option<(&str, &[Linear])>,
+    ) {
+        let mut query = facts.known.clone();
+        query.push(Formula::Not(Box::new(goal)));
+        let message = format!("Cannot prove that {what} satisfies `{}`", show(refinement.pred));
+        let diagnostic = match solver::solve(&Formula::And(query)) {
+            Outcome::Unsat => return,
+            Outcome::Sat(model) => {
+                // The unknowns are internal, only the parameters are meaningful
+                let values: Vec<String> = model
+                    .iter()
+                    .filter(|(x, _)| facts.locals.contains(x))
+                    .map(|(x, value)| format!("{x} = {value}"))
+                    .collect();
+                let args = call.and_then(|(ident, args)| {
+                    let args: Option<Vec<String>> = args.iter().map(|arg| Some(arg.value_in(&model)?.to_string())).collect();
+                    Some(format!("it fails for `{ident}({})`", args?.join(", ")))
+                });
+                let note = match args {
+                    _ if !values.is_empty() => format!("counterexample: {}", values.join(", ")),
+                    Some(args) => args,
+                    None => "it does not hold in general".to_string(),
+                };
+                Diagnostic::new(Severity::Error, format!("{message}."), pos).with_notes(vec![note])
+            }
+            Outcome::Unknown => Diagnostic::new(Severity::Error, format!("{message}, the solver gave up."), pos),
+        };
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn not_linear(&mut self, refinement: Refinement) {
+        self.diagnostics.push(Diagnostic::new(
+            Severity::Error,
+            format!(
+                "The refinement `{}` of `{}` is not a comparison of linear integer expressions.",
+                show(refinement.pred),
+                refinement.var
+            ),
+            pos_in(refinement.pred),
+        ));
+    }
+
+    // The parameters of a function bound to the values of the arguments of a call
+    fn call_env(&self, signature: &Signature, values: &[Linear]) -> HashMap<String, Linear> {
+        signature.params.iter().cloned().zip(values.iter().cloned()).collect()
+    }
+
+    // A fresh variable standing for a value about which nothing is known
+    fn unknown(&mut self) -> Linear {
+        self.unknowns += 1;
+        Linear::var(&format!("#{}", self.unknowns))
+    }
+
+    // The formula of a refinement, only mentioning the variables of `env`
+    fn predicate(&mut self, refinement: Refinement, env: HashMap<String, Linear>) -> Option<Formula> {
+        let mut facts = Facts { env, ..Facts::default() };
+        self.translate(refinement.pred, &mut facts, true)
+    }
+
+    // The formula of the condition `expr`, if it is linear arithmetic
+    fn formula(&mut self, expr: &Expr, facts: &mut Facts) -> Option<Formula> {
+        self.translate(expr, facts, false)
+    }
+
+    // The integer value of `expr`, as a fresh unknown when it is not linear
+    fn value(&mut self, expr: &Expr, facts: &mut Facts) -> Linear {
+        self.term(expr, facts, false).unwrap_or_else(|| self.unknown())
+    }
+
+    // Unless `strict`, the calls to functions returning an Int are unknowns, constrained by the
+    // refinement of their result. The facts learned about them are added to `facts`.
+    fn translate(&mut self, expr: &Expr, facts: &mut Facts, strict: bool) -> Option<Formula> {
+        let Expr::LamCall { ident, params, .. } = expr else {
+            return None;
+        };
+        match (ident.as_str(), params.as_slice()) {
+            ("&&" | "||", [l, r]) => {
+                let both = vec![self.translate(l, facts, strict)?, self.translate(r, facts, strict)?];
+                Some(if ident == "&&" { Formula::And(both) } else { Formula::Or(both) })
+            }
+            ("not", [e]) => Some(Formula::Not(Box::new(self.translate(e, facts, strict)?))),
+            (op @ ("<=" | ">=" | "<" | ">" | "==" | "!="), [l, r]) => {
+                let (l, r) = (self.term(l, facts, strict)?, self.term(r, facts, strict)?);
+                let one = Linear::constant(1);
+                Some(match op {
+                    "<=" => Formula::Le(l.sub(&r)),
+                    ">=" => Formula::Le(r.sub(&l)),
+                    "<" => Formula::Le(l.sub(&r).add(&one)),
+                    ">" => Formula::Le(r.sub(&l).add(&one)),
+                    "==" => Formula::Eq(l.sub(&r)),
+                    _ => Formula::Not(Box::new(Formula::Eq(l.sub(&r)))),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn term(&mut self, expr: &Expr, facts: &mut Facts, strict: bool) -> Option<Linear> {
+        match expr {
+            Expr::Literal(Literal::Int(n)) => n.to_i128().map(Linear::constant),
+            Expr::Var(name, _) => facts.env.get(name).cloned(),
+            Expr::LamCall { ident, params, style: LamStyle::Infix, .. } if matches!(ident.as_str(), "+" | "-" | "*") => {
+                let (l, r) = (self.term(&params[0], facts, strict)?, self.term(&params[1], facts, strict)?);
+                match ident.as_str() {
+                    "+" => Some(l.add(&r)),
+                    "-" => Some(l.sub(&r)),
+                    _ => match (l.as_constant(), r.as_constant()) {
+                        (Some(k), _) => Some(r.scale(k)),
+                        (_, Some(k)) => Some(l.scale(k)),
+                        _ if strict => None,
+                        _ => Some(self.unknown()),
+                    },
+                }
+            }
+            Expr::LamCall { ident, params, .. } if ident == "negate" && params.len() == 1 => {
+                Some(Linear::constant(0).sub(&self.term(&params[0], facts, strict)?))
+            }
+            Expr::LamCall { ident, params, .. } if !strict && !facts.locals.contains(ident) => {
+                let ret = self.signatures.get(ident.as_str()).filter(|s| s.params.len() == params.len())?.ret;
+                let result = self.unknown();
+                if let Some(refinement) = ret {
+                    let values: Vec<Linear> = params.iter().map(|arg| self.value(arg, facts)).collect();
+                    let mut env = self.call_env(&self.signatures[ident.as_str()], &values);
+                    env.insert(refinement.var.to_string(), result.clone());
+                    if let Some(fact) = self.predicate(refinement, env) {
+                        facts.known.push(fact);
+                    }
+                }
+                Some(result)
+            }
+            Expr::If { cond, then, alter, .. } if !strict => {
+                // The value of one of the branches, depending on the condition
+                let cond = self.formula(cond, facts)?;
+                let result = self.unknown();
+                let (mut then_facts, mut alter_facts) = (facts.clone(), facts.clone());
+                let then = self.term(then, &mut then_facts, strict)?;
+                let alter = self.term(alter, &mut alter_facts, strict)?;
+                let not_cond = Formula::Not(Box::new(cond.clone()));
+                facts.known.push(Formula::Or(vec![not_cond.clone(), Formula::Eq(result.sub(&then))]));
+                facts.known.push(Formula::Or(vec![cond, Formula::Eq(result.sub(&alter))]));
+                Some(result)
+            }
+            _ => None,
+        }
+    }
+}
+
+// The position of the first call or hole in `expr`, where the errors about it are reported
+fn pos_in(expr: &Expr) -> Option<(usize, usize)> {
+    match expr {
+        Expr::LamCall { pos, .. } | Expr::Hole { pos, .. } | Expr::Cast { pos, .. } => Some(*pos),
+        _ => expr.sub_exprs().into_iter().find_map(pos_in),
+    }
+}
+
+// The source of a refinement predicate
+fn show(expr: &Expr) -> String {
+    match expr {
+        Expr::Literal(Literal::Int(n)) => n.to_string(),
+        Expr::Literal(Literal::Str(s)) => format!("{s:?}"),
+        Expr::Var(name, _) => name.clone(),
+        Expr::LamCall { ident, params, .. } if ident == "negate" && params.len() == 1 => format!("-{}", show(&params[0])),
+        Expr::LamCall { ident, params, .. } if ident == "not" && params.len() == 1 => format!("not {}", show(&params[0])),
+        Expr::LamCall { ident, params, style: LamStyle::Infix, .. } if params.len() == 2 => {
+            format!("{} {ident} {}", show(&params[0]), show(&params[1]))
+        }
+        Expr::LamCall { ident, params, .. } => {
+            format!("{ident}({})", params.iter().map(show).collect::<Vec<_>>().join(", "))
+        }
+        _ => "...".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::typechecker::TypeChecker;
+
+    fn check(source: &str) -> Vec<Diagnostic> {
+        let ast = Parser::new(&mut Lexer::new(source)).parse().unwrap();
+        let gamma = TypeChecker::new(&ast).init().unwrap();
+        RefinementChecker::new(&ast, &gamma).check()
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let defs = "lam div :: x: Int, y: {v: Int | v != 0} -> x / y;\n\
+                    lam abs :: x: Int : {v: Int | v >= 0} -> if x >= 0 ? x : 0 - x;\n";
+
+        // Guarded by a condition, a literal, or a refined result
+        let accepted = format!(
+            "{defs}lam f :: x: Int, y: Int -> if y != 0 ? div(x, y) : div(x, 2);\n\
+             lam g :: x: Int, y: Int -> div(x, abs(y) + 1);"
+        );
+        assert_eq!(check(&accepted), Vec::new());
+
+        let rejected = format!("{defs}lam f :: x: Int, y: Int -> if x > y ? div(x, y) : 0;");
+        let errors = check(&rejected);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Cannot prove that argument 2 of `div` satisfies `v != 0`.");
+        assert_eq!(errors[0].pos, Some((3, 39)));
+        assert_eq!(errors[0].notes, vec!["counterexample: x = 1, y = 0".to_string()]);
+
+        let errors = check("lam pos :: x: Int : {v: Int | v > 0} -> if x > 0 ? x : 0 - x;");
+        assert_eq!(errors[0].message, "Cannot prove that the result of `pos` satisfies `v > 0`.");
+        assert_eq!(errors[0].notes, vec!["counterexample: x = 0".to_string()]);
+
+        let errors = check(&format!("{defs}lam main -> div(1, 3 - 3);"));
+        assert_eq!(errors[0].notes, vec!["it fails for `div(1, 0)`".to_string()]);
+    }
+
+    #[test]
+    fn refined_functions_are_only_called() {
+        let defs = "lam div :: x: Int, y: {v: Int | v != 0} -> x / y;\n\
+                    lam apply :: f, a, b -> f(a, b);\n\
+                    lam map :: f, xs -> match xs with | [] -> [] | x :: rest -> f(x) :: map(f, rest);\n";
+        let messages = |source: &str| {
+            let errors = check(&format!("{defs}{source}"));
+            errors.into_iter().map(|error| (error.message, error.pos)).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages("lam main -> apply(div, 1, 0);"),
+            vec![(
+                "`div` cannot be used as a value, the refinements of its parameters are only checked where it is called.".to_string(),
+                Some((4, 19))
+            )]
+        );
+        assert_eq!(
+            messages("lam main -> [0] |> map(div(1));"),
+            vec![("`div` cannot be partially applied, the refinement of its argument 2 would not be checked.".to_string(), Some((4, 24)))]
+        );
+        // The refined argument is given, and checked
+        let by = "lam by :: y: {v: Int | v != 0}, x: Int -> x / y;";
+        assert_eq!(messages(&format!("{by} lam main -> [4] |> map(by(2));")), Vec::new());
+        let errors = messages(&format!("{by} lam main -> [4] |> map(by(0));"));
+        assert_eq!(errors[0].0, "Cannot prove that argument 1 of `by` satisfies `v != 0`.");
+    }
+}
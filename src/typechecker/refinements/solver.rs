@@ -0,0 +1,416 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+/*
+    Decision procedure for quantifier-free linear integer arithmetic.
+    A formula is put in disjunctive normal form, each conjunction of linear constraints being
+    solved over the rationals by Fourier-Motzkin elimination, the constraints being tightened to
+    their integer solutions on the way. When the rational solution found is not integral, the
+    search branches on a fractional variable (branch and bound).
+*/
+
+// The number of branchings after which the search gives up
+const MAX_DEPTH: usize = 32;
+// The number of constraints after which an elimination gives up, as it can grow exponentially
+const MAX_CONSTRAINTS: usize = 2048;
+
+/// A linear combination of integer variables plus a constant
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Linear {
+    /// The coefficient of each variable, never zero
+    pub coeffs: BTreeMap<String, i128>,
+    pub constant: i128,
+}
+
+/// Formulas of linear integer arithmetic
+#[derive(Clone, Debug, PartialEq)]
+pub enum Formula {
+    /// t <= 0
+    Le(Linear),
+    /// t == 0
+    Eq(Linear),
+    Not(Box<Formula>),
+    And(Vec<Formula>),
+    Or(Vec<Formula>),
+}
+
+/// Whether a formula can be satisfied, with a model when it can
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Unsat,
+    Sat(BTreeMap<String, i128>),
+    /// The search was too long
+    Unknown,
+}
+
+// The rational num / den, with den > 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Ratio {
+    num: i128,
+    den: i128,
+}
+
+impl Linear {
+    pub fn constant(constant: i128) -> Self {
+        Linear {
+            coeffs: BTreeMap::new(),
+            constant,
+        }
+    }
+
+    pub fn var(name: &str) -> Self {
+        Linear {
+            coeffs: BTreeMap::from([(name.to_string(), 1)]),
+            constant: 0,
+        }
+    }
+
+    pub fn add(&self, other: &Linear) -> Self {
+        let mut sum = self.clone();
+        for (x, a) in &other.coeffs {
+            let coeff = sum.coeff(x) + a;
+            if coeff == 0 {
+                sum.coeffs.remove(x);
+            } else {
+                sum.coeffs.insert(x.clone(), coeff);
+            }
+        }
+        sum.constant += other.constant;
+        sum
+    }
+
+    pub fn sub(&self, other: &Linear) -> Self {
+        self.add(&other.scale(-1))
+    }
+
+    pub fn scale(&self, k: i128) -> Self {
+        if k == 0 {
+            return Linear::constant(0);
+        }
+        Linear {
+            coeffs: self.coeffs.iter().map(|(x, a)| (x.clone(), a * k)).collect(),
+            constant: self.constant * k,
+        }
+    }
+
+    /// The value of the combination when it has no variable
+    pub fn as_constant(&self) -> Option<i128> {
+        self.coeffs.is_empty().then_some(self.constant)
+    }
+
+    /// The value of the combination in `model`, if it gives a value to each of its variables
+    pub fn value_in(&self, model: &BTreeMap<String, i128>) -> Option<i128> {
+        self.coeffs
+            .iter()
+            .try_fold(self.constant, |sum, (x, a)| Some(sum + a * model.get(x)?))
+    }
+
+    fn coeff(&self, x: &str) -> i128 {
+        self.coeffs.get(x).copied().unwrap_or(0)
+    }
+
+    fn without(&self, x: &str) -> Self {
+        let mut rest = self.clone();
+        rest.coeffs.remove(x);
+        rest
+    }
+
+    // Divides `t <= 0` by the gcd of its coefficients, rounding the constant up: the constraint
+    // has the same integer solutions, and fewer rational ones.
+    fn tighten(mut self) -> Self {
+        let g = self.coeffs.values().fold(0, |g, a| gcd(g, *a));
+        if g > 1 {
+            self.coeffs.values_mut().for_each(|a| *a /= g);
+            self.constant = div_ceil(self.constant, g);
+        }
+        self
+    }
+
+    fn eval(&self, model: &BTreeMap<String, Ratio>) -> Ratio {
+        self.coeffs.iter().fold(Ratio::int(self.constant), |acc, (x, a)| {
+            let value = model.get(x).copied().unwrap_or(Ratio::int(0));
+            acc.add(value.mul(Ratio::int(*a)))
+        })
+    }
+}
+
+impl Ratio {
+    fn new(num: i128, den: i128) -> Self {
+        let g = gcd(num, den).max(1) * den.signum();
+        Ratio { num: num / g, den: den / g }
+    }
+
+    fn int(n: i128) -> Self {
+        Ratio { num: n, den: 1 }
+    }
+
+    fn add(self, other: Ratio) -> Ratio {
+        Ratio::new(self.num * other.den + other.num * self.den, self.den * other.den)
+    }
+
+    fn mul(self, other: Ratio) -> Ratio {
+        Ratio::new(self.num * other.num, self.den * other.den)
+    }
+
+    fn div(self, other: Ratio) -> Ratio {
+        Ratio::new(self.num * other.den, self.den * other.num)
+    }
+
+    fn floor(self) -> i128 {
+        self.num.div_euclid(self.den)
+    }
+
+    fn ceil(self) -> i128 {
+        div_ceil(self.num, self.den)
+    }
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.num * other.den).cmp(&(other.num * self.den))
+    }
+}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn div_ceil(a: i128, b: i128) -> i128 {
+    -(-a).div_euclid(b)
+}
+
+/// Looks for integer values of the variables satisfying `formula`.
+pub fn solve(formula: &Formula) -> Outcome {
+    let mut unknown = false;
+    for conjunction in dnf(&nnf(formula, true)) {
+        match solve_conjunction(conjunction) {
+            Outcome::Sat(model) => return Outcome::Sat(model),
+            Outcome::Unknown => unknown = true,
+            Outcome::Unsat => {}
+        }
+    }
+    if unknown {
+        Outcome::Unknown
+    } else {
+        Outcome::Unsat
+    }
+}
+
+// Pushes the negations down to the constraints, which absorb them as the variables are integers
+fn nnf(formula: &Formula, positive: bool) -> Formula {
+    let one = Linear::constant(1);
+    match (formula, positive) {
+        (Formula::Le(t), true) => Formula::Le(t.clone()),
+        // not (t <= 0) is 1 - t <= 0
+        (Formula::Le(t), false) => Formula::Le(one.sub(t)),
+        (Formula::Eq(t), true) => Formula::Eq(t.clone()),
+        // t != 0 is t < 0 or t > 0
+        (Formula::Eq(t), false) => Formula::Or(vec![Formula::Le(t.add(&one)), Formula::Le(one.sub(t))]),
+        (Formula::Not(f), _) => nnf(f, !positive),
+        (Formula::And(fs), true) | (Formula::Or(fs), false) => Formula::And(fs.iter().map(|f| nnf(f, positive)).collect()),
+        (Formula::Or(fs), true) | (Formula::And(fs), false) => Formula::Or(fs.iter().map(|f| nnf(f, positive)).collect()),
+    }
+}
+
+// The conjunctions of constraints `(t, is_equality)` whose disjunction is the formula in NNF
+fn dnf(formula: &Formula) -> Vec<Vec<(Linear, bool)>> {
+    match formula {
+        Formula::Le(t) => vec![vec![(t.clone(), false)]],
+        Formula::Eq(t) => vec![vec![(t.clone(), true)]],
+        Formula::Or(fs) => fs.iter().flat_map(dnf).collect(),
+        Formula::And(fs) => fs.iter().fold(vec![Vec::new()], |acc, f| {
+            let right = dnf(f);
+            acc.iter()
+                .flat_map(|l| right.iter().map(move |r| l.iter().chain(r).cloned().collect()))
+                .collect()
+        }),
+        Formula::Not(_) => unreachable!("not in negation normal form"),
+    }
+}
+
+fn solve_conjunction(constraints: Vec<(Linear, bool)>) -> Outcome {
+    // Equalities with a unit coefficient define a variable in terms of the others
+    let mut definitions: Vec<(String, Linear)> = Vec::new();
+    let mut inequalities = Vec::new();
+    let mut pending = constraints;
+    while let Some((t, is_eq)) = pending.pop() {
+        if !is_eq {
+            inequalities.push(t);
+            continue;
+        }
+        let Some((x, a)) = t.coeffs.iter().find(|(_, a)| a.abs() == 1) else {
+            inequalities.push(t.clone());
+            inequalities.push(t.scale(-1));
+            continue;
+        };
+        // a * x + rest == 0, so x == -a * rest
+        let (x, definition) = (x.clone(), t.without(x).scale(-a));
+        let substitute = |c: &Linear| c.without(&x).add(&definition.scale(c.coeff(&x)));
+        pending = pending.iter().map(|(c, eq)| (substitute(c), *eq)).collect();
+        inequalities = inequalities.iter().map(substitute).collect();
+        definitions = definitions.iter().map(|(y, d)| (y.clone(), substitute(d))).collect();
+        definitions.push((x, definition));
+    }
+
+    match branch_and_bound(inequalities, MAX_DEPTH) {
+        Outcome::Sat(mut model) => {
+            for (x, definition) in definitions.iter().rev() {
+                let value = definition
+                    .coeffs
+                    .iter()
+                    .fold(definition.constant, |acc, (y, a)| acc + a * model.get(y).copied().unwrap_or(0));
+                model.insert(x.clone(), value);
+            }
+            Outcome::Sat(model)
+        }
+        outcome => outcome,
+    }
+}
+
+fn branch_and_bound(constraints: Vec<Linear>, depth: usize) -> Outcome {
+    let model = match relaxation(constraints.clone()) {
+        Relaxation::Infeasible => return Outcome::Unsat,
+        Relaxation::TooLarge => return Outcome::Unknown,
+        Relaxation::Point(model) => model,
+    };
+    let Some((x, value)) = model.iter().find(|(_, v)| v.den != 1) else {
+        return Outcome::Sat(model.into_iter().map(|(x, v)| (x, v.num)).collect());
+    };
+    if depth == 0 {
+        return Outcome::Unknown;
+    }
+
+    // x <= floor(value) or x >= ceil(value)
+    let below = Linear::var(x).sub(&Linear::constant(value.floor()));
+    let above = Linear::constant(value.ceil()).sub(&Linear::var(x));
+    let mut unknown = false;
+    for bound in [below, above] {
+        let mut constraints = constraints.clone();
+        constraints.push(bound);
+        match branch_and_bound(constraints, depth - 1) {
+            Outcome::Sat(model) => return Outcome::Sat(model),
+            Outcome::Unknown => unknown = true,
+            Outcome::Unsat => {}
+        }
+    }
+    if unknown {
+        Outcome::Unknown
+    } else {
+        Outcome::Unsat
+    }
+}
+
+enum Relaxation {
+    Infeasible,
+    Point(BTreeMap<String, Ratio>),
+    TooLarge,
+}
+
+// Solves the constraints `t <= 0` over the rationals by Fourier-Motzkin elimination, preferring
+// integer values for the variables.
+fn relaxation(mut constraints: Vec<Linear>) -> Relaxation {
+    // Each eliminated variable with the constraints bounding it at that point
+    let mut eliminated: Vec<(String, Vec<Linear>)> = Vec::new();
+    loop {
+        let mut kept = BTreeSet::new();
+        for c in constraints {
+            let c = c.tighten();
+            match c.as_constant() {
+                Some(n) if n > 0 => return Relaxation::Infeasible,
+                Some(_) => {}
+                None => {
+                    kept.insert(c);
+                }
+            }
+        }
+        if kept.len() > MAX_CONSTRAINTS {
+            return Relaxation::TooLarge;
+        }
+
+        // The variable producing the fewest new constraints
+        let vars: BTreeSet<&String> = kept.iter().flat_map(|c| c.coeffs.keys()).collect();
+        let cost = |x: &str| {
+            let lower = kept.iter().filter(|c| c.coeff(x) < 0).count();
+            let upper = kept.iter().filter(|c| c.coeff(x) > 0).count();
+            lower * upper
+        };
+        let Some(x) = vars.into_iter().min_by_key(|x| cost(x)).cloned() else {
+            break;
+        };
+
+        let (bounds, others): (Vec<Linear>, Vec<Linear>) = kept.into_iter().partition(|c| c.coeff(&x) != 0);
+        constraints = others;
+        for lower in bounds.iter().filter(|c| c.coeff(&x) < 0) {
+            for upper in bounds.iter().filter(|c| c.coeff(&x) > 0) {
+                constraints.push(lower.scale(upper.coeff(&x)).add(&upper.scale(-lower.coeff(&x))));
+            }
+        }
+        eliminated.push((x, bounds));
+    }
+
+    let mut model = BTreeMap::new();
+    for (x, bounds) in eliminated.iter().rev() {
+        let (mut lo, mut hi): (Option<Ratio>, Option<Ratio>) = (None, None);
+        for c in bounds {
+            // a * x + rest <= 0
+            let a = c.coeff(x);
+            let bound = c.without(x).eval(&model).mul(Ratio::int(-1)).div(Ratio::int(a));
+            if a > 0 {
+                hi = Some(hi.map_or(bound, |hi| hi.min(bound)));
+            } else {
+                lo = Some(lo.map_or(bound, |lo| lo.max(bound)));
+            }
+        }
+        let value = match (lo, hi) {
+            (Some(lo), Some(hi)) if lo.ceil() <= hi.floor() => Ratio::int(0.clamp(lo.ceil(), hi.floor())),
+            // No integer in between, the search branches on this value
+            (Some(lo), Some(_)) => lo,
+            (Some(lo), None) => Ratio::int(lo.ceil().max(0)),
+            (None, Some(hi)) => Ratio::int(hi.floor().min(0)),
+            (None, None) => Ratio::int(0),
+        };
+        model.insert(x.clone(), value);
+    }
+    Relaxation::Point(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn x() -> Linear {
+        Linear::var("x")
+    }
+
+    #[test]
+    fn integer_solutions() {
+        // 2x == 1 has a rational solution only
+        assert_eq!(solve(&Formula::Eq(x().scale(2).sub(&Linear::constant(1)))), Outcome::Unsat);
+
+        // 1 <= 3x - 3y <= 2 neither
+        let t = x().scale(3).sub(&Linear::var("y").scale(3));
+        let f = Formula::And(vec![
+            Formula::Le(Linear::constant(1).sub(&t)),
+            Formula::Le(t.sub(&Linear::constant(2))),
+        ]);
+        assert_eq!(solve(&f), Outcome::Unsat);
+
+        // 2 <= 2x <= 5 and x != 2, so x == 3 (branching as 5/2 is the rational bound)
+        let f = Formula::And(vec![
+            Formula::Le(Linear::constant(2).sub(&x().scale(2))),
+            Formula::Le(x().scale(2).sub(&Linear::constant(5))),
+            Formula::Not(Box::new(Formula::Eq(x().sub(&Linear::constant(1))))),
+        ]);
+        assert_eq!(solve(&f), Outcome::Sat(BTreeMap::from([("x".to_string(), 2)])));
+    }
+}